@@ -0,0 +1,87 @@
+// cargo bench --bench dom
+//
+// parses, lays out and serializes a large synthetic page and prints the time
+// per iteration of each step
+use project_agr::rendering::{HTMLParser, Layout, outer_html, query_selector_all};
+use project_agr::utils::{Dom, Node, NodeId};
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SECTIONS: usize = 2000;
+const ITERATIONS: u32 = 10;
+
+fn page(sections: usize) -> String {
+  let mut page = String::from("<!DOCTYPE html><html><head><title>bench</title></head><body>");
+  for i in 0..sections {
+    page.push_str(&format!(
+      "<div class=section id=s{i}><h2>Section {i}</h2><p>Lorem ipsum <b>dolor</b> sit amet, \
+       <i>consectetur</i> adipiscing elit, sed do <a href=#s{i}>eiusmod</a> tempor incididunt \
+       ut labore et dolore magna aliqua.</p><ul><li>one<li>two<li>three</ul><table><tr><td>a\
+       <td>b</table><pre>  code {i}\n  more</pre></div>"
+    ));
+  }
+  page.push_str("</body></html>");
+  page
+}
+
+// visits every node the way layout does, without the text shaping
+fn walk(dom: &Dom, node: NodeId) -> usize {
+  let own = match &dom[node] {
+    Node::Text(text) => text.text.len(),
+    _ => 0,
+  };
+  own
+    + dom
+      .children(node)
+      .map(|child| walk(dom, child))
+      .sum::<usize>()
+}
+
+fn report(name: &str, elapsed: Duration) {
+  println!(
+    "{name:<10} {:>10.2} ms/iter",
+    elapsed.as_secs_f64() * 1000.0 / ITERATIONS as f64
+  );
+}
+
+fn main() {
+  let html = page(SECTIONS);
+  println!("{SECTIONS} sections, {} bytes", html.len());
+
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    HTMLParser::new(html.clone()).parse();
+  }
+  report("parse", start.elapsed());
+
+  // the tree is built on another thread and handed back, like a page loaded
+  // off the ui thread would be
+  let tree = thread::spawn(move || HTMLParser::new(html).parse())
+    .join()
+    .unwrap();
+
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    walk(&tree, tree.document());
+  }
+  report("walk", start.elapsed());
+
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    Layout::new(&tree, 800.0);
+  }
+  report("layout", start.elapsed());
+
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    outer_html(&tree, tree.document());
+  }
+  report("serialize", start.elapsed());
+
+  let start = Instant::now();
+  for _ in 0..ITERATIONS {
+    query_selector_all(&tree, tree.document(), "div.section > ul li:nth-child(2)").unwrap();
+  }
+  report("query", start.elapsed());
+}
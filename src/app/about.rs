@@ -0,0 +1,369 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::app::Browser;
+use crate::net::activity::iso_8601;
+use crate::net::config;
+use crate::net::download::{self, DownloadState};
+use crate::net::url_handler;
+use crate::rendering::XMLError;
+
+struct AboutPage {
+  name: &'static str,
+  description: &'static str,
+  render: fn(&Browser) -> String,
+}
+
+const PAGES: &[AboutPage] = &[
+  AboutPage {
+    name: "blank",
+    description: "An empty page",
+    render: blank,
+  },
+  AboutPage {
+    name: "cache",
+    description: "Cached responses and when they expire",
+    render: cache,
+  },
+  AboutPage {
+    name: "config",
+    description: "Browser preferences",
+    render: config,
+  },
+  AboutPage {
+    name: "downloads",
+    description: "Files saved to disk",
+    render: downloads,
+  },
+  AboutPage {
+    name: "history",
+    description: "Pages visited in this session",
+    render: history,
+  },
+  AboutPage {
+    name: "version",
+    description: "Version and build information",
+    render: version,
+  },
+  AboutPage {
+    name: "about",
+    description: "This list",
+    render: index,
+  },
+];
+
+// `about:name` -> "name", also accepting a view-source: prefix. about: pages
+// only show state, anything after a `?` is ignored
+pub fn parse(url: &str) -> Option<&str> {
+  let (url, _) = url_handler::split_fragment(url);
+  let url = url.strip_prefix("view-source:").unwrap_or(url);
+  let rest = url.strip_prefix("about:")?;
+
+  Some(rest.split_once('?').map_or(rest, |(name, _)| name))
+}
+
+pub fn render(browser: &Browser, name: &str) -> String {
+  match PAGES.iter().find(|page| page.name == name) {
+    Some(page) => (page.render)(browser),
+    None => page(
+      "Page not found",
+      &format!(
+        "<p>There is no page called about:{}.</p>{}",
+        escape(name),
+        page_list()
+      ),
+    ),
+  }
+}
+
+pub fn offline_page(url: &str) -> String {
+  page(
+    "You are offline",
+    &format!(
+      "<p>{} has not been cached, so it cannot be shown while offline mode is on.</p><p><small>Press Go online and try again.</small></p>",
+      escape(url)
+    ),
+  )
+}
+
+// shown instead of a document that isn't well-formed XML, with the
+// offending line and a marker under the column
+pub fn xml_error_page(url: &str, error: &XMLError, source: &str) -> String {
+  let line = source
+    .lines()
+    .nth(error.position.line.saturating_sub(1))
+    .unwrap_or("");
+
+  page(
+    "XML Parsing Error",
+    &format!(
+      "<p>{}</p><p>Location: {}<br>Line Number {}, Column {}:</p><pre>{}\n{}^</pre>",
+      escape(&error.message),
+      escape(url),
+      error.position.line,
+      error.position.column,
+      escape(line),
+      "-".repeat(error.position.column.saturating_sub(1))
+    ),
+  )
+}
+
+fn page(title: &str, body: &str) -> String {
+  format!(
+    "<html><body><p><big><b>{}</b></big></p>{}</body></html>",
+    escape(title),
+    body
+  )
+}
+
+fn blank(_: &Browser) -> String {
+  String::new()
+}
+
+fn index(_: &Browser) -> String {
+  page("about: pages", &page_list())
+}
+
+fn page_list() -> String {
+  PAGES
+    .iter()
+    .map(|page| format!("<p><b>about:{}</b><br>{}</p>", page.name, page.description))
+    .collect()
+}
+
+fn cache(_: &Browser) -> String {
+  let mut body = String::new();
+
+  let entries = url_handler::cache_entries();
+  let now = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs();
+
+  if entries.is_empty() {
+    body.push_str("<p>The cache is empty.</p>");
+  }
+
+  for entry in &entries {
+    let expiry = match entry.expires {
+      None => String::from("never expires"),
+      Some(expires) if expires <= now => format!("expired {}", timestamp(expires)),
+      Some(expires) => format!("expires {}", timestamp(expires)),
+    };
+
+    body.push_str(&format!(
+      "<p><b>{}</b><br>{}, {}<br>stored {}, {}</p>",
+      escape(&entry.key),
+      escape(&entry.content_type),
+      download::format_bytes(entry.size as u64),
+      timestamp(entry.stored),
+      expiry,
+    ));
+  }
+
+  page("Cache", &body)
+}
+
+fn config(_: &Browser) -> String {
+  let mut body = String::new();
+
+  body.push_str("<pre>");
+  for (key, value) in config::get().entries() {
+    body.push_str(&format!("{} = {}\n", escape(&key), escape(&value)));
+  }
+  body.push_str("</pre>");
+
+  body.push_str(&format!(
+    "<p><small>Change a preference with the fields above the page. Preferences are saved to {}.</small></p>",
+    escape(&config::profile_dir().join("config").to_string_lossy())
+  ));
+  page("Configuration", &body)
+}
+
+fn downloads(_: &Browser) -> String {
+  let downloads = download::downloads();
+  let mut body = String::new();
+
+  if downloads.is_empty() {
+    body.push_str("<p>No downloads yet.</p>");
+  }
+
+  for download in downloads.iter().rev() {
+    let progress = match download.total {
+      Some(total) => format!(
+        "{} of {}",
+        download::format_bytes(download.received),
+        download::format_bytes(total)
+      ),
+      None => download::format_bytes(download.received),
+    };
+    let state = match &download.state {
+      DownloadState::InProgress => String::from("Downloading"),
+      DownloadState::Cancelled => String::from("Cancelled"),
+      DownloadState::Completed => String::from("Completed"),
+      DownloadState::Failed(reason) => format!("Failed: {}", escape(reason)),
+    };
+
+    body.push_str(&format!(
+      "<p><b>{}</b><br>{}<br>{} - {}<br><small>{}</small></p>",
+      escape(&download.file_name()),
+      escape(&download.url),
+      state,
+      progress,
+      escape(&download.path.to_string_lossy()),
+    ));
+  }
+
+  page("Downloads", &body)
+}
+
+fn history(browser: &Browser) -> String {
+  let mut body = String::new();
+
+  if browser.history.is_empty() {
+    body.push_str("<p>No pages visited yet.</p>");
+  }
+
+  for entry in browser.history.iter().rev() {
+    let title = entry.title.as_deref().unwrap_or(&entry.url);
+    body.push_str(&format!(
+      "<p><b>{}</b><br>{}<br><small>{}</small></p>",
+      escape(title),
+      escape(&entry.url),
+      iso_8601(entry.visited)
+    ));
+  }
+
+  page("History", &body)
+}
+
+fn version(_: &Browser) -> String {
+  let config = config::get();
+
+  page(
+    "project-agr",
+    &format!(
+      "<p>Version {}<br>User agent: {}<br>Profile: {}</p>",
+      env!("CARGO_PKG_VERSION"),
+      escape(&config.user_agent),
+      escape(&config::profile_dir().to_string_lossy())
+    ),
+  )
+}
+
+fn timestamp(seconds: u64) -> String {
+  iso_8601(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+fn escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::app::HistoryEntry;
+  use crate::rendering::HTMLParser;
+  use crate::utils::{Node, Position};
+
+  // what a page shows, once parsed
+  fn text(html: &str) -> String {
+    let dom = HTMLParser::new(html.to_string()).parse();
+    dom.text_content(dom.document())
+  }
+
+  #[test]
+  fn parse_about_urls() {
+    let cases = [
+      ("about:blank", Some("blank")),
+      ("about:cache?purge=1", Some("cache")),
+      ("about:history#today", Some("history")),
+      ("view-source:about:config", Some("config")),
+      ("about:", Some("")),
+      ("https://example.com/about:blank", None),
+      ("view-source:https://example.com", None),
+    ];
+    for (url, expected) in cases {
+      assert_eq!(parse(url), expected, "{url}");
+    }
+  }
+
+  #[test]
+  fn every_page_is_listed_and_unknown_ones_are_escaped() {
+    let (browser, _) = Browser::new();
+
+    assert_eq!(render(&browser, "blank"), "");
+    let index = text(&render(&browser, "about"));
+    for page in PAGES {
+      assert!(
+        index.contains(&format!("about:{}", page.name)),
+        "{}",
+        page.name
+      );
+    }
+
+    let missing = render(&browser, "<b>x</b>");
+    assert!(!missing.contains("<b>x</b>"));
+    assert!(text(&missing).contains("There is no page called about:<b>x</b>."));
+  }
+
+  #[test]
+  fn history_titles_and_urls_are_escaped() {
+    let (mut browser, _) = Browser::new();
+    assert!(text(&render(&browser, "history")).contains("No pages visited yet."));
+
+    browser.history.push(HistoryEntry {
+      url: String::from("https://a.com/?a=1&b=<2>"),
+      title: Some(String::from("<script>alert(1)</script> & co")),
+      visited: UNIX_EPOCH,
+    });
+    let page = render(&browser, "history");
+    assert!(!page.contains("<script>"));
+    let shown = text(&page);
+    assert!(shown.contains("<script>alert(1)</script> & co"));
+    assert!(shown.contains("https://a.com/?a=1&b=<2>"));
+  }
+
+  #[test]
+  fn offline_page_escapes_the_url() {
+    let page = offline_page("https://a.com/<i>");
+    assert!(!page.contains("<i>"));
+    assert!(text(&page).contains("https://a.com/<i> has not been cached"));
+  }
+
+  #[test]
+  fn xml_errors_are_escaped() {
+    let source = "<?xml version=\"1.0\"?>\n<a><b attr=\"&x;\"></a>\n";
+    let error = XMLError {
+      message: String::from("mismatched tag: expected </b>, found </a> & more"),
+      position: Position {
+        line: 2,
+        column: 19,
+      },
+    };
+    let page = xml_error_page("https://a.com/<feed>", &error, source);
+
+    // none of the markup from the message, the url or the source survives
+    // as tags
+    let dom = HTMLParser::new(page.clone()).parse();
+    let tags: Vec<&str> = dom
+      .elements(dom.document())
+      .filter_map(|node| match &dom[node] {
+        Node::Element(element) => Some(element.tag.as_str()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(
+      tags,
+      ["html", "body", "p", "big", "b", "p", "p", "br", "pre"]
+    );
+
+    let shown = text(&page);
+    assert!(shown.contains("mismatched tag: expected </b>, found </a> & more"));
+    assert!(shown.contains("Location: https://a.com/<feed>"));
+    assert!(shown.contains("Line Number 2, Column 19:"));
+    // the offending line with a marker under the column
+    assert!(shown.contains("<a><b attr=\"&x;\"></a>\n------------------^"));
+  }
+}
@@ -1,6 +1,37 @@
 #[derive(Debug, Clone)]
 pub enum Message {
   ScrollChanged(f32),
-  LoadUrl(),
+  LoadUrl(Navigation),
   WindowResized(f32, f32),
+  ToggleWrap,
+  ToggleLineNumbers,
+  PageLoadTick,
+  DownloadTick,
+  CancelDownload(usize),
+  ResumeDownload(usize),
+  AuthUsernameChanged(String),
+  AuthPasswordChanged(String),
+  SubmitAuth,
+  CancelAuth,
+  UrlInputChanged(String),
+  Navigate,
+  ToggleOffline,
+  PurgeCache,
+  PurgeCacheEntry(String),
+  ConfigKeyChanged(String),
+  ConfigValueChanged(String),
+  SetConfig,
+  SavePage,
+  FollowLink(String),
+}
+
+// what started a load, it decides whether the request carries a Referer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Navigation {
+  // an address typed into the bar or given on the command line
+  Typed,
+  // a link followed from the current document
+  Link,
+  // the same request again, as after signing in
+  Reload,
 }
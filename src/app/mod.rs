@@ -1,5 +1,7 @@
+mod about;
 mod message;
+mod page_load;
 mod state;
 
-pub use message::Message;
-pub use state::Browser;
+pub use message::{Message, Navigation};
+pub use state::{Browser, HistoryEntry};
@@ -0,0 +1,154 @@
+use crate::net::auth::AuthRequired;
+use crate::net::{Body, DownloadStarted, Offline, URLHandler};
+use crate::rendering::{HTMLParser, is_xml};
+
+use std::error::Error;
+use std::io;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// a partial page is laid out again at most this often, and never more often
+// than ten times the last layout took, so big pages stay linear overall
+const LAYOUT_INTERVAL: Duration = Duration::from_millis(100);
+
+enum Chunk {
+  Opened(Box<URLHandler>, Response),
+  Data(Vec<u8>),
+  Done(io::Result<()>),
+}
+
+pub enum Response {
+  // an html page, its body follows in chunks and is parsed as it arrives
+  Streaming,
+  // any other document, read in full
+  Complete(String),
+  Failed(Failure),
+}
+
+// errors can't cross threads, so the ones the browser reacts to are picked
+// out before they are sent
+pub enum Failure {
+  Download,
+  Offline,
+  Auth(AuthRequired),
+  Other(String),
+}
+
+pub enum Progress {
+  // the response headers aren't in yet, or nothing new arrived
+  Waiting,
+  // the response headers are in
+  Opened(Box<URLHandler>, Response),
+  // the tree grew and it's time to lay it out again
+  Received,
+  // the whole body is in, or reading it failed
+  Done(io::Result<()>),
+}
+
+// a page being loaded: a thread connects and reads the body off the
+// connection while the parser builds the tree out of what came in so far
+pub struct PageLoad {
+  pub parser: HTMLParser,
+  receiver: Receiver<Chunk>,
+  // the tree changed since it was last laid out
+  changed: bool,
+  laid_out: Instant,
+  layout_time: Duration,
+}
+
+impl PageLoad {
+  pub fn start(mut url_handler: URLHandler) -> Self {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+      let response = match url_handler.open() {
+        Ok(Body::Streaming(body)) if streams(&url_handler) => {
+          // once the receiver is gone the page was navigated away from
+          if sender
+            .send(Chunk::Opened(Box::new(url_handler), Response::Streaming))
+            .is_err()
+          {
+            return;
+          }
+          let result = body.read_with(|chunk| {
+            sender
+              .send(Chunk::Data(chunk.to_vec()))
+              .map_err(|_| io::Error::other("page load cancelled"))
+          });
+          let _ = sender.send(Chunk::Done(result.map(|_| ())));
+          return;
+        }
+        Ok(Body::Streaming(body)) => match body.read_with(|_| Ok(())) {
+          Ok(value) => Response::Complete(value),
+          Err(error) => Response::Failed(Failure::Other(error.to_string())),
+        },
+        Ok(Body::Complete(value)) => Response::Complete(value),
+        Err(error) => Response::Failed(failure(error)),
+      };
+      let _ = sender.send(Chunk::Opened(Box::new(url_handler), response));
+    });
+
+    PageLoad {
+      parser: HTMLParser::streaming(),
+      receiver,
+      changed: false,
+      laid_out: Instant::now(),
+      layout_time: Duration::ZERO,
+    }
+  }
+
+  // feeds the parser everything received since the last poll
+  pub fn poll(&mut self) -> Progress {
+    loop {
+      match self.receiver.try_recv() {
+        Ok(Chunk::Opened(url_handler, response)) => {
+          return Progress::Opened(url_handler, response);
+        }
+        Ok(Chunk::Data(bytes)) => {
+          self.parser.feed(&bytes);
+          self.changed = true;
+        }
+        Ok(Chunk::Done(result)) => return Progress::Done(result),
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => {
+          return Progress::Done(Err(io::Error::other("page load stopped")));
+        }
+      }
+    }
+
+    let interval = LAYOUT_INTERVAL.max(self.layout_time * 10);
+    if self.changed && self.laid_out.elapsed() >= interval {
+      Progress::Received
+    } else {
+      Progress::Waiting
+    }
+  }
+
+  // the partial tree was laid out in `time`
+  pub fn laid_out(&mut self, time: Duration) {
+    self.changed = false;
+    self.laid_out = Instant::now();
+    self.layout_time = time;
+  }
+}
+
+// html is laid out while it arrives, anything else is read in full
+fn streams(url_handler: &URLHandler) -> bool {
+  !url_handler.view_source
+    && url_handler.content_type != "text/plain"
+    && !is_xml(&url_handler.content_type)
+}
+
+fn failure(error: Box<dyn Error>) -> Failure {
+  if error.is::<DownloadStarted>() {
+    return Failure::Download;
+  }
+  if error.is::<Offline>() {
+    return Failure::Offline;
+  }
+  match error.downcast::<AuthRequired>() {
+    Ok(required) => Failure::Auth(*required),
+    Err(error) => Failure::Other(error.to_string()),
+  }
+}
@@ -1,24 +1,69 @@
-use iced::widget::{canvas, container};
-use iced::{Element, Subscription, Task, window};
+use iced::widget::{
+  Column, button, canvas, column, container, progress_bar, row, text, text_input,
+};
+use iced::{Element, Subscription, Task, time, window};
 
-use crate::app::Message;
-use crate::net::URLHandler;
-use crate::rendering::{DisplayList, HTMLParser, Layout, print_tree, syntax_highlight};
+use crate::app::page_load::{Failure, PageLoad, Progress, Response};
+use crate::app::{Message, Navigation, about};
+use crate::net::activity;
+use crate::net::auth;
+use crate::net::download::{self, DownloadState};
+use crate::net::headers::ReferrerPolicy;
+use crate::net::url_handler;
+use crate::net::{URLHandler, config, idna};
+use crate::rendering::{
+  DisplayList, HTMLParser, Layout, PlainTextLayout, TextOptions, XMLParser,
+  get_elements_by_tag_name, is_xml, outer_html, syntax_highlight,
+};
 use crate::ui::BrowserCanvas;
-use crate::utils::Node;
+use crate::utils::Dom;
 
-use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
-use std::rc::Rc;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+pub struct AuthPrompt {
+  pub origin: String,
+  pub realm: String,
+  pub username: String,
+  pub password: String,
+}
+
+pub struct HistoryEntry {
+  pub url: String,
+  pub title: Option<String>,
+  pub visited: SystemTime,
+}
 
 pub struct Browser {
   pub display_list: DisplayList,
   pub scroll_offset: f32,
   pub current_url: String,
+  pub page_url: Option<String>,
+  pub page_referrer_policy: Option<ReferrerPolicy>,
+  // the document that led to the current load and its policy, a reload
+  // sends the same Referer as the first request did
+  pub referrer: Option<(String, Option<ReferrerPolicy>)>,
   pub max_y: f32,
   pub width: f32,
-  pub tree: Option<Rc<RefCell<Node>>>,
+  pub tree: Option<Dom>,
+  pub plain_text: Option<PlainTextLayout>,
+  pub text_options: TextOptions,
+  pub auth_prompt: Option<AuthPrompt>,
   pub height: f32,
+  pub url_input: String,
+  pub history: Vec<HistoryEntry>,
+  pub offline_copy: bool,
+  pub anchors: HashMap<String, f32>,
+  // the tree generation the display list was laid out from
+  pub layout_generation: u64,
+  // the page being parsed while its body arrives
+  pub loading: Option<PageLoad>,
+  // the preference being edited on about:config
+  pub config_key: String,
+  pub config_value: String,
+  pub config_status: Option<String>,
 }
 
 impl Browser {
@@ -26,11 +71,8 @@ impl Browser {
     let mut url = String::from("about:blank");
     let args: Vec<String> = env::args().collect();
 
-    match args.get(1) {
-      Some(value) => {
-        url = value.to_string();
-      }
-      _ => (),
+    if let Some(value) = args.get(1) {
+      url = value.to_string();
     }
 
     (
@@ -38,93 +80,451 @@ impl Browser {
         display_list: DisplayList::new(),
         scroll_offset: 0.0,
         max_y: 0.0,
-        current_url: String::from(url),
+        url_input: idna::display_url(&url),
+        current_url: url,
+        page_url: None,
+        page_referrer_policy: None,
+        referrer: None,
         tree: None,
+        plain_text: None,
+        text_options: TextOptions::default(),
+        auth_prompt: None,
         width: 0.0,
         height: 0.0,
+        history: Vec::new(),
+        offline_copy: false,
+        anchors: HashMap::new(),
+        layout_generation: 0,
+        loading: None,
+        config_key: String::new(),
+        config_value: String::new(),
+        config_status: None,
       },
-      Task::done(Message::LoadUrl()),
+      Task::done(Message::LoadUrl(Navigation::Typed)),
     )
   }
 
   pub fn subscription(&self) -> Subscription<Message> {
-    window::resize_events().map(|(_id, size)| Message::WindowResized(size.width, size.height))
+    let resize =
+      window::resize_events().map(|(_id, size)| Message::WindowResized(size.width, size.height));
+
+    let mut subscriptions = vec![resize];
+    if download::has_active() {
+      subscriptions.push(time::every(Duration::from_millis(250)).map(|_| Message::DownloadTick));
+    }
+    if self.loading.is_some() {
+      subscriptions.push(time::every(Duration::from_millis(100)).map(|_| Message::PageLoadTick));
+    }
+    Subscription::batch(subscriptions)
   }
 
   pub fn update(&mut self, message: Message) -> Task<Message> {
+    let task = self.handle(message);
+
+    // the tree was edited through the Dom methods since it was laid out
+    if self.plain_text.is_none()
+      && let Some(tree) = &self.tree
+      && tree.generation() != self.layout_generation
+    {
+      self.layout();
+    }
+
+    task
+  }
+
+  fn handle(&mut self, message: Message) -> Task<Message> {
     match message {
       Message::ScrollChanged(offset) => {
         self.scroll_offset = offset;
+        // plain text only lays out the lines on screen
+        if let Some(plain_text) = &self.plain_text {
+          self.display_list = plain_text.display_list(offset, self.height);
+        }
         Task::none()
       }
-      Message::LoadUrl() => {
+      Message::LoadUrl(navigation) => {
+        self.loading = None;
+
+        match navigation {
+          // following a link sends the document it was on as the referrer
+          Navigation::Link => {
+            self.referrer = self
+              .page_url
+              .clone()
+              .map(|url| (url, self.page_referrer_policy))
+          }
+          Navigation::Typed => self.referrer = None,
+          Navigation::Reload => (),
+        }
+
+        if let Some(name) = about::parse(&self.current_url) {
+          let page = about::render(self, name);
+          self.show_internal_page(page);
+          return Task::none();
+        }
+
         let mut url_handler = URLHandler::default();
         url_handler.init(self.current_url.clone(), false);
 
-        let body_result = url_handler.request();
+        // kept through redirects, they are requested on behalf of the same
+        // document
+        if let Some((referrer, policy)) = &self.referrer {
+          url_handler.referrer = Some(referrer.clone());
+          url_handler.referrer_policy = *policy;
+        }
 
-        // self.view_source = url_handler.view_source;
+        // connecting can take a while, the current page stays up until the
+        // response headers are in
+        self.loading = Some(PageLoad::start(url_handler));
 
-        match body_result {
-          Ok(value) => {
-            let mut html_parser = HTMLParser::new(value);
-            self.tree = Some(html_parser.parse());
-          }
-          _ => (),
-        }
+        Task::none()
+      }
+      Message::WindowResized(width, height) => {
+        self.width = width;
+        self.height = height;
+
+        self.layout();
+
+        Task::none()
+      }
+      Message::ToggleWrap => {
+        self.text_options.wrap = !self.text_options.wrap;
+        self.layout();
+
+        Task::none()
+      }
+      Message::ToggleLineNumbers => {
+        self.text_options.line_numbers = !self.text_options.line_numbers;
+        self.layout();
 
-        match &self.tree {
-          Some(node) => {
-            if url_handler.view_source {
-              let highlighted = syntax_highlight(node);
+        Task::none()
+      }
+      Message::PageLoadTick => {
+        let Some(loading) = &mut self.loading else {
+          return Task::none();
+        };
 
-              let mut html_parser = HTMLParser::new(highlighted);
-              self.tree = Some(html_parser.parse());
+        match loading.poll() {
+          Progress::Waiting => (),
+          Progress::Opened(url_handler, Response::Streaming) => {
+            self.start_page(&url_handler);
+            self.layout();
+          }
+          Progress::Opened(url_handler, response) => {
+            self.loading = None;
+            self.show_response(&url_handler, response);
+          }
+          Progress::Received => {
+            let started = Instant::now();
+            self.layout();
+            if let Some(loading) = &mut self.loading {
+              loading.laid_out(started.elapsed());
             }
           }
-          _ => (),
-        }
+          Progress::Done(result) => {
+            if let Err(error) = result {
+              log::warn!(url = self.current_url.as_str(), error:% = error; "page load failed");
+            }
+            if let Some(mut loading) = self.loading.take() {
+              self.tree = Some(loading.parser.parse());
+            }
+
+            self.history.push(HistoryEntry {
+              url: idna::display_url(&self.current_url),
+              title: self.tree.as_ref().and_then(find_title),
+              visited: SystemTime::now(),
+            });
+            export_har();
 
-        match &self.tree {
-          Some(node) => {
-            // print_tree(node, 0);
-            let layout = Layout::new(node, self.width);
-            self.display_list = layout.display_list;
+            self.layout();
+            self.scroll_to_fragment();
           }
-          _ => (),
         }
 
-        self.max_y = self
-          .display_list
-          .items()
-          .iter()
-          .map(|item| item.y)
-          .fold(0.0, f32::max);
+        Task::none()
+      }
+      Message::DownloadTick => {
+        if self.current_url == "about:downloads" {
+          return Task::done(Message::LoadUrl(Navigation::Reload));
+        }
 
         Task::none()
       }
-      Message::WindowResized(width, height) => {
-        self.width = width;
-        self.height = height;
+      Message::CancelDownload(id) => {
+        download::cancel(id);
+        Task::none()
+      }
+      Message::ResumeDownload(id) => {
+        download::resume(id);
+        Task::none()
+      }
+      Message::AuthUsernameChanged(username) => {
+        if let Some(prompt) = &mut self.auth_prompt {
+          prompt.username = username;
+        }
+        Task::none()
+      }
+      Message::AuthPasswordChanged(password) => {
+        if let Some(prompt) = &mut self.auth_prompt {
+          prompt.password = password;
+        }
+        Task::none()
+      }
+      Message::SubmitAuth => match self.auth_prompt.take() {
+        Some(prompt) => {
+          auth::store(
+            &prompt.origin,
+            &prompt.realm,
+            prompt.username,
+            prompt.password,
+          );
+          Task::done(Message::LoadUrl(Navigation::Reload))
+        }
+        None => Task::none(),
+      },
+      Message::CancelAuth => {
+        self.auth_prompt = None;
+        Task::none()
+      }
+      Message::UrlInputChanged(url) => {
+        self.url_input = url;
+        Task::none()
+      }
+      Message::Navigate => {
+        let url = self.url_input.trim();
+        if url.is_empty() {
+          return Task::none();
+        }
+
+        self.navigate(url.to_string(), Navigation::Typed)
+      }
+      Message::FollowLink(href) => {
+        let base = self.page_url.as_deref().unwrap_or(&self.current_url);
+        let url = url_handler::resolve(base, &href);
+        self.navigate(url, Navigation::Link)
+      }
+      Message::ToggleOffline => {
+        let offline = !config::get().offline;
+        if let Err(error) = config::set("network.offline", &offline.to_string()) {
+          log::warn!(error = error.as_str(); "could not save offline mode");
+        }
+        Task::none()
+      }
+      Message::PurgeCache => {
+        url_handler::purge_cache(None);
+        Task::done(Message::LoadUrl(Navigation::Reload))
+      }
+      Message::PurgeCacheEntry(key) => {
+        url_handler::purge_cache(Some(&key));
+        Task::done(Message::LoadUrl(Navigation::Reload))
+      }
+      Message::ConfigKeyChanged(key) => {
+        self.config_key = key;
+        Task::none()
+      }
+      Message::ConfigValueChanged(value) => {
+        self.config_value = value;
+        Task::none()
+      }
+      Message::SetConfig => {
+        let key = self.config_key.trim();
+        if key.is_empty() {
+          return Task::none();
+        }
 
-        match &self.tree {
-          Some(node) => {
-            let layout = Layout::new(node, self.width);
-            self.display_list = layout.display_list;
+        self.config_status = Some(match config::set(key, self.config_value.trim()) {
+          Ok(()) => format!("Set {} to {}.", key, self.config_value.trim()),
+          Err(error) => error,
+        });
+        Task::done(Message::LoadUrl(Navigation::Reload))
+      }
+      Message::SavePage => {
+        if let Some(tree) = &self.tree {
+          match download::save_page(&self.current_url, &outer_html(tree, tree.document())) {
+            Ok(path) => log::info!(path:? = path; "saved page"),
+            Err(error) => log::warn!(error:% = error; "could not save page"),
           }
-          _ => (),
         }
+        Task::none()
+      }
+    }
+  }
 
-        self.max_y = self
-          .display_list
-          .items()
-          .iter()
-          .map(|item| item.y)
-          .fold(0.0, f32::max);
+  fn navigate(&mut self, url: String, navigation: Navigation) -> Task<Message> {
+    let (target, fragment) = url_handler::split_fragment(&url);
 
-        Task::none()
+    // only the fragment changed, stay on the document and scroll
+    if fragment.is_some()
+      && let Some(page_url) = &self.page_url
+      && url_handler::split_fragment(page_url).0 == target
+    {
+      self.current_url = url.clone();
+      self.page_url = Some(url.clone());
+      self.url_input = idna::display_url(&url);
+      self.scroll_to_fragment();
+      return Task::none();
+    }
+
+    self.current_url = url;
+    self.url_input = idna::display_url(&self.current_url);
+    self.scroll_offset = 0.0;
+    self.config_status = None;
+    Task::done(Message::LoadUrl(navigation))
+  }
+
+  // a document that was read in full, or the reason it couldn't be
+  fn show_response(&mut self, url_handler: &URLHandler, response: Response) {
+    export_har();
+
+    match response {
+      Response::Complete(value) => {
+        self.start_page(url_handler);
+
+        if url_handler.content_type == "text/plain" {
+          self.plain_text = Some(PlainTextLayout::new(value));
+        } else if is_xml(&url_handler.content_type) {
+          self.tree = match XMLParser::new(value.clone()).parse() {
+            Ok(tree) => Some(tree),
+            Err(error) => {
+              log::warn!(url = self.current_url.as_str(), error:% = error; "XML parsing failed");
+              let page = about::xml_error_page(&self.current_url, &error, &value);
+              Some(HTMLParser::new(page).parse())
+            }
+          };
+        } else {
+          let mut html_parser = HTMLParser::new(value);
+          self.tree = Some(html_parser.parse());
+        }
+
+        self.history.push(HistoryEntry {
+          url: idna::display_url(&self.current_url),
+          title: self.tree.as_ref().and_then(find_title),
+          visited: SystemTime::now(),
+        });
+      }
+      // html is streamed through the page load instead
+      Response::Streaming => return,
+      // the response is being saved to disk, keep showing the current page
+      Response::Failed(Failure::Download) => return,
+      Response::Failed(Failure::Offline) => {
+        let page = about::offline_page(&self.current_url);
+        self.show_internal_page(page);
+        return;
+      }
+      Response::Failed(Failure::Auth(required)) => {
+        self.auth_prompt = Some(AuthPrompt {
+          origin: required.origin,
+          realm: required.realm,
+          username: String::new(),
+          password: String::new(),
+        });
+      }
+      Response::Failed(Failure::Other(error)) => {
+        log::warn!(url = self.current_url.as_str(), error = error.as_str(); "page load failed");
+      }
+    }
+
+    if let Some(tree) = &self.tree
+      && url_handler.view_source
+    {
+      let highlighted = syntax_highlight(tree, tree.document());
+
+      let mut html_parser = HTMLParser::new(highlighted);
+      self.tree = Some(html_parser.parse());
+    }
+
+    self.layout();
+    self.scroll_to_fragment();
+  }
+
+  fn scroll_to_fragment(&mut self) {
+    let Some(fragment) = url_handler::split_fragment(&self.current_url).1 else {
+      return;
+    };
+
+    let decoded = url_handler::percent_decode(fragment);
+    let target = self
+      .anchors
+      .get(fragment)
+      .or_else(|| self.anchors.get(&decoded))
+      .copied();
+
+    match target {
+      Some(y) => self.scroll_offset = y.min(self.max_y).max(0.0),
+      // an empty fragment or #top goes to the start of the document
+      None if fragment.is_empty() || decoded.eq_ignore_ascii_case("top") => {
+        self.scroll_offset = 0.0
       }
+      None => (),
+    }
+  }
+
+  // the page `url_handler` fetched replaces the current one
+  fn start_page(&mut self, url_handler: &URLHandler) {
+    // a redirect may have moved the document and brought its own fragment
+    if let Some(url) = url_handler.final_url() {
+      self.current_url = url;
+      self.url_input = idna::display_url(&self.current_url);
     }
+
+    self.tree = None;
+    self.plain_text = None;
+    self.page_url = Some(self.current_url.clone());
+    self.page_referrer_policy = url_handler.page_referrer_policy;
+    self.offline_copy = url_handler.offline_copy;
+  }
+
+  fn show_internal_page(&mut self, page: String) {
+    let view_source = self.current_url.starts_with("view-source:");
+
+    self.plain_text = None;
+    self.page_url = Some(self.current_url.clone());
+    self.page_referrer_policy = None;
+    self.offline_copy = false;
+    // about:blank is truly empty, there is nothing to parse or lay out
+    self.tree = if page.is_empty() {
+      None
+    } else if view_source {
+      let tree = HTMLParser::new(page).parse();
+      Some(HTMLParser::new(syntax_highlight(&tree, tree.document())).parse())
+    } else {
+      Some(HTMLParser::new(page).parse())
+    };
+
+    self.layout();
+  }
+
+  fn layout(&mut self) {
+    self.anchors.clear();
+
+    // the rows of a text document are counted, not measured
+    if let Some(plain_text) = &mut self.plain_text {
+      plain_text.set_width(self.width, self.text_options);
+      self.display_list = plain_text.display_list(self.scroll_offset, self.height);
+      self.max_y = plain_text.max_y();
+      return;
+    }
+
+    if let Some(tree) = &self.tree {
+      let layout = Layout::new(tree, self.width);
+      self.layout_generation = layout.generation;
+      self.display_list = layout.display_list;
+      self.anchors = layout.anchors;
+    } else if let Some(loading) = &self.loading {
+      // the part of the page that's in so far
+      let layout = Layout::new(loading.parser.dom(), self.width);
+      self.display_list = layout.display_list;
+      self.anchors = layout.anchors;
+    } else {
+      self.display_list = DisplayList::new();
+    }
+
+    self.max_y = self
+      .display_list
+      .items()
+      .iter()
+      .map(|item| item.y)
+      .fold(0.0, f32::max);
   }
 
   pub fn view(&self) -> Element<'_, Message> {
@@ -135,9 +535,16 @@ impl Browser {
       height: self.height,
     };
 
-    let content = canvas(browser_canvas)
-      .width(iced::Length::Fill)
-      .height(iced::Length::Fill);
+    let content = column![
+      self.address_bar(),
+      self.offline_banner(),
+      self.auth_panel(),
+      self.about_panel(),
+      canvas(browser_canvas)
+        .width(iced::Length::Fill)
+        .height(iced::Length::Fill),
+      self.downloads_panel(),
+    ];
 
     container(content)
       .width(iced::Length::Fill)
@@ -146,7 +553,159 @@ impl Browser {
       .into()
   }
 
+  fn address_bar(&self) -> Element<'_, Message> {
+    let mut bar = row![
+      text_input("Enter a URL", &self.url_input)
+        .on_input(Message::UrlInputChanged)
+        .on_submit(Message::Navigate),
+      button("Go").on_press(Message::Navigate),
+      button(if config::get().offline {
+        "Go online"
+      } else {
+        "Work offline"
+      })
+      .on_press(Message::ToggleOffline),
+    ]
+    .spacing(10);
+
+    if self.tree.is_some() {
+      bar = bar.push(button("Save page").on_press(Message::SavePage));
+    }
+
+    bar.into()
+  }
+
+  fn offline_banner(&self) -> Column<'_, Message> {
+    if !self.offline_copy {
+      return Column::new();
+    }
+
+    column![text(
+      "Offline copy: this page was loaded from the cache and may be out of date."
+    )]
+    .padding(6)
+  }
+
+  fn auth_panel(&self) -> Column<'_, Message> {
+    let Some(prompt) = &self.auth_prompt else {
+      return Column::new();
+    };
+
+    column![
+      text(format!(
+        "{} is asking for a username and password for \"{}\"",
+        prompt.origin, prompt.realm
+      )),
+      text_input("Username", &prompt.username).on_input(Message::AuthUsernameChanged),
+      text_input("Password", &prompt.password)
+        .secure(true)
+        .on_input(Message::AuthPasswordChanged)
+        .on_submit(Message::SubmitAuth),
+      row![
+        button("Sign in").on_press(Message::SubmitAuth),
+        button("Cancel").on_press(Message::CancelAuth),
+      ]
+      .spacing(10),
+    ]
+    .spacing(6)
+    .padding(10)
+  }
+
+  // about: pages only show state, changing it goes through these controls
+  fn about_panel(&self) -> Column<'_, Message> {
+    match about::parse(&self.current_url) {
+      Some("cache") => {
+        let mut panel = column![button("Purge cache").on_press(Message::PurgeCache)].spacing(4);
+        for entry in url_handler::cache_entries() {
+          panel = panel.push(
+            row![
+              text(entry.key.clone()).width(iced::Length::Fill),
+              button("Purge").on_press(Message::PurgeCacheEntry(entry.key)),
+            ]
+            .spacing(10)
+            .align_y(iced::Alignment::Center),
+          );
+        }
+        panel.padding(6)
+      }
+      Some("config") => {
+        let mut panel = column![
+          row![
+            text_input("Preference", &self.config_key).on_input(Message::ConfigKeyChanged),
+            text_input("Value", &self.config_value)
+              .on_input(Message::ConfigValueChanged)
+              .on_submit(Message::SetConfig),
+            button("Set").on_press(Message::SetConfig),
+          ]
+          .spacing(10)
+        ]
+        .spacing(4);
+        if let Some(status) = &self.config_status {
+          panel = panel.push(text(status.clone()));
+        }
+        panel.padding(6)
+      }
+      _ => Column::new(),
+    }
+  }
+
+  fn downloads_panel(&self) -> Column<'_, Message> {
+    let mut panel = Column::new().spacing(4);
+
+    for download in download::downloads() {
+      let progress: Element<'_, Message> = match download.total {
+        Some(total) if total > 0 => progress_bar(0.0..=total as f32, download.received as f32)
+          .height(8)
+          .width(160)
+          .into(),
+        _ => text(download::format_bytes(download.received)).into(),
+      };
+
+      let action: Element<'_, Message> = match download.state {
+        DownloadState::InProgress => button("Cancel")
+          .on_press(Message::CancelDownload(download.id))
+          .into(),
+        DownloadState::Cancelled | DownloadState::Failed(_) => button("Resume")
+          .on_press(Message::ResumeDownload(download.id))
+          .into(),
+        DownloadState::Completed => text("Done").into(),
+      };
+
+      panel = panel.push(
+        row![
+          text(download.file_name()).width(iced::Length::Fill),
+          progress,
+          action
+        ]
+        .spacing(10)
+        .align_y(iced::Alignment::Center),
+      );
+    }
+
+    panel
+  }
+
   pub fn theme(&self) -> iced::Theme {
     iced::Theme::Light
   }
 }
+
+// html pages other than view-source ones are parsed as they arrive
+fn export_har() {
+  if let Ok(path) = env::var("AGR_HAR")
+    && let Err(error) = activity::export_har(Path::new(&path))
+  {
+    log::warn!(path = path.as_str(), error:% = error; "could not export HAR");
+  }
+}
+
+fn find_title(dom: &Dom) -> Option<String> {
+  get_elements_by_tag_name(dom, dom.document(), "title")
+    .into_iter()
+    .find_map(|title| {
+      let title = dom.text_content(title);
+      let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+
+      (!title.is_empty()).then_some(title)
+    })
+}
@@ -0,0 +1,5 @@
+pub mod app;
+pub mod net;
+pub mod rendering;
+pub mod ui;
+pub mod utils;
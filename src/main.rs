@@ -1,14 +1,71 @@
-mod app;
-mod net;
-mod rendering;
-mod ui;
-mod utils;
+use project_agr::app::Browser;
+use project_agr::rendering::{HTMLParser, outer_html};
+use project_agr::utils::logging;
 
-use app::Browser;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
 
-fn main() -> iced::Result {
-  iced::application("project-agr", Browser::update, Browser::view)
+fn main() -> ExitCode {
+  let args: Vec<String> = env::args().collect();
+  if args.get(1).map(String::as_str) == Some("--lint") {
+    return lint(&args[2..]);
+  }
+
+  logging::init();
+
+  let result = iced::application("project-agr", Browser::update, Browser::view)
     .subscription(Browser::subscription)
     .theme(Browser::theme)
-    .run_with(Browser::new)
+    .run_with(Browser::new);
+
+  match result {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(error) => {
+      eprintln!("{error}");
+      ExitCode::FAILURE
+    }
+  }
+}
+
+// `project-agr --lint page.html ...` prints parse errors as file:line:column
+// and fails if there were any, or if the serialized tree doesn't parse back
+// to itself
+fn lint(paths: &[String]) -> ExitCode {
+  let mut failed = false;
+
+  for path in paths {
+    let body = match fs::read(path) {
+      Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+      Err(error) => {
+        eprintln!("{path}: {error}");
+        failed = true;
+        continue;
+      }
+    };
+
+    let mut parser = HTMLParser::new(body);
+    let tree = parser.parse();
+
+    for error in parser.errors() {
+      println!(
+        "{}:{}:{}: {}",
+        path, error.position.line, error.position.column, error.code
+      );
+      failed = true;
+    }
+
+    let serialized = outer_html(&tree, tree.document());
+    let reparsed = HTMLParser::new(serialized.clone()).parse();
+    if outer_html(&reparsed, reparsed.document()) != serialized {
+      println!("{path}: serialized tree does not round-trip");
+      failed = true;
+    }
+  }
+
+  if failed {
+    ExitCode::FAILURE
+  } else {
+    ExitCode::SUCCESS
+  }
 }
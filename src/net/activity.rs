@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+
+const MAX_RECORDS: usize = 1000;
+
+lazy_static! {
+  static ref ACTIVITY: Mutex<VecDeque<RequestRecord>> = Mutex::new(VecDeque::new());
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CacheOutcome {
+  Hit,
+  Stored,
+  NotStored,
+}
+
+impl CacheOutcome {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      CacheOutcome::Hit => "hit",
+      CacheOutcome::Stored => "stored",
+      CacheOutcome::NotStored => "not-stored",
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Timings {
+  pub connect: Duration,
+  pub send: Duration,
+  pub wait: Duration,
+  pub receive: Duration,
+}
+
+impl Timings {
+  pub fn total(&self) -> Duration {
+    self.connect + self.send + self.wait + self.receive
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestRecord {
+  pub url: String,
+  pub method: &'static str,
+  pub status: u16,
+  pub started: SystemTime,
+  pub timings: Timings,
+  // the decoded body
+  pub body_size: u64,
+  // the body as it came over the wire, before content decoding; 0 when
+  // served from the cache
+  pub transfer_size: u64,
+  pub content_type: String,
+  pub cache: CacheOutcome,
+  pub request_headers: Vec<(String, String)>,
+  pub response_headers: Vec<(String, String)>,
+}
+
+pub fn record(record: RequestRecord) {
+  let mut activity = ACTIVITY.lock().unwrap();
+  if activity.len() == MAX_RECORDS {
+    activity.pop_front();
+  }
+  activity.push_back(record);
+}
+
+pub fn records() -> Vec<RequestRecord> {
+  ACTIVITY.lock().unwrap().iter().cloned().collect()
+}
+
+pub fn clear() {
+  ACTIVITY.lock().unwrap().clear();
+}
+
+pub fn export_har(path: &Path) -> std::io::Result<()> {
+  fs::write(path, to_har())
+}
+
+// HTTP Archive 1.2, see http://www.softwareishard.com/blog/har-12-spec/
+pub fn to_har() -> String {
+  let entries: Vec<String> = records().iter().map(har_entry).collect();
+
+  format!(
+    "{{\"log\":{{\"version\":\"1.2\",\"creator\":{{\"name\":\"project-agr\",\"version\":\"{}\"}},\"entries\":[{}]}}}}",
+    env!("CARGO_PKG_VERSION"),
+    entries.join(",")
+  )
+}
+
+fn har_entry(record: &RequestRecord) -> String {
+  let query = record
+    .url
+    .split_once('?')
+    .map(|(_, query)| query)
+    .unwrap_or("");
+  let query_string: Vec<String> = query
+    .split('&')
+    .filter(|pair| !pair.is_empty())
+    .map(|pair| {
+      let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+      format!(
+        "{{\"name\":{},\"value\":{}}}",
+        json_string(name),
+        json_string(value)
+      )
+    })
+    .collect();
+
+  let location = record
+    .response_headers
+    .iter()
+    .find(|(name, _)| name.eq_ignore_ascii_case("location"))
+    .map(|(_, value)| value.as_str())
+    .unwrap_or("");
+
+  format!(
+    concat!(
+      "{{\"startedDateTime\":{},\"time\":{},",
+      "\"request\":{{\"method\":\"{}\",\"url\":{},\"httpVersion\":\"HTTP/1.1\",\"cookies\":[],",
+      "\"headers\":{},\"queryString\":[{}],\"headersSize\":-1,\"bodySize\":0}},",
+      "\"response\":{{\"status\":{},\"statusText\":\"\",\"httpVersion\":\"HTTP/1.1\",\"cookies\":[],",
+      "\"headers\":{},\"content\":{{\"size\":{},\"mimeType\":{}}},\"redirectURL\":{},",
+      "\"headersSize\":-1,\"bodySize\":{}}},",
+      "\"cache\":{{}},",
+      "\"timings\":{{\"blocked\":-1,\"dns\":-1,\"ssl\":-1,\"connect\":{},\"send\":{},\"wait\":{},\"receive\":{}}},",
+      "\"comment\":\"cache {}\"}}"
+    ),
+    json_string(&iso_8601(record.started)),
+    millis(record.timings.total()),
+    record.method,
+    json_string(&record.url),
+    har_headers(&record.request_headers),
+    query_string.join(","),
+    record.status,
+    har_headers(&record.response_headers),
+    record.body_size,
+    json_string(&record.content_type),
+    json_string(location),
+    record.transfer_size,
+    millis(record.timings.connect),
+    millis(record.timings.send),
+    millis(record.timings.wait),
+    millis(record.timings.receive),
+    record.cache.as_str(),
+  )
+}
+
+fn har_headers(headers: &[(String, String)]) -> String {
+  let headers: Vec<String> = headers
+    .iter()
+    .map(|(name, value)| {
+      format!(
+        "{{\"name\":{},\"value\":{}}}",
+        json_string(name),
+        json_string(value)
+      )
+    })
+    .collect();
+  format!("[{}]", headers.join(","))
+}
+
+fn millis(duration: Duration) -> String {
+  format!("{:.3}", duration.as_secs_f64() * 1000.0)
+}
+
+fn json_string(s: &str) -> String {
+  let mut escaped = String::with_capacity(s.len() + 2);
+  escaped.push('"');
+
+  for c in s.chars() {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+      c => escaped.push(c),
+    }
+  }
+
+  escaped.push('"');
+  escaped
+}
+
+pub(crate) fn iso_8601(time: SystemTime) -> String {
+  let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let seconds = since_epoch.as_secs();
+  let (hour, minute, second) = (seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+
+  // days since 1970-01-01 to a civil date (Howard Hinnant's algorithm)
+  let days = (seconds / 86400) as i64 + 719468;
+  let era = days.div_euclid(146097);
+  let day_of_era = days.rem_euclid(146097);
+  let year_of_era =
+    (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+  format!(
+    "{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}.{:03}Z",
+    since_epoch.subsec_millis()
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn har_separates_content_and_transfer_size() {
+    let record = RequestRecord {
+      url: String::from("http://example.com/page?a=1"),
+      method: "GET",
+      status: 200,
+      started: UNIX_EPOCH,
+      timings: Timings::default(),
+      body_size: 5000,
+      transfer_size: 1200,
+      content_type: String::from("text/html"),
+      cache: CacheOutcome::Stored,
+      request_headers: Vec::new(),
+      response_headers: vec![(String::from("Content-Encoding"), String::from("gzip"))],
+    };
+
+    let entry = har_entry(&record);
+    assert!(entry.contains("\"content\":{\"size\":5000,\"mimeType\":\"text/html\"}"));
+    assert!(entry.contains("\"headersSize\":-1,\"bodySize\":1200}"));
+    assert!(entry.contains("\"queryString\":[{\"name\":\"a\",\"value\":\"1\"}]"));
+  }
+}
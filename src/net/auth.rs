@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+
+use crate::net::hash::{base64_encode, md5_hex, sha256_hex};
+
+lazy_static! {
+  static ref CREDENTIALS: Mutex<HashMap<(String, String), Credentials>> =
+    Mutex::new(HashMap::new());
+  static ref SPACES: Mutex<Vec<ProtectionSpace>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthScheme {
+  Basic,
+  Digest,
+}
+
+#[derive(Debug, Clone)]
+pub struct Challenge {
+  pub scheme: AuthScheme,
+  pub realm: String,
+  nonce: String,
+  opaque: Option<String>,
+  qop: Vec<String>,
+  algorithm: String,
+}
+
+#[derive(Clone)]
+pub struct Credentials {
+  pub username: String,
+  pub password: String,
+}
+
+// a protection space is the part of an origin guarded by one realm; requests
+// below `path` are answered pre-emptively once the user has signed in
+struct ProtectionSpace {
+  origin: String,
+  path: String,
+  challenge: Challenge,
+  nonce_count: u32,
+}
+
+#[derive(Debug)]
+pub struct AuthRequired {
+  pub origin: String,
+  pub realm: String,
+}
+
+impl fmt::Display for AuthRequired {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} requires credentials for \"{}\"",
+      self.origin, self.realm
+    )
+  }
+}
+
+impl std::error::Error for AuthRequired {}
+
+pub fn store(origin: &str, realm: &str, username: String, password: String) {
+  CREDENTIALS.lock().unwrap().insert(
+    (origin.to_string(), realm.to_string()),
+    Credentials { username, password },
+  );
+}
+
+pub(crate) fn forget(origin: &str, realm: &str) {
+  CREDENTIALS
+    .lock()
+    .unwrap()
+    .remove(&(origin.to_string(), realm.to_string()));
+  SPACES
+    .lock()
+    .unwrap()
+    .retain(|s| !(s.origin == origin && s.challenge.realm == realm));
+}
+
+pub(crate) fn credentials(origin: &str, realm: &str) -> Option<Credentials> {
+  CREDENTIALS
+    .lock()
+    .unwrap()
+    .get(&(origin.to_string(), realm.to_string()))
+    .cloned()
+}
+
+pub(crate) fn remember(origin: &str, path: &str, challenge: Challenge) {
+  let directory = match path.rsplit_once('/') {
+    Some((directory, _)) => format!("{directory}/"),
+    None => String::from("/"),
+  };
+
+  let mut spaces = SPACES.lock().unwrap();
+  spaces.retain(|s| !(s.origin == origin && s.challenge.realm == challenge.realm));
+  spaces.push(ProtectionSpace {
+    origin: origin.to_string(),
+    path: directory,
+    challenge,
+    nonce_count: 0,
+  });
+}
+
+pub(crate) fn authorization(origin: &str, method: &str, uri: &str) -> Option<String> {
+  let mut spaces = SPACES.lock().unwrap();
+  // a space is remembered before the user has answered its prompt, so skip
+  // the ones there are no credentials for yet
+  let (space, credentials) = spaces
+    .iter_mut()
+    .filter(|s| s.origin == origin && uri.starts_with(&s.path))
+    .filter_map(|s| credentials(origin, &s.challenge.realm).map(|c| (s, c)))
+    .max_by_key(|(s, _)| s.path.len())?;
+
+  match space.challenge.scheme {
+    AuthScheme::Basic => {
+      let token = format!("{}:{}", credentials.username, credentials.password);
+      Some(format!("Basic {}", base64_encode(token.as_bytes())))
+    }
+    AuthScheme::Digest => {
+      space.nonce_count += 1;
+      let cnonce = cnonce(&space.challenge, space.nonce_count);
+      Some(digest_response(
+        &space.challenge,
+        &credentials,
+        method,
+        uri,
+        space.nonce_count,
+        &cnonce,
+      ))
+    }
+  }
+}
+
+// picks the strongest challenge we can answer out of a WWW-Authenticate value
+pub(crate) fn parse_challenge(header: &str) -> Option<Challenge> {
+  let mut challenges = Vec::new();
+  let mut current: Option<(String, HashMap<String, String>)> = None;
+
+  for token in split_params(header) {
+    let token = token.trim();
+    if token.is_empty() {
+      continue;
+    }
+
+    // a new challenge starts with a bare scheme name, optionally followed by
+    // its first parameter: `Digest realm="x"`
+    let (first, rest) = match token.split_once(char::is_whitespace) {
+      Some((first, rest)) if !first.contains('=') => (Some(first), rest.trim()),
+      _ if !token.contains('=') => (Some(token), ""),
+      _ => (None, token),
+    };
+
+    if let Some(scheme) = first {
+      if let Some(challenge) = current.take() {
+        challenges.push(challenge);
+      }
+      current = Some((scheme.to_lowercase(), HashMap::new()));
+    }
+
+    if let Some((key, value)) = rest.split_once('=')
+      && let Some((_, params)) = current.as_mut()
+    {
+      params.insert(key.trim().to_lowercase(), unquote(value.trim()));
+    }
+  }
+
+  if let Some(challenge) = current.take() {
+    challenges.push(challenge);
+  }
+
+  // a Digest challenge that only offers qop="auth-int" would need a hash of
+  // the request body; answering it without qop would just fail again
+  let (scheme, params) = challenges
+    .iter()
+    .find(|(scheme, params)| {
+      scheme == "digest"
+        && ["md5", "md5-sess", "sha-256", "sha-256-sess"].contains(
+          &params
+            .get("algorithm")
+            .map(|a| a.to_lowercase())
+            .unwrap_or_else(|| String::from("md5"))
+            .as_str(),
+        )
+        && params.get("qop").is_none_or(|qop| {
+          qop
+            .split(',')
+            .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+        })
+    })
+    .or_else(|| challenges.iter().find(|(scheme, _)| scheme == "basic"))?;
+
+  Some(Challenge {
+    scheme: if scheme == "digest" {
+      AuthScheme::Digest
+    } else {
+      AuthScheme::Basic
+    },
+    realm: params.get("realm").cloned().unwrap_or_default(),
+    nonce: params.get("nonce").cloned().unwrap_or_default(),
+    opaque: params.get("opaque").cloned(),
+    qop: params
+      .get("qop")
+      .map(|q| q.split(',').map(|v| v.trim().to_lowercase()).collect())
+      .unwrap_or_default(),
+    algorithm: params
+      .get("algorithm")
+      .cloned()
+      .unwrap_or_else(|| String::from("MD5")),
+  })
+}
+
+fn split_params(header: &str) -> Vec<String> {
+  let mut parts = Vec::new();
+  let mut current = String::new();
+  let mut quoted = false;
+  let mut escaped = false;
+
+  for c in header.chars() {
+    match c {
+      _ if escaped => {
+        escaped = false;
+        current.push(c);
+      }
+      '\\' if quoted => {
+        escaped = true;
+        current.push(c);
+      }
+      '"' => {
+        quoted = !quoted;
+        current.push(c);
+      }
+      ',' if !quoted => parts.push(std::mem::take(&mut current)),
+      _ => current.push(c),
+    }
+  }
+  parts.push(current);
+
+  parts
+}
+
+// a client nonce that differs for every request
+fn cnonce(challenge: &Challenge, nonce_count: u32) -> String {
+  let seed = format!(
+    "{}:{}:{}",
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap()
+      .as_nanos(),
+    challenge.nonce,
+    nonce_count
+  );
+  md5_hex(seed.as_bytes())[..16].to_string()
+}
+
+fn digest_response(
+  challenge: &Challenge,
+  credentials: &Credentials,
+  method: &str,
+  uri: &str,
+  nonce_count: u32,
+  cnonce: &str,
+) -> String {
+  let algorithm = challenge.algorithm.to_lowercase();
+  let hash = |input: String| {
+    if algorithm.starts_with("sha-256") {
+      sha256_hex(input.as_bytes())
+    } else {
+      md5_hex(input.as_bytes())
+    }
+  };
+
+  let nc = format!("{nonce_count:08x}");
+
+  let mut ha1 = hash(format!(
+    "{}:{}:{}",
+    credentials.username, challenge.realm, credentials.password
+  ));
+  if algorithm.ends_with("-sess") {
+    ha1 = hash(format!("{}:{}:{}", ha1, challenge.nonce, cnonce));
+  }
+  let ha2 = hash(format!("{method}:{uri}"));
+
+  let qop = challenge.qop.iter().any(|q| q == "auth");
+  let response = if qop {
+    hash(format!(
+      "{}:{}:{}:{}:auth:{}",
+      ha1, challenge.nonce, nc, cnonce, ha2
+    ))
+  } else {
+    hash(format!("{}:{}:{}", ha1, challenge.nonce, ha2))
+  };
+
+  let mut header = format!(
+    "Digest username={}, realm={}, nonce={}, uri={}, algorithm={}, response=\"{}\"",
+    quote(&credentials.username),
+    quote(&challenge.realm),
+    quote(&challenge.nonce),
+    quote(uri),
+    challenge.algorithm,
+    response
+  );
+  if qop {
+    header.push_str(&format!(", qop=auth, nc={nc}, cnonce={}", quote(cnonce)));
+  }
+  if let Some(opaque) = &challenge.opaque {
+    header.push_str(&format!(", opaque={}", quote(opaque)));
+  }
+
+  header
+}
+
+// a quoted-string for a header parameter, with `"` and `\` escaped
+fn quote(value: &str) -> String {
+  let mut quoted = String::with_capacity(value.len() + 2);
+  quoted.push('"');
+  for c in value.chars() {
+    if c == '"' || c == '\\' {
+      quoted.push('\\');
+    }
+    quoted.push(c);
+  }
+  quoted.push('"');
+  quoted
+}
+
+// the value of a quoted-string, or the token itself when it isn't quoted
+fn unquote(value: &str) -> String {
+  let Some(inner) = value
+    .strip_prefix('"')
+    .map(|v| v.strip_suffix('"').unwrap_or(v))
+  else {
+    return value.to_string();
+  };
+
+  let mut unquoted = String::with_capacity(inner.len());
+  let mut chars = inner.chars();
+  while let Some(c) = chars.next() {
+    match c {
+      '\\' => unquoted.extend(chars.next()),
+      _ => unquoted.push(c),
+    }
+  }
+  unquoted
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mufasa(password: &str) -> Credentials {
+    Credentials {
+      username: String::from("Mufasa"),
+      password: password.to_string(),
+    }
+  }
+
+  // the value of one parameter in an Authorization header
+  fn param(header: &str, name: &str) -> String {
+    split_params(header.strip_prefix("Digest ").unwrap())
+      .iter()
+      .find_map(|p| {
+        let (key, value) = p.trim().split_once('=')?;
+        (key == name).then(|| unquote(value))
+      })
+      .unwrap()
+  }
+
+  #[test]
+  fn parse_basic_and_digest_challenges() {
+    let challenge = parse_challenge("Basic realm=\"WallyWorld\"").unwrap();
+    assert_eq!(challenge.scheme, AuthScheme::Basic);
+    assert_eq!(challenge.realm, "WallyWorld");
+
+    // the digest challenge wins over basic, commas inside quotes don't split
+    let challenge = parse_challenge(
+      "Basic realm=\"b\", Digest realm=\"a, \\\"b\\\"\", qop=\"auth,auth-int\", \
+       nonce=\"n\", opaque=\"o\", algorithm=SHA-256",
+    )
+    .unwrap();
+    assert_eq!(challenge.scheme, AuthScheme::Digest);
+    assert_eq!(challenge.realm, "a, \"b\"");
+    assert_eq!(challenge.nonce, "n");
+    assert_eq!(challenge.opaque.as_deref(), Some("o"));
+    assert_eq!(challenge.qop, ["auth", "auth-int"]);
+    assert_eq!(challenge.algorithm, "SHA-256");
+
+    assert!(parse_challenge("Negotiate").is_none());
+    assert!(parse_challenge("Digest realm=\"r\", nonce=\"n\", algorithm=SHA-512").is_none());
+  }
+
+  #[test]
+  fn auth_int_only_challenges_are_not_answered() {
+    assert!(parse_challenge("Digest realm=\"r\", nonce=\"n\", qop=\"auth-int\"").is_none());
+
+    let challenge =
+      parse_challenge("Digest realm=\"r\", nonce=\"n\", qop=\"auth-int\", Basic realm=\"r\"")
+        .unwrap();
+    assert_eq!(challenge.scheme, AuthScheme::Basic);
+  }
+
+  // RFC 2617, section 3.5
+  #[test]
+  fn rfc_2617_example() {
+    let challenge = parse_challenge(
+      "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+       nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+       opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+    )
+    .unwrap();
+    let header = digest_response(
+      &challenge,
+      &mufasa("Circle Of Life"),
+      "GET",
+      "/dir/index.html",
+      1,
+      "0a4f113b",
+    );
+    assert_eq!(
+      param(&header, "response"),
+      "6629fae49393a05397450978507c4ef1"
+    );
+    assert_eq!(param(&header, "qop"), "auth");
+    assert_eq!(param(&header, "nc"), "00000001");
+    assert_eq!(param(&header, "cnonce"), "0a4f113b");
+    assert_eq!(param(&header, "opaque"), "5ccc069c403ebaf9f0171e9517f40e41");
+  }
+
+  // RFC 7616, section 3.9.1
+  #[test]
+  fn rfc_7616_examples() {
+    let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+    let challenge = |algorithm: &str| {
+      parse_challenge(&format!(
+        "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+         algorithm={algorithm}, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+         opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\""
+      ))
+      .unwrap()
+    };
+    let response = |algorithm: &str| {
+      let header = digest_response(
+        &challenge(algorithm),
+        &mufasa("Circle of Life"),
+        "GET",
+        "/dir/index.html",
+        1,
+        cnonce,
+      );
+      param(&header, "response")
+    };
+
+    assert_eq!(response("MD5"), "8ca523f5e9506fed4657c9700eebdbec");
+    assert_eq!(
+      response("SHA-256"),
+      "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+    );
+  }
+
+  // RFC 2069 style, for servers that send no qop
+  #[test]
+  fn digest_without_qop() {
+    let challenge = parse_challenge("Digest realm=\"r\", nonce=\"n\"").unwrap();
+    let header = digest_response(&challenge, &mufasa("p"), "GET", "/", 1, "c");
+    let expected =
+      md5_hex(format!("{}:n:{}", md5_hex(b"Mufasa:r:p"), md5_hex(b"GET:/")).as_bytes());
+    assert_eq!(param(&header, "response"), expected);
+    assert!(!header.contains("qop="));
+  }
+}
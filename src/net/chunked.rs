@@ -0,0 +1,72 @@
+use std::io::{self, BufRead, Read};
+
+pub struct ChunkedReader<R: BufRead> {
+  inner: R,
+  remaining: usize,
+  done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+  pub fn new(inner: R) -> Self {
+    ChunkedReader {
+      inner,
+      remaining: 0,
+      done: false,
+    }
+  }
+
+  fn next_chunk(&mut self) -> io::Result<()> {
+    let mut line = String::new();
+    self.inner.read_line(&mut line)?;
+
+    // chunk extensions (";name=value") are allowed after the size and ignored
+    let size = line.split(';').next().unwrap_or("").trim();
+    self.remaining = usize::from_str_radix(size, 16)
+      .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk size hex"))?;
+
+    if self.remaining == 0 {
+      loop {
+        let mut trailer_line = String::new();
+        self.inner.read_line(&mut trailer_line)?;
+        if trailer_line == "\r\n" || trailer_line.is_empty() {
+          break;
+        }
+      }
+      self.done = true;
+    }
+
+    Ok(())
+  }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    if self.done || buf.is_empty() {
+      return Ok(0);
+    }
+
+    if self.remaining == 0 {
+      self.next_chunk()?;
+      if self.done {
+        return Ok(0);
+      }
+    }
+
+    let limit = buf.len().min(self.remaining);
+    let read = self.inner.read(&mut buf[..limit])?;
+    if read == 0 {
+      return Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Connection closed inside a chunk",
+      ));
+    }
+
+    self.remaining -= read;
+    if self.remaining == 0 {
+      let mut footer = String::new();
+      self.inner.read_line(&mut footer)?;
+    }
+
+    Ok(read)
+  }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+use lazy_static::lazy_static;
+
+use crate::net::headers::ReferrerPolicy;
+
+const CONFIG_FILE: &str = "config";
+
+lazy_static! {
+  static ref CONFIG: RwLock<Config> = RwLock::new(Config::load());
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+  pub user_agent: String,
+  pub accept_language: Option<String>,
+  pub referrer_policy: ReferrerPolicy,
+  pub https_first: bool,
+  pub offline: bool,
+  // host -> [(header, value)], applied last so they win over everything else
+  pub host_headers: BTreeMap<String, Vec<(String, String)>>,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      user_agent: String::from("Project P"),
+      accept_language: None,
+      referrer_policy: ReferrerPolicy::StrictOriginWhenCrossOrigin,
+      https_first: false,
+      offline: false,
+      host_headers: BTreeMap::new(),
+    }
+  }
+}
+
+impl Config {
+  fn load() -> Self {
+    let mut config = Config::default();
+
+    let Ok(contents) = fs::read_to_string(profile_dir().join(CONFIG_FILE)) else {
+      return config;
+    };
+
+    for line in contents.lines() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      if let Some((key, value)) = line.split_once('=')
+        && let Err(error) = config.apply(key.trim(), value.trim())
+      {
+        log::warn!(line = line, error = error.as_str(); "ignoring config line");
+      }
+    }
+
+    config
+  }
+
+  fn apply(&mut self, key: &str, value: &str) -> Result<(), String> {
+    match key {
+      "network.user_agent" => self.user_agent = value.to_string(),
+      "network.accept_language" => {
+        self.accept_language = if value.is_empty() {
+          None
+        } else {
+          Some(value.to_string())
+        }
+      }
+      "network.referrer_policy" => {
+        self.referrer_policy =
+          ReferrerPolicy::parse(value).ok_or_else(|| format!("Unknown referrer policy: {value}"))?
+      }
+      "network.https_first" => self.https_first = parse_bool(value)?,
+      "network.offline" => self.offline = parse_bool(value)?,
+      _ => {
+        // host.<hostname>.<Header-Name>; the host may itself contain dots, so
+        // the header name is everything after the last one
+        let Some((host, header)) = key
+          .strip_prefix("host.")
+          .and_then(|rest| rest.rsplit_once('.'))
+        else {
+          return Err(format!("Unknown config key: {key}"));
+        };
+
+        let headers = self.host_headers.entry(host.to_lowercase()).or_default();
+        headers.retain(|(name, _)| !name.eq_ignore_ascii_case(header));
+        if !value.is_empty() {
+          headers.push((header.to_string(), value.to_string()));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn entries(&self) -> Vec<(String, String)> {
+    let mut entries = vec![
+      (String::from("network.user_agent"), self.user_agent.clone()),
+      (
+        String::from("network.accept_language"),
+        self.accept_language.clone().unwrap_or_default(),
+      ),
+      (
+        String::from("network.referrer_policy"),
+        self.referrer_policy.as_str().to_string(),
+      ),
+      (
+        String::from("network.https_first"),
+        self.https_first.to_string(),
+      ),
+      (String::from("network.offline"), self.offline.to_string()),
+    ];
+
+    for (host, headers) in &self.host_headers {
+      for (header, value) in headers {
+        entries.push((format!("host.{host}.{header}"), value.clone()));
+      }
+    }
+
+    entries
+  }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+  match value {
+    "true" | "1" | "on" => Ok(true),
+    "false" | "0" | "off" => Ok(false),
+    _ => Err(format!("Expected true or false, got: {value}")),
+  }
+}
+
+pub fn get() -> Config {
+  CONFIG.read().unwrap().clone()
+}
+
+pub fn set(key: &str, value: &str) -> Result<(), String> {
+  let mut config = CONFIG.write().unwrap();
+  config.apply(key, value)?;
+  save(&config).map_err(|error| error.to_string())
+}
+
+fn save(config: &Config) -> std::io::Result<()> {
+  let dir = profile_dir();
+  fs::create_dir_all(&dir)?;
+
+  let contents: String = config
+    .entries()
+    .iter()
+    .map(|(key, value)| format!("{key} = {value}\n"))
+    .collect();
+
+  fs::write(dir.join(CONFIG_FILE), contents)
+}
+
+pub fn profile_dir() -> PathBuf {
+  if let Ok(dir) = env::var("XDG_CONFIG_HOME") {
+    return PathBuf::from(dir).join("project-agr");
+  }
+
+  if let Ok(dir) = env::var("APPDATA") {
+    return PathBuf::from(dir).join("project-agr");
+  }
+
+  match env::var("HOME") {
+    Ok(home) => PathBuf::from(home).join(".config").join("project-agr"),
+    Err(_) => PathBuf::from(".project-agr"),
+  }
+}
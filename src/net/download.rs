@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use flate2::read::GzDecoder;
+
+use lazy_static::lazy_static;
+
+use crate::net::URLHandler;
+use crate::net::url_handler::{Response, percent_decode};
+
+lazy_static! {
+  static ref DOWNLOADS: Mutex<Vec<Download>> = Mutex::new(Vec::new());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DownloadState {
+  InProgress,
+  Cancelled,
+  Completed,
+  Failed(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Download {
+  pub id: usize,
+  pub url: String,
+  pub path: PathBuf,
+  pub received: u64,
+  pub total: Option<u64>,
+  pub state: DownloadState,
+  validator: Option<String>,
+  resumable: bool,
+  // bumped by cancel and resume; a worker only writes while the download
+  // is still on the attempt it was started for
+  attempt: u64,
+}
+
+impl Download {
+  pub fn file_name(&self) -> String {
+    self
+      .path
+      .file_name()
+      .map(|name| name.to_string_lossy().to_string())
+      .unwrap_or_default()
+  }
+
+  fn part_path(&self) -> PathBuf {
+    let mut name = self.path.clone().into_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+  }
+}
+
+pub fn downloads() -> Vec<Download> {
+  DOWNLOADS.lock().unwrap().clone()
+}
+
+pub fn has_active() -> bool {
+  DOWNLOADS
+    .lock()
+    .unwrap()
+    .iter()
+    .any(|d| d.state == DownloadState::InProgress)
+}
+
+pub fn cancel(id: usize) {
+  let mut downloads = DOWNLOADS.lock().unwrap();
+  if let Some(download) = downloads.iter_mut().find(|d| d.id == id)
+    && download.state == DownloadState::InProgress
+  {
+    download.attempt += 1;
+    download.state = DownloadState::Cancelled;
+  }
+}
+
+pub fn resume(id: usize) {
+  let download = {
+    let mut downloads = DOWNLOADS.lock().unwrap();
+    let Some(download) = downloads.iter_mut().find(|d| d.id == id) else {
+      return;
+    };
+
+    if matches!(
+      download.state,
+      DownloadState::InProgress | DownloadState::Completed
+    ) {
+      return;
+    }
+
+    download.attempt += 1;
+    download.state = DownloadState::InProgress;
+    download.clone()
+  };
+
+  thread::spawn(move || {
+    if let Err(error) = continue_download(&download) {
+      update_current(&download, |d| {
+        d.state = DownloadState::Failed(error.to_string())
+      });
+    }
+  });
+}
+
+pub(crate) fn begin(url: String, response: Response) -> Result<usize, Box<dyn std::error::Error>> {
+  let headers = response.headers.clone();
+  let gzipped = headers.get("content-encoding").map(|v| v.as_str()) == Some("gzip");
+
+  let name = headers
+    .get("content-disposition")
+    .and_then(|v| filename_from_disposition(v))
+    .or_else(|| filename_from_url(&url))
+    .unwrap_or_else(|| String::from("download"));
+
+  let mut downloads = DOWNLOADS.lock().unwrap();
+
+  let download = Download {
+    id: downloads.len(),
+    url,
+    path: unique_path(&download_dir(), &name),
+    received: 0,
+    // a gzip body is decoded on the fly, so neither its length nor byte
+    // offsets into it match the file we end up writing
+    total: if gzipped {
+      None
+    } else {
+      headers.get("content-length").and_then(|v| v.parse().ok())
+    },
+    state: DownloadState::InProgress,
+    validator: headers
+      .get("etag")
+      .or_else(|| headers.get("last-modified"))
+      .cloned(),
+    resumable: !gzipped && headers.get("accept-ranges").map(|v| v.as_str()) == Some("bytes"),
+    attempt: 0,
+  };
+
+  let id = download.id;
+  let file = File::create(download.part_path())?;
+  let mut body = response.into_body()?;
+  if gzipped {
+    body = Box::new(GzDecoder::new(body));
+  }
+
+  downloads.push(download.clone());
+  drop(downloads);
+
+  thread::spawn(move || {
+    if let Err(error) = stream_to_file(&download, body, file) {
+      update_current(&download, |d| {
+        d.state = DownloadState::Failed(error.to_string())
+      });
+    }
+  });
+
+  Ok(id)
+}
+
+// "save page as": the document is written next to the downloads and listed
+// with them as an already finished download
+pub fn save_page(url: &str, html: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+  let name = filename_from_url(url).unwrap_or_else(|| String::from("index"));
+  let name = if name.ends_with(".html") || name.ends_with(".htm") {
+    name
+  } else {
+    format!("{name}.html")
+  };
+
+  let path = unique_path(&download_dir(), &name);
+  fs::write(&path, html)?;
+
+  let mut downloads = DOWNLOADS.lock().unwrap();
+  let size = html.len() as u64;
+  let id = downloads.len();
+  downloads.push(Download {
+    id,
+    url: url.to_string(),
+    path: path.clone(),
+    received: size,
+    total: Some(size),
+    state: DownloadState::Completed,
+    validator: None,
+    resumable: false,
+    attempt: 0,
+  });
+
+  Ok(path)
+}
+
+fn continue_download(download: &Download) -> Result<(), Box<dyn std::error::Error>> {
+  let offset = if download.resumable {
+    download.received
+  } else {
+    0
+  };
+
+  let mut url_handler = URLHandler::default();
+  url_handler.init(download.url.clone(), false);
+  let mut response = url_handler.request_range(offset, download.validator.as_deref())?;
+
+  // appending a range that doesn't start where the file ends would corrupt it
+  if response.status == "206" && range_start(&response.headers) != Some(offset) {
+    log::warn!(url = download.url.as_str(), offset = offset; "server sent another range, restarting the download");
+    response = url_handler.request_range(0, None)?;
+  }
+
+  let file = match response.status.as_str() {
+    "206" => OpenOptions::new().append(true).open(download.part_path())?,
+    "200" => {
+      let total = response
+        .headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok());
+      update_current(download, |d| {
+        d.received = 0;
+        d.total = total;
+      });
+      File::create(download.part_path())?
+    }
+    status => return Err(format!("Server answered resume with status {status}").into()),
+  };
+
+  stream_to_file(download, response.into_body()?, file)
+}
+
+fn stream_to_file(
+  download: &Download,
+  mut body: Box<dyn Read + Send>,
+  mut file: File,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut buffer = [0u8; 16 * 1024];
+
+  loop {
+    if !is_current(download) {
+      return Ok(());
+    }
+
+    let read = body.read(&mut buffer)?;
+    if read == 0 {
+      break;
+    }
+
+    // the write happens under the downloads lock, so once cancel or resume
+    // has moved on, a worker that was blocked in read can't append to the
+    // file the next attempt is using
+    let mut written = Ok(());
+    let current = update_current(download, |d| {
+      written = file.write_all(&buffer[..read]);
+      if written.is_ok() {
+        d.received += read as u64;
+      }
+    });
+    if !current {
+      return Ok(());
+    }
+    written?;
+  }
+
+  file.flush()?;
+
+  let mut finished: Result<(), Box<dyn std::error::Error>> = Ok(());
+  update_current(download, |d| {
+    finished = match d.total {
+      Some(total) if d.received < total => {
+        Err("Connection closed before the download finished".into())
+      }
+      _ => fs::rename(download.part_path(), &download.path).map_err(Into::into),
+    };
+    if finished.is_ok() {
+      d.state = DownloadState::Completed;
+    }
+  });
+
+  finished
+}
+
+// the first byte of a "Content-Range: bytes 100-999/1000" response
+fn range_start(headers: &HashMap<String, String>) -> Option<u64> {
+  let range = headers
+    .get("content-range")?
+    .trim()
+    .strip_prefix("bytes ")?;
+  let (start, _) = range.split_once('-')?;
+  start.trim().parse().ok()
+}
+
+// runs f on the download only if it is still on the attempt this copy was
+// made for; returns whether it was
+fn update_current(download: &Download, f: impl FnOnce(&mut Download)) -> bool {
+  let mut downloads = DOWNLOADS.lock().unwrap();
+  match downloads.iter_mut().find(|d| d.id == download.id) {
+    Some(current) if current.attempt == download.attempt => {
+      f(current);
+      true
+    }
+    _ => false,
+  }
+}
+
+fn is_current(download: &Download) -> bool {
+  update_current(download, |_| ())
+}
+
+fn download_dir() -> PathBuf {
+  if let Ok(dir) = env::var("XDG_DOWNLOAD_DIR") {
+    return PathBuf::from(dir);
+  }
+
+  let home = env::var("HOME").or_else(|_| env::var("USERPROFILE"));
+  if let Ok(home) = home {
+    let dir = Path::new(&home).join("Downloads");
+    if dir.is_dir() {
+      return dir;
+    }
+  }
+
+  PathBuf::from(".")
+}
+
+fn unique_path(dir: &Path, name: &str) -> PathBuf {
+  let candidate = dir.join(name);
+  if !candidate.exists() {
+    return candidate;
+  }
+
+  let (stem, extension) = match name.rsplit_once('.') {
+    Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+    _ => (name, String::new()),
+  };
+
+  (1..)
+    .map(|n| dir.join(format!("{stem} ({n}){extension}")))
+    .find(|path| !path.exists())
+    .unwrap()
+}
+
+fn filename_from_disposition(value: &str) -> Option<String> {
+  let mut fallback = None;
+
+  for (key, value) in disposition_params(value) {
+    if key == "filename*" {
+      // RFC 5987: charset'language'percent-encoded-value
+      if let Some((_, encoded)) = value.rsplit_once('\'') {
+        return sanitize(&percent_decode(encoded));
+      }
+    } else if key == "filename" {
+      fallback = sanitize(&value);
+    }
+  }
+
+  fallback
+}
+
+// the parameters after the disposition type; a quoted value may contain ';'
+// and backslash escapes
+fn disposition_params(value: &str) -> Vec<(String, String)> {
+  let mut params = Vec::new();
+  let mut chars = value.chars().skip_while(|&c| c != ';').peekable();
+
+  while chars.next() == Some(';') {
+    let mut key = String::new();
+    while let Some(c) = chars.next_if(|&c| c != '=' && c != ';') {
+      key.push(c);
+    }
+
+    let mut value = String::new();
+    if chars.next_if_eq(&'=').is_some() {
+      while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+      if chars.next_if_eq(&'"').is_some() {
+        while let Some(c) = chars.next() {
+          match c {
+            '"' => break,
+            '\\' => value.extend(chars.next()),
+            _ => value.push(c),
+          }
+        }
+        // anything between the closing quote and the next ';' is junk
+        while chars.next_if(|&c| c != ';').is_some() {}
+      } else {
+        while let Some(c) = chars.next_if(|&c| c != ';') {
+          value.push(c);
+        }
+        value.truncate(value.trim_end().len());
+      }
+    }
+
+    params.push((key.trim().to_lowercase(), value));
+  }
+
+  params
+}
+
+fn filename_from_url(url: &str) -> Option<String> {
+  let path = url.split(['?', '#']).next().unwrap_or("");
+  let (_, last) = path.rsplit_once('/')?;
+  sanitize(&percent_decode(last))
+}
+
+fn sanitize(name: &str) -> Option<String> {
+  let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+  let cleaned: String = name
+    .chars()
+    .filter(|c| !c.is_control() && !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+    .collect();
+  let cleaned = cleaned.trim().trim_start_matches('.').to_string();
+
+  if cleaned.is_empty() {
+    None
+  } else {
+    Some(cleaned)
+  }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+  const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+  let mut value = bytes as f64;
+  let mut unit = 0;
+
+  while value >= 1024.0 && unit < UNITS.len() - 1 {
+    value /= 1024.0;
+    unit += 1;
+  }
+
+  if unit == 0 {
+    format!("{bytes} B")
+  } else {
+    format!("{value:.1} {}", UNITS[unit])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn filename_from_content_disposition() {
+    let cases = [
+      ("attachment; filename=report.pdf", Some("report.pdf")),
+      ("attachment; filename=\"a;b.txt\"", Some("a;b.txt")),
+      (
+        "attachment; filename=\"say \\\"hi\\\".txt\"",
+        Some("say hi.txt"),
+      ),
+      ("attachment; FILENAME = \"x.txt\" ; size=3", Some("x.txt")),
+      (
+        "attachment; filename=\"fallback.txt\"; filename*=UTF-8''%E2%82%AC%20rates.txt",
+        Some("\u{20ac} rates.txt"),
+      ),
+      ("attachment; filename=\"../../etc/passwd\"", Some("passwd")),
+      ("attachment; filename=\"\"", None),
+      ("inline", None),
+    ];
+    for (value, expected) in cases {
+      assert_eq!(
+        filename_from_disposition(value).as_deref(),
+        expected,
+        "{value}"
+      );
+    }
+  }
+
+  #[test]
+  fn sanitize_keeps_only_a_safe_file_name() {
+    assert_eq!(sanitize("C:\\Users\\a\\b.exe").as_deref(), Some("b.exe"));
+    assert_eq!(sanitize("a<b>:c?.txt").as_deref(), Some("abc.txt"));
+    assert_eq!(sanitize("..hidden").as_deref(), Some("hidden"));
+    assert_eq!(sanitize(" x\u{7}y ").as_deref(), Some("xy"));
+    assert_eq!(sanitize("..").as_deref(), None);
+    assert_eq!(sanitize("a/").as_deref(), None);
+  }
+
+  #[test]
+  fn unique_path_numbers_taken_names() {
+    let dir = env::temp_dir().join(format!("agr-unique-path-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    assert_eq!(unique_path(&dir, "a.txt"), dir.join("a.txt"));
+    fs::write(dir.join("a.txt"), "").unwrap();
+    assert_eq!(unique_path(&dir, "a.txt"), dir.join("a (1).txt"));
+    fs::write(dir.join("a (1).txt"), "").unwrap();
+    assert_eq!(unique_path(&dir, "a.txt"), dir.join("a (2).txt"));
+
+    // a leading dot isn't an extension
+    fs::write(dir.join(".profile"), "").unwrap();
+    assert_eq!(unique_path(&dir, ".profile"), dir.join(".profile (1)"));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn range_start_reads_content_range() {
+    let headers = |value: &str| HashMap::from([("content-range".to_string(), value.to_string())]);
+    assert_eq!(range_start(&headers("bytes 100-999/1000")), Some(100));
+    assert_eq!(range_start(&headers(" bytes 0-0/*")), Some(0));
+    assert_eq!(range_start(&headers("bytes */1000")), None);
+    assert_eq!(range_start(&headers("items 1-2/3")), None);
+    assert_eq!(range_start(&HashMap::new()), None);
+  }
+
+  #[test]
+  fn format_bytes_picks_a_unit() {
+    assert_eq!(format_bytes(0), "0 B");
+    assert_eq!(format_bytes(1023), "1023 B");
+    assert_eq!(format_bytes(1024), "1.0 KB");
+    assert_eq!(format_bytes(1536), "1.5 KB");
+    assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MB");
+    assert_eq!(format_bytes(3 << 40), "3072.0 GB");
+  }
+
+  #[test]
+  fn a_superseded_worker_writes_nothing() {
+    let dir = env::temp_dir().join(format!("agr-attempt-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let download = {
+      let mut downloads = DOWNLOADS.lock().unwrap();
+      let download = Download {
+        id: downloads.len(),
+        url: String::from("http://example.com/a"),
+        path: dir.join("a"),
+        received: 0,
+        total: None,
+        state: DownloadState::InProgress,
+        validator: None,
+        resumable: true,
+        attempt: 0,
+      };
+      downloads.push(download.clone());
+      download
+    };
+
+    // the worker was blocked in read while the download was cancelled, a
+    // resume would move the attempt on the same way
+    cancel(download.id);
+    let body: Box<dyn Read + Send> = Box::new(&b"late bytes"[..]);
+    let file = File::create(download.part_path()).unwrap();
+    stream_to_file(&download, body, file).unwrap();
+
+    let now = downloads()
+      .into_iter()
+      .find(|d| d.id == download.id)
+      .unwrap();
+    assert_eq!(now.received, 0);
+    assert_eq!(now.state, DownloadState::Cancelled);
+    assert_eq!(fs::read(download.part_path()).unwrap(), b"");
+    assert!(!download.path.exists());
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,232 @@
+// Small, dependency-free digests for HTTP Digest authentication (RFC 7616),
+// plus the base64 encoding Basic authentication needs.
+
+pub fn md5_hex(input: &[u8]) -> String {
+  const S: [u32; 64] = [
+    7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9,
+    14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15,
+    21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+  ];
+
+  let k: Vec<u32> = (0..64)
+    .map(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32)
+    .collect();
+
+  let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+  let mut message = input.to_vec();
+  let bit_len = (input.len() as u64).wrapping_mul(8);
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&bit_len.to_le_bytes());
+
+  for block in message.chunks(64) {
+    let m: Vec<u32> = block
+      .chunks(4)
+      .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+      .collect();
+
+    let [mut a, mut b, mut c, mut d] = state;
+
+    for i in 0..64 {
+      let (f, g) = match i {
+        0..=15 => ((b & c) | (!b & d), i),
+        16..=31 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+        32..=47 => (b ^ c ^ d, (3 * i + 5) % 16),
+        _ => (c ^ (b | !d), (7 * i) % 16),
+      };
+
+      let rotated = a
+        .wrapping_add(f)
+        .wrapping_add(k[i])
+        .wrapping_add(m[g])
+        .rotate_left(S[i]);
+      a = d;
+      d = c;
+      c = b;
+      b = b.wrapping_add(rotated);
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+  }
+
+  state
+    .iter()
+    .flat_map(|word| word.to_le_bytes())
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+pub fn sha256_hex(input: &[u8]) -> String {
+  const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+  ];
+
+  let mut state: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+  ];
+
+  let mut message = input.to_vec();
+  let bit_len = (input.len() as u64).wrapping_mul(8);
+  message.push(0x80);
+  while message.len() % 64 != 56 {
+    message.push(0);
+  }
+  message.extend_from_slice(&bit_len.to_be_bytes());
+
+  for block in message.chunks(64) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks(4).enumerate() {
+      w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+    }
+    for i in 16..64 {
+      let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+      let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+      w[i] = w[i - 16]
+        .wrapping_add(s0)
+        .wrapping_add(w[i - 7])
+        .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+
+    for i in 0..64 {
+      let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+      let ch = (e & f) ^ (!e & g);
+      let temp1 = h
+        .wrapping_add(s1)
+        .wrapping_add(ch)
+        .wrapping_add(K[i])
+        .wrapping_add(w[i]);
+      let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+      let maj = (a & b) ^ (a & c) ^ (b & c);
+      let temp2 = s0.wrapping_add(maj);
+
+      h = g;
+      g = f;
+      f = e;
+      e = d.wrapping_add(temp1);
+      d = c;
+      c = b;
+      b = a;
+      a = temp1.wrapping_add(temp2);
+    }
+
+    for (value, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+      *value = value.wrapping_add(add);
+    }
+  }
+
+  state
+    .iter()
+    .flat_map(|word| word.to_be_bytes())
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+pub fn base64_encode(input: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+  let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+
+  for group in input.chunks(3) {
+    let bytes = [
+      group[0],
+      *group.get(1).unwrap_or(&0),
+      *group.get(2).unwrap_or(&0),
+    ];
+    let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+    for i in 0..4 {
+      if i <= group.len() {
+        let index = (bits >> (18 - 6 * i)) & 0x3f;
+        encoded.push(ALPHABET[index as usize] as char);
+      } else {
+        encoded.push('=');
+      }
+    }
+  }
+
+  encoded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // RFC 1321, appendix A.5
+  #[test]
+  fn md5_test_suite() {
+    let cases = [
+      ("", "d41d8cd98f00b204e9800998ecf8427e"),
+      ("a", "0cc175b9c0f1b6a831c399e269772661"),
+      ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+      ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+      (
+        "abcdefghijklmnopqrstuvwxyz",
+        "c3fcd3d76192e4007dfb496cca67e13b",
+      ),
+      (
+        "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+        "d174ab98d277d9f5a5611c2c9f419d9f",
+      ),
+      (
+        "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+        "57edf4a22be3c955ac49da2e2107b67a",
+      ),
+    ];
+    for (input, digest) in cases {
+      assert_eq!(md5_hex(input.as_bytes()), digest, "{input:?}");
+    }
+  }
+
+  // FIPS 180-2, appendix B, plus the empty message
+  #[test]
+  fn sha256_test_vectors() {
+    assert_eq!(
+      sha256_hex(b""),
+      "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+      sha256_hex(b"abc"),
+      "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+      sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+      "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(
+      sha256_hex(&vec![b'a'; 1_000_000]),
+      "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+    );
+  }
+
+  // RFC 4648, section 10
+  #[test]
+  fn base64_test_vectors() {
+    let cases = [
+      ("", ""),
+      ("f", "Zg=="),
+      ("fo", "Zm8="),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg=="),
+      ("fooba", "Zm9vYmE="),
+      ("foobar", "Zm9vYmFy"),
+    ];
+    for (input, encoded) in cases {
+      assert_eq!(base64_encode(input.as_bytes()), encoded, "{input:?}");
+    }
+  }
+}
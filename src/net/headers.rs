@@ -0,0 +1,231 @@
+use std::env;
+
+use crate::net::config;
+
+pub const ACCEPT_DOCUMENT: &str =
+  "text/html,application/xhtml+xml,application/xml;q=0.9,text/plain;q=0.8,*/*;q=0.5";
+pub const ACCEPT_ANY: &str = "*/*";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReferrerPolicy {
+  NoReferrer,
+  NoReferrerWhenDowngrade,
+  SameOrigin,
+  Origin,
+  StrictOrigin,
+  OriginWhenCrossOrigin,
+  StrictOriginWhenCrossOrigin,
+  UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+  pub fn parse(value: &str) -> Option<Self> {
+    // a Referrer-Policy header may list several policies for backwards
+    // compatibility; the last one we understand wins
+    value
+      .split(',')
+      .rev()
+      .find_map(|token| match token.trim().to_lowercase().as_str() {
+        "no-referrer" => Some(ReferrerPolicy::NoReferrer),
+        "no-referrer-when-downgrade" => Some(ReferrerPolicy::NoReferrerWhenDowngrade),
+        "same-origin" => Some(ReferrerPolicy::SameOrigin),
+        "origin" => Some(ReferrerPolicy::Origin),
+        "strict-origin" => Some(ReferrerPolicy::StrictOrigin),
+        "origin-when-cross-origin" => Some(ReferrerPolicy::OriginWhenCrossOrigin),
+        "strict-origin-when-cross-origin" => Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+        "unsafe-url" => Some(ReferrerPolicy::UnsafeUrl),
+        _ => None,
+      })
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      ReferrerPolicy::NoReferrer => "no-referrer",
+      ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+      ReferrerPolicy::SameOrigin => "same-origin",
+      ReferrerPolicy::Origin => "origin",
+      ReferrerPolicy::StrictOrigin => "strict-origin",
+      ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+      ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+      ReferrerPolicy::UnsafeUrl => "unsafe-url",
+    }
+  }
+}
+
+pub struct Referrer<'a> {
+  pub scheme: &'a str,
+  pub origin: &'a str,
+  pub url: &'a str,
+}
+
+pub fn referrer_value(
+  referrer: &Referrer,
+  target_scheme: &str,
+  target_origin: &str,
+  policy: ReferrerPolicy,
+) -> Option<String> {
+  if referrer.scheme != "http" && referrer.scheme != "https" {
+    return None;
+  }
+
+  let same_origin = referrer.origin == target_origin;
+  let downgrade = referrer.scheme == "https" && target_scheme != "https";
+  let origin = format!("{}/", referrer.origin);
+
+  let value = match policy {
+    ReferrerPolicy::NoReferrer => return None,
+    ReferrerPolicy::UnsafeUrl => referrer.url.to_string(),
+    ReferrerPolicy::NoReferrerWhenDowngrade if downgrade => return None,
+    ReferrerPolicy::NoReferrerWhenDowngrade => referrer.url.to_string(),
+    ReferrerPolicy::SameOrigin if same_origin => referrer.url.to_string(),
+    ReferrerPolicy::SameOrigin => return None,
+    ReferrerPolicy::Origin => origin,
+    ReferrerPolicy::StrictOrigin if downgrade => return None,
+    ReferrerPolicy::StrictOrigin => origin,
+    ReferrerPolicy::OriginWhenCrossOrigin if same_origin => referrer.url.to_string(),
+    ReferrerPolicy::OriginWhenCrossOrigin => origin,
+    ReferrerPolicy::StrictOriginWhenCrossOrigin if same_origin => referrer.url.to_string(),
+    ReferrerPolicy::StrictOriginWhenCrossOrigin if downgrade => return None,
+    ReferrerPolicy::StrictOriginWhenCrossOrigin => origin,
+  };
+
+  Some(value)
+}
+
+pub fn accept_language() -> String {
+  if let Some(value) = config::get().accept_language {
+    return value;
+  }
+
+  let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+    .iter()
+    .filter_map(|name| env::var(name).ok())
+    .find(|value| !value.is_empty() && value != "C" && value != "POSIX");
+
+  // "de_AT.UTF-8@euro" -> "de-AT,de;q=0.9"
+  let Some(locale) = locale else {
+    return String::from("en-US,en;q=0.9");
+  };
+  let tag = locale
+    .split(['.', '@'])
+    .next()
+    .unwrap_or("")
+    .replace('_', "-");
+
+  match tag.split_once('-') {
+    Some((language, _)) => format!("{tag},{language};q=0.9"),
+    None => tag,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn referrer_policy_table() {
+    use ReferrerPolicy::*;
+
+    let page = Referrer {
+      scheme: "https",
+      origin: "https://a.com",
+      url: "https://a.com/p?q",
+    };
+    let full = Some("https://a.com/p?q");
+    let origin = Some("https://a.com/");
+
+    // same origin, cross origin over https, and a downgrade to http
+    let cases = [
+      (NoReferrer, [None, None, None]),
+      (NoReferrerWhenDowngrade, [full, full, None]),
+      (SameOrigin, [full, None, None]),
+      (Origin, [origin, origin, origin]),
+      (StrictOrigin, [origin, origin, None]),
+      (OriginWhenCrossOrigin, [full, origin, origin]),
+      (StrictOriginWhenCrossOrigin, [full, origin, None]),
+      (UnsafeUrl, [full, full, full]),
+    ];
+    let targets = [
+      ("https", "https://a.com"),
+      ("https", "https://b.com"),
+      ("http", "http://b.com"),
+    ];
+    for (policy, expected) in cases {
+      for ((scheme, target), expected) in targets.iter().zip(expected) {
+        assert_eq!(
+          referrer_value(&page, scheme, target, policy).as_deref(),
+          expected,
+          "{} to {target}",
+          policy.as_str()
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn referrers_from_http_and_other_schemes() {
+    let page = Referrer {
+      scheme: "http",
+      origin: "http://a.com",
+      url: "http://a.com/p",
+    };
+    // http to http is not a downgrade, and a different port is another origin
+    let cases = [
+      (
+        ReferrerPolicy::NoReferrerWhenDowngrade,
+        "http://b.com",
+        Some("http://a.com/p"),
+      ),
+      (
+        ReferrerPolicy::StrictOrigin,
+        "http://b.com",
+        Some("http://a.com/"),
+      ),
+      (
+        ReferrerPolicy::StrictOriginWhenCrossOrigin,
+        "http://a.com:8080",
+        Some("http://a.com/"),
+      ),
+      (ReferrerPolicy::SameOrigin, "http://a.com:8080", None),
+    ];
+    for (policy, target, expected) in cases {
+      assert_eq!(
+        referrer_value(&page, "http", target, policy).as_deref(),
+        expected,
+        "{} to {target}",
+        policy.as_str()
+      );
+    }
+
+    // file:, data: and about: documents never send one
+    for scheme in ["file", "data", "about"] {
+      let page = Referrer {
+        scheme,
+        origin: "",
+        url: "x",
+      };
+      assert_eq!(
+        referrer_value(&page, "https", "https://a.com", ReferrerPolicy::UnsafeUrl),
+        None
+      );
+    }
+  }
+
+  #[test]
+  fn referrer_policy_header_takes_the_last_known_token() {
+    let cases = [
+      ("no-referrer", Some(ReferrerPolicy::NoReferrer)),
+      (" Origin ", Some(ReferrerPolicy::Origin)),
+      ("unsafe-url, bogus", Some(ReferrerPolicy::UnsafeUrl)),
+      (
+        "no-referrer, strict-origin",
+        Some(ReferrerPolicy::StrictOrigin),
+      ),
+      ("bogus", None),
+      ("", None),
+    ];
+    for (header, expected) in cases {
+      assert_eq!(ReferrerPolicy::parse(header), expected, "{header:?}");
+    }
+  }
+}
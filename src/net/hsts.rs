@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+
+use crate::net::config;
+
+const HSTS_FILE: &str = "hsts";
+
+lazy_static! {
+  static ref HSTS: Mutex<HashMap<String, HstsPolicy>> = Mutex::new(load());
+}
+
+#[derive(Debug, Clone)]
+pub struct HstsPolicy {
+  pub expires: u64,
+  pub include_subdomains: bool,
+}
+
+// records a Strict-Transport-Security header; only call this for responses
+// that arrived over an authenticated https connection
+pub fn observe(host: &str, header: &str) {
+  let mut policies = HSTS.lock().unwrap();
+  if record(&mut policies, host, header, now()) {
+    save(&policies);
+  }
+}
+
+pub fn is_secure_host(host: &str) -> bool {
+  lookup(&HSTS.lock().unwrap(), host, now())
+}
+
+pub fn policies() -> Vec<(String, HstsPolicy)> {
+  let mut policies: Vec<(String, HstsPolicy)> = HSTS
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(host, policy)| (host.clone(), policy.clone()))
+    .collect();
+  policies.sort_by(|a, b| a.0.cmp(&b.0));
+  policies
+}
+
+// IP literals and single-label names like "localhost" never get HSTS or
+// speculative https upgrades
+pub fn is_upgradable(host: &str) -> bool {
+  let host = host.strip_suffix('.').unwrap_or(host);
+  let literal = host.trim_start_matches('[').trim_end_matches(']');
+  literal.parse::<IpAddr>().is_err() && host.contains('.')
+}
+
+// "Example.COM." and "example.com" are the same host
+fn normalize(host: &str) -> String {
+  let host = host.to_ascii_lowercase();
+  match host.strip_suffix('.') {
+    Some(host) => host.to_string(),
+    None => host,
+  }
+}
+
+// the max-age and whether includeSubDomains was given, None when the header
+// has no valid max-age and must be ignored entirely
+fn parse_header(header: &str) -> Option<(u64, bool)> {
+  let mut max_age = None;
+  let mut include_subdomains = false;
+
+  for directive in header.split(';') {
+    let directive = directive.trim();
+    match directive.split_once('=') {
+      Some((name, value)) if name.trim().eq_ignore_ascii_case("max-age") => {
+        max_age = value.trim().trim_matches('"').parse::<u64>().ok();
+      }
+      None if directive.eq_ignore_ascii_case("includesubdomains") => include_subdomains = true,
+      _ => (),
+    }
+  }
+
+  max_age.map(|max_age| (max_age, include_subdomains))
+}
+
+// true when the policies changed and need saving
+fn record(policies: &mut HashMap<String, HstsPolicy>, host: &str, header: &str, now: u64) -> bool {
+  let host = normalize(host);
+  if !is_upgradable(&host) {
+    return false;
+  }
+  let Some((max_age, include_subdomains)) = parse_header(header) else {
+    return false;
+  };
+
+  if max_age == 0 {
+    policies.remove(&host).is_some()
+  } else {
+    policies.insert(
+      host,
+      HstsPolicy {
+        expires: now.saturating_add(max_age),
+        include_subdomains,
+      },
+    );
+    true
+  }
+}
+
+fn lookup(policies: &HashMap<String, HstsPolicy>, host: &str, now: u64) -> bool {
+  let host = normalize(host);
+
+  if let Some(policy) = policies.get(&host)
+    && policy.expires > now
+  {
+    return true;
+  }
+
+  // walk up the superdomains: a.b.example.com -> b.example.com -> example.com
+  let mut rest = host.as_str();
+  while let Some((_, parent)) = rest.split_once('.') {
+    if let Some(policy) = policies.get(parent)
+      && policy.include_subdomains
+      && policy.expires > now
+    {
+      return true;
+    }
+    rest = parent;
+  }
+
+  false
+}
+
+fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .unwrap()
+    .as_secs()
+}
+
+fn load() -> HashMap<String, HstsPolicy> {
+  match fs::read_to_string(config::profile_dir().join(HSTS_FILE)) {
+    Ok(contents) => parse_policies(&contents, now()),
+    Err(_) => HashMap::new(),
+  }
+}
+
+// the saved policies that haven't expired yet
+fn parse_policies(contents: &str, now: u64) -> HashMap<String, HstsPolicy> {
+  let mut policies = HashMap::new();
+
+  for line in contents.lines() {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if let [host, expires, include_subdomains] = parts[..]
+      && let Ok(expires) = expires.parse::<u64>()
+      && expires > now
+    {
+      policies.insert(
+        normalize(host),
+        HstsPolicy {
+          expires,
+          include_subdomains: include_subdomains == "1",
+        },
+      );
+    }
+  }
+
+  policies
+}
+
+fn save(policies: &HashMap<String, HstsPolicy>) {
+  let dir = config::profile_dir();
+  let contents: String = policies
+    .iter()
+    .map(|(host, policy)| {
+      format!(
+        "{} {} {}\n",
+        host,
+        policy.expires,
+        if policy.include_subdomains { 1 } else { 0 }
+      )
+    })
+    .collect();
+
+  if let Err(error) =
+    fs::create_dir_all(&dir).and_then(|_| fs::write(dir.join(HSTS_FILE), contents))
+  {
+    log::warn!(error:% = error; "could not persist HSTS policies");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const NOW: u64 = 1_000_000;
+
+  #[test]
+  fn max_age_parsing() {
+    let cases = [
+      ("max-age=31536000", Some((31536000, false))),
+      ("max-age=\"600\"; includeSubDomains", Some((600, true))),
+      (" MAX-AGE = 600 ;INCLUDESUBDOMAINS ", Some((600, true))),
+      ("includeSubDomains; max-age=0", Some((0, true))),
+      ("max-age=0", Some((0, false))),
+      ("includeSubDomains", None),
+      ("max-age=", None),
+      ("max-age=-1", None),
+      ("max-age=soon", None),
+      ("", None),
+      // includeSubDomains takes no value
+      ("max-age=1; includeSubDomains=yes", Some((1, false))),
+    ];
+    for (header, expected) in cases {
+      assert_eq!(parse_header(header), expected, "{header:?}");
+    }
+  }
+
+  #[test]
+  fn record_and_remove() {
+    let mut policies = HashMap::new();
+
+    assert!(record(&mut policies, "Example.COM", "max-age=100", NOW));
+    let policy = &policies["example.com"];
+    assert_eq!(
+      (policy.expires, policy.include_subdomains),
+      (NOW + 100, false)
+    );
+    assert!(lookup(&policies, "example.com", NOW));
+    assert!(lookup(&policies, "EXAMPLE.com", NOW + 99));
+    assert!(!lookup(&policies, "example.com", NOW + 100));
+
+    // a header without a valid max-age changes nothing
+    assert!(!record(&mut policies, "example.com", "max-age=x", NOW));
+    assert!(lookup(&policies, "example.com", NOW));
+
+    // max-age=0 removes the policy
+    assert!(record(&mut policies, "example.com", "max-age=0", NOW));
+    assert!(policies.is_empty());
+    assert!(!record(&mut policies, "example.com", "max-age=0", NOW));
+  }
+
+  #[test]
+  fn subdomains_inherit_only_when_included() {
+    let mut policies = HashMap::new();
+    record(
+      &mut policies,
+      "example.com",
+      "max-age=100; includeSubDomains",
+      NOW,
+    );
+    record(&mut policies, "other.org", "max-age=100", NOW);
+
+    assert!(lookup(&policies, "a.b.example.com", NOW));
+    assert!(lookup(&policies, "www.example.com", NOW));
+    assert!(!lookup(&policies, "notexample.com", NOW));
+    assert!(!lookup(&policies, "com", NOW));
+    assert!(lookup(&policies, "other.org", NOW));
+    assert!(!lookup(&policies, "www.other.org", NOW));
+
+    // an expired superdomain policy doesn't count
+    assert!(!lookup(&policies, "www.example.com", NOW + 100));
+  }
+
+  #[test]
+  fn a_trailing_dot_is_the_same_host() {
+    let mut policies = HashMap::new();
+    record(&mut policies, "example.com.", "max-age=100", NOW);
+    assert!(policies.contains_key("example.com"));
+    assert!(lookup(&policies, "example.com", NOW));
+    assert!(lookup(&policies, "example.com.", NOW));
+
+    record(&mut policies, "example.com", "max-age=0", NOW);
+    assert!(!lookup(&policies, "example.com.", NOW));
+  }
+
+  #[test]
+  fn ip_literals_and_single_labels_are_ignored() {
+    for host in [
+      "127.0.0.1",
+      "[::1]",
+      "::1",
+      "localhost",
+      "localhost.",
+      "intranet",
+    ] {
+      let mut policies = HashMap::new();
+      assert!(!is_upgradable(host), "{host}");
+      assert!(!record(&mut policies, host, "max-age=100", NOW), "{host}");
+      assert!(!lookup(&policies, host, NOW), "{host}");
+    }
+    assert!(is_upgradable("example.com"));
+    assert!(is_upgradable("example.com."));
+  }
+
+  #[test]
+  fn saved_policies_load_until_they_expire() {
+    let contents = "example.com 1000100 1\nold.com 999999 0\nbad line\nEXAMPLE.org. 1000100 0\n";
+    let policies = parse_policies(contents, NOW);
+    let mut hosts: Vec<&str> = policies.keys().map(String::as_str).collect();
+    hosts.sort();
+    assert_eq!(hosts, ["example.com", "example.org"]);
+    assert!(policies["example.com"].include_subdomains);
+    assert!(!policies["example.org"].include_subdomains);
+  }
+}
@@ -0,0 +1,401 @@
+// punycode parameters from RFC 3492
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+
+const ACE_PREFIX: &str = "xn--";
+
+// ideographic and fullwidth full stops separate labels just like '.'
+const LABEL_SEPARATORS: [char; 4] = ['.', '\u{3002}', '\u{ff0e}', '\u{ff61}'];
+
+pub fn to_ascii(host: &str) -> Result<String, String> {
+  // IPv6 literals are passed through untouched
+  if host.starts_with('[') {
+    return Ok(host.to_string());
+  }
+
+  let labels: Result<Vec<String>, String> = host
+    .split(LABEL_SEPARATORS)
+    .map(|label| {
+      let label = label.to_lowercase();
+      if label.is_ascii() {
+        return Ok(label);
+      }
+
+      let encoded = punycode_encode(&label).ok_or_else(|| format!("Invalid host: {host}"))?;
+      Ok(format!("{ACE_PREFIX}{encoded}"))
+    })
+    .collect();
+
+  let ascii = labels?.join(".");
+
+  if ascii
+    .chars()
+    .any(|c| c.is_ascii_control() || " #%/:<>?@[\\]^|".contains(c))
+  {
+    return Err(format!("Invalid host: {host}"));
+  }
+
+  Ok(ascii)
+}
+
+pub fn to_unicode(host: &str) -> String {
+  host
+    .split('.')
+    .map(|label| {
+      // a real punycode label decodes to something non-ASCII, anything else
+      // is shown as it was written
+      label
+        .strip_prefix(ACE_PREFIX)
+        .and_then(punycode_decode)
+        .filter(|decoded| !decoded.is_ascii())
+        .unwrap_or_else(|| label.to_string())
+    })
+    .collect::<Vec<String>>()
+    .join(".")
+}
+
+// UTF-8 percent-encodes everything a server should not see raw in the request
+// line; existing escapes are left alone so encoding twice is harmless
+pub fn encode_path(path: &str) -> String {
+  let (path, query) = match path.split_once('?') {
+    Some((path, query)) => (path, Some(query)),
+    None => (path, None),
+  };
+
+  let mut encoded = percent_encode(path, "\"<>`{}");
+  if let Some(query) = query {
+    encoded.push('?');
+    encoded.push_str(&percent_encode(query, "\"<>'"));
+  }
+
+  encoded
+}
+
+fn percent_encode(text: &str, extra: &str) -> String {
+  let mut encoded = String::with_capacity(text.len());
+
+  for c in text.chars() {
+    if c.is_ascii_graphic() && !extra.contains(c) {
+      encoded.push(c);
+    } else {
+      let mut buffer = [0; 4];
+      for byte in c.encode_utf8(&mut buffer).bytes() {
+        encoded.push_str(&format!("%{byte:02X}"));
+      }
+    }
+  }
+
+  encoded
+}
+
+// the form shown to people: punycode labels decoded and percent-encoded
+// UTF-8 in the path turned back into characters
+pub fn display_url(url: &str) -> String {
+  let Some((scheme, rest)) = url.split_once("://") else {
+    return url.to_string();
+  };
+  if scheme != "http" && scheme != "https" {
+    return url.to_string();
+  }
+
+  let (authority, path) = match rest.find('/') {
+    Some(index) => rest.split_at(index),
+    None => (rest, ""),
+  };
+  let (userinfo, host_port) = match authority.rsplit_once('@') {
+    Some((userinfo, host_port)) => (Some(userinfo), host_port),
+    None => (None, authority),
+  };
+  let (host, port) = match host_port.rsplit_once(':') {
+    Some((host, port)) if !host_port.starts_with('[') || host.ends_with(']') => (host, Some(port)),
+    _ => (host_port, None),
+  };
+
+  let mut display = format!("{scheme}://");
+  if let Some(userinfo) = userinfo {
+    display.push_str(userinfo);
+    display.push('@');
+  }
+  display.push_str(&to_unicode(host));
+  if let Some(port) = port {
+    display.push(':');
+    display.push_str(port);
+  }
+  display.push_str(&decode_non_ascii(path));
+  display
+}
+
+// only decodes escapes that form non-ASCII UTF-8, so reserved characters
+// like %2F or %3F keep their meaning
+fn decode_non_ascii(text: &str) -> String {
+  let bytes = text.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+
+  while i < bytes.len() {
+    if bytes[i] == b'%'
+      && i + 2 < bytes.len()
+      && let Ok(hex) = std::str::from_utf8(&bytes[i + 1..i + 3])
+      && let Ok(byte) = u8::from_str_radix(hex, 16)
+      && byte >= 0x80
+    {
+      decoded.push(byte);
+      i += 3;
+    } else {
+      decoded.push(bytes[i]);
+      i += 1;
+    }
+  }
+
+  String::from_utf8(decoded).unwrap_or_else(|_| text.to_string())
+}
+
+fn adapt(mut delta: u32, points: u32, first: bool) -> u32 {
+  delta /= if first { DAMP } else { 2 };
+  delta += delta / points;
+
+  let mut k = 0;
+  while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+    delta /= BASE - T_MIN;
+    k += BASE;
+  }
+
+  k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+  if k <= bias {
+    T_MIN
+  } else if k >= bias + T_MAX {
+    T_MAX
+  } else {
+    k - bias
+  }
+}
+
+fn encode_digit(digit: u32) -> char {
+  match digit {
+    0..=25 => (b'a' + digit as u8) as char,
+    _ => (b'0' + (digit - 26) as u8) as char,
+  }
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+  match c {
+    'a'..='z' => Some(c as u32 - 'a' as u32),
+    'A'..='Z' => Some(c as u32 - 'A' as u32),
+    '0'..='9' => Some(c as u32 - '0' as u32 + 26),
+    _ => None,
+  }
+}
+
+fn punycode_encode(input: &str) -> Option<String> {
+  let code_points: Vec<u32> = input.chars().map(|c| c as u32).collect();
+  let mut output: String = input.chars().filter(char::is_ascii).collect();
+
+  let basic = output.len() as u32;
+  let mut handled = basic;
+  if basic > 0 {
+    output.push('-');
+  }
+
+  let mut n = INITIAL_N;
+  let mut delta: u32 = 0;
+  let mut bias = INITIAL_BIAS;
+
+  while (handled as usize) < code_points.len() {
+    let next = *code_points.iter().filter(|&&c| c >= n).min()?;
+    delta = delta.checked_add((next - n).checked_mul(handled + 1)?)?;
+    n = next;
+
+    for &c in &code_points {
+      if c < n {
+        delta = delta.checked_add(1)?;
+      }
+
+      if c == n {
+        let mut q = delta;
+        let mut k = BASE;
+        loop {
+          let t = threshold(k, bias);
+          if q < t {
+            break;
+          }
+          output.push(encode_digit(t + (q - t) % (BASE - t)));
+          q = (q - t) / (BASE - t);
+          k += BASE;
+        }
+        output.push(encode_digit(q));
+
+        bias = adapt(delta, handled + 1, handled == basic);
+        delta = 0;
+        handled += 1;
+      }
+    }
+
+    delta += 1;
+    n += 1;
+  }
+
+  Some(output)
+}
+
+fn punycode_decode(input: &str) -> Option<String> {
+  let (basic, encoded) = match input.rfind('-') {
+    Some(index) => (&input[..index], &input[index + 1..]),
+    None => ("", input),
+  };
+  if !basic.is_ascii() {
+    return None;
+  }
+
+  let mut output: Vec<char> = basic.chars().collect();
+  let mut n = INITIAL_N;
+  let mut i: u32 = 0;
+  let mut bias = INITIAL_BIAS;
+  let mut digits = encoded.chars();
+
+  while !digits.as_str().is_empty() {
+    let old_i = i;
+    let mut weight = 1u32;
+    let mut k = BASE;
+
+    loop {
+      let digit = decode_digit(digits.next()?)?;
+      i = i.checked_add(digit.checked_mul(weight)?)?;
+
+      let t = threshold(k, bias);
+      if digit < t {
+        break;
+      }
+      weight = weight.checked_mul(BASE - t)?;
+      k += BASE;
+    }
+
+    let length = output.len() as u32 + 1;
+    bias = adapt(i - old_i, length, old_i == 0);
+    n = n.checked_add(i / length)?;
+    i %= length;
+
+    output.insert(i as usize, char::from_u32(n)?);
+    i += 1;
+  }
+
+  Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the sample strings of RFC 3492 section 7.1
+  const SAMPLES: [(&str, &str); 4] = [
+    (
+      "\u{644}\u{64a}\u{647}\u{645}\u{627}\u{628}\u{62a}\u{643}\u{644}\u{645}\u{648}\u{634}\u{639}\u{631}\u{628}\u{64a}\u{61f}",
+      "egbpdaj6bu4bxfgehfvwxn",
+    ),
+    (
+      "\u{4ed6}\u{4eec}\u{4e3a}\u{4ec0}\u{4e48}\u{4e0d}\u{8bf4}\u{4e2d}\u{6587}",
+      "ihqwcrb4cv8a8dqg056pqjye",
+    ),
+    (
+      "3\u{5e74}B\u{7d44}\u{91d1}\u{516b}\u{5148}\u{751f}",
+      "3B-ww4c5e180e575a65lsy2b",
+    ),
+    ("-> $1.00 <-", "-> $1.00 <--"),
+  ];
+
+  #[test]
+  fn punycode_samples() {
+    for (unicode, encoded) in SAMPLES {
+      assert_eq!(punycode_encode(unicode).as_deref(), Some(encoded));
+      assert_eq!(punycode_decode(encoded).as_deref(), Some(unicode));
+    }
+  }
+
+  #[test]
+  fn hosts_to_ascii_and_back() {
+    let cases = [
+      ("b\u{fc}cher.de", "xn--bcher-kva.de"),
+      ("M\u{fc}NCHEN.DE", "xn--mnchen-3ya.de"),
+      (
+        "\u{4f8b}\u{3048}.\u{30c6}\u{30b9}\u{30c8}",
+        "xn--r8jz45g.xn--zckzah",
+      ),
+      // an ideographic full stop separates labels too
+      (
+        "\u{4f8b}\u{3048}\u{3002}\u{30c6}\u{30b9}\u{30c8}",
+        "xn--r8jz45g.xn--zckzah",
+      ),
+      ("Example.COM", "example.com"),
+      ("xn--bcher-kva.de", "xn--bcher-kva.de"),
+      ("[::1]", "[::1]"),
+    ];
+    for (host, ascii) in cases {
+      assert_eq!(to_ascii(host).as_deref(), Ok(ascii), "{host}");
+    }
+
+    assert_eq!(to_unicode("xn--bcher-kva.de"), "b\u{fc}cher.de");
+    assert_eq!(
+      to_unicode("xn--r8jz45g.xn--zckzah"),
+      "\u{4f8b}\u{3048}.\u{30c6}\u{30b9}\u{30c8}"
+    );
+    assert_eq!(to_unicode("example.com"), "example.com");
+    // a label that isn't valid punycode is shown as it is
+    assert_eq!(to_unicode("xn--.com"), "xn--.com");
+    assert_eq!(to_unicode("xn--abc-.com"), "xn--abc-.com");
+    assert_eq!(to_unicode("xn--a!.com"), "xn--a!.com");
+
+    for host in ["a b.com", "a/b.com", "a%41.com", "a\u{0}.com"] {
+      assert!(to_ascii(host).is_err(), "{host:?}");
+    }
+  }
+
+  #[test]
+  fn paths_are_percent_encoded_once() {
+    let cases = [
+      ("/", "/"),
+      ("/\u{fc}ber path", "/%C3%BCber%20path"),
+      ("/a?q=\u{e4}\"'", "/a?q=%C3%A4%22%27"),
+      ("/a'b", "/a'b"),
+      ("/<x>`{}", "/%3Cx%3E%60%7B%7D"),
+      // already encoded paths stay as they are
+      ("/a%20b", "/a%20b"),
+      ("/%C3%BCber?x=%2F", "/%C3%BCber?x=%2F"),
+      ("/a?b?c", "/a?b?c"),
+    ];
+    for (path, encoded) in cases {
+      assert_eq!(encode_path(path), encoded, "{path:?}");
+      assert_eq!(encode_path(encoded), encoded, "{path:?}");
+    }
+  }
+
+  #[test]
+  fn urls_are_displayed_in_unicode() {
+    let cases = [
+      (
+        "https://xn--bcher-kva.de/%C3%BCber?x=%2F#%C3%A4",
+        "https://b\u{fc}cher.de/\u{fc}ber?x=%2F#\u{e4}",
+      ),
+      (
+        "http://user@xn--mnchen-3ya.de:8080/a%20b",
+        "http://user@m\u{fc}nchen.de:8080/a%20b",
+      ),
+      ("https://[::1]:443/x", "https://[::1]:443/x"),
+      ("https://example.com", "https://example.com"),
+      // a broken escape or a lone continuation byte is left encoded
+      ("https://a.com/%C3", "https://a.com/%C3"),
+      ("https://a.com/%FF", "https://a.com/%FF"),
+      ("about:blank", "about:blank"),
+      ("file:///tmp/%C3%BC", "file:///tmp/%C3%BC"),
+    ];
+    for (url, display) in cases {
+      assert_eq!(display_url(url), display, "{url}");
+    }
+  }
+}
@@ -1,3 +1,12 @@
+pub mod activity;
+pub mod auth;
+mod chunked;
+pub mod config;
+pub mod download;
+mod hash;
+pub mod headers;
+pub mod hsts;
+pub mod idna;
 pub mod url_handler;
 
-pub use url_handler::URLHandler;
+pub use url_handler::{Body, BodyReader, DownloadStarted, Offline, URLHandler};
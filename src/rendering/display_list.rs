@@ -1,8 +1,9 @@
 use iced::font::Font;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct DisplayList {
   items: Vec<DisplayItem>,
+  links: Vec<LinkArea>,
 }
 
 #[derive(Debug, Clone)]
@@ -14,9 +15,22 @@ pub struct DisplayItem {
   pub size: f32,
 }
 
+// the box a word of a link covers, in page coordinates
+#[derive(Debug, Clone)]
+pub struct LinkArea {
+  pub x: f32,
+  pub y: f32,
+  pub width: f32,
+  pub height: f32,
+  pub href: String,
+}
+
 impl DisplayList {
   pub fn new() -> Self {
-    Self { items: Vec::new() }
+    Self {
+      items: Vec::new(),
+      links: Vec::new(),
+    }
   }
 
   pub fn add_item(&mut self, x: f32, y: f32, word: String, font: Font, size: f32) {
@@ -29,7 +43,28 @@ impl DisplayList {
     });
   }
 
+  pub fn add_link(&mut self, x: f32, y: f32, width: f32, height: f32, href: String) {
+    self.links.push(LinkArea {
+      x,
+      y,
+      width,
+      height,
+      href,
+    });
+  }
+
   pub fn items(&self) -> &[DisplayItem] {
     &self.items
   }
+
+  // the target of the link under a point of the page
+  pub fn link_at(&self, x: f32, y: f32) -> Option<&str> {
+    self
+      .links
+      .iter()
+      .find(|area| {
+        x >= area.x && x < area.x + area.width && y >= area.y && y < area.y + area.height
+      })
+      .map(|area| area.href.as_str())
+  }
 }
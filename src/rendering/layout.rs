@@ -1,5 +1,5 @@
 use crate::rendering::DisplayList;
-use crate::utils::Node;
+use crate::utils::{DocumentMode, Dom, Edge, HTML_NAMESPACE, Node, NodeId};
 
 use iced::advanced::graphics::text::Paragraph as GraphicsParagraph;
 use iced::advanced::text::Paragraph;
@@ -10,9 +10,6 @@ use iced::widget::text::Wrapping;
 use iced::widget::text::{LineHeight, Shaping};
 use iced::{Pixels, Size};
 
-use std::cell::RefCell;
-use std::rc::Rc;
-
 use std::collections::HashMap;
 
 const HSTEP: f32 = 9.0;
@@ -26,10 +23,12 @@ struct FontKey {
 
 struct LineItem {
   x: f32,
+  width: f32,
   word: String,
   font: Font,
   size: f32,
   is_superscript: bool,
+  link: Option<String>,
 }
 
 pub struct Layout {
@@ -42,14 +41,25 @@ pub struct Layout {
   style: Style,
   size: f32,
   font_cache: HashMap<FontKey, Font>,
+  // shaping is the slow part of layout and pages repeat their words, so
+  // widths are measured once per font and size
+  widths: HashMap<(FontKey, u32), HashMap<String, f32>>,
   is_center: bool,
   is_superscript: bool,
   is_preformatted: bool,
   needs_space: bool,
+  // the href of the <a> being laid out
+  link: Option<String>,
+  // fragment targets: element id or <a name> -> y of the line it starts on
+  pub anchors: HashMap<String, f32>,
+  names: HashMap<String, f32>,
+  pub mode: DocumentMode,
+  // the document generation this layout was made from
+  pub generation: u64,
 }
 
 impl Layout {
-  pub fn new(tree: &Rc<RefCell<Node>>, width: f32) -> Self {
+  pub fn new(dom: &Dom, width: f32) -> Self {
     let mut layout = Self {
       display_list: DisplayList::new(),
       width,
@@ -60,34 +70,66 @@ impl Layout {
       style: Style::Normal,
       size: 16.0,
       font_cache: HashMap::new(),
+      widths: HashMap::new(),
       is_center: false,
       is_superscript: false,
       is_preformatted: false,
       needs_space: false,
+      link: None,
+      anchors: HashMap::new(),
+      names: HashMap::new(),
+      mode: dom.mode(),
+      generation: dom.generation(),
     };
 
-    layout.recurse(tree);
+    layout.walk(dom, dom.document());
     layout.flush();
+
+    // an id anywhere in the document beats an <a name>
+    for (name, y) in std::mem::take(&mut layout.names) {
+      layout.anchors.entry(name).or_insert(y);
+    }
     layout
   }
 
-  pub fn recurse(&mut self, node_rc: &Rc<RefCell<Node>>) {
-    let node = node_rc.borrow();
+  pub fn walk(&mut self, dom: &Dom, root: NodeId) {
+    let mut traverse = dom.traverse(root);
+    while let Some(edge) = traverse.next() {
+      match edge {
+        Edge::Open(node) => {
+          if !self.open(&dom[node]) {
+            traverse.skip_children();
+          }
+        }
+        Edge::Close(node) => {
+          if let Node::Element(element) = &dom[node]
+            && element.namespace == HTML_NAMESPACE
+          {
+            self.close_tag(&element.tag);
+          }
+        }
+      }
+    }
+  }
 
-    match &*node {
+  // false when the children of the node shouldn't be laid out
+  fn open(&mut self, node: &Node) -> bool {
+    match node {
+      Node::Document(_) | Node::Doctype(_) | Node::Comment(_) | Node::ProcessingInstruction(_) => {}
       Node::Text(text) => {
-        let decoded = decode_entities(&text.text);
+        let content = &text.text;
         if self.is_preformatted {
-          for line in decoded.split('\n') {
+          for line in content.split('\n') {
             for word in line.split(' ') {
               self.word(word.to_string());
             }
             self.flush();
           }
         } else {
-          let words: Vec<&str> = decoded.split_whitespace().collect();
+          // only ASCII whitespace separates words, &nbsp; keeps them together
+          let words: Vec<&str> = content.split_ascii_whitespace().collect();
           for (i, word) in words.iter().enumerate() {
-            if i == 0 && !decoded.starts_with(|c: char| c.is_whitespace()) {
+            if i == 0 && !content.starts_with(|c: char| c.is_ascii_whitespace()) {
               self.needs_space = false;
             }
             self.word(word.to_string());
@@ -95,19 +137,32 @@ impl Layout {
         }
       }
       Node::Element(element) => {
-        if element.tag == "script" {
-          return;
+        if element.tag == "script" || element.tag == "style" {
+          return false;
         }
 
-        self.open_tag(&element.tag);
-
-        for child in &element.children {
-          self.recurse(&Rc::clone(child));
+        // only HTML elements carry presentational meaning, an XML <b> doesn't
+        if element.namespace == HTML_NAMESPACE {
+          self.open_tag(&element.tag);
         }
 
-        self.close_tag(&element.tag);
+        if let Some(id) = element.attribute("id") {
+          self.anchors.entry(id.to_string()).or_insert(self.cursor_y);
+        }
+        if element.tag == "a"
+          && let Some(name) = element.attribute("name")
+        {
+          self.names.entry(name.to_string()).or_insert(self.cursor_y);
+        }
+        if element.tag == "a"
+          && element.namespace == HTML_NAMESPACE
+          && let Some(href) = element.attribute("href")
+        {
+          self.link = Some(href.to_string());
+        }
       }
     }
+    true
   }
 
   pub fn flush(&mut self) {
@@ -134,7 +189,16 @@ impl Layout {
 
       self
         .display_list
-        .add_item(item.x + offset, y, item.word.clone(), item.font, item.size)
+        .add_item(item.x + offset, y, item.word.clone(), item.font, item.size);
+      if let Some(href) = &item.link {
+        self.display_list.add_link(
+          item.x + offset,
+          y,
+          item.width,
+          item.size * 1.3,
+          href.clone(),
+        );
+      }
     }
 
     self.cursor_y = baseline + 1.25 * max_ascent;
@@ -143,67 +207,81 @@ impl Layout {
     self.line.clear();
   }
 
-  pub fn word(&mut self, word: String) {
+  fn measure(&mut self, text: &str) -> f32 {
+    let key = FontKey {
+      weight: self.weight,
+      style: self.style,
+    };
     let font = self.get_font(self.weight, self.style);
+    let size = self.size;
 
-    let make_paragraph = |content: &str| {
-      GraphicsParagraph::with_text(AdvancedText {
-        content,
-        bounds: Size::INFINITY,
-        size: Pixels(self.size),
-        line_height: LineHeight::default(),
-        font,
-        horizontal_alignment: alignment::Horizontal::Left,
-        vertical_alignment: alignment::Vertical::Top,
-        shaping: Shaping::Basic,
-        wrapping: Wrapping::None,
-      })
-    };
+    let widths = self.widths.entry((key, size.to_bits())).or_default();
+    if let Some(width) = widths.get(text) {
+      return *width;
+    }
+
+    let width = GraphicsParagraph::with_text(AdvancedText {
+      content: text,
+      bounds: Size::INFINITY,
+      size: Pixels(size),
+      line_height: LineHeight::default(),
+      font,
+      horizontal_alignment: alignment::Horizontal::Left,
+      vertical_alignment: alignment::Vertical::Top,
+      shaping: Shaping::Basic,
+      wrapping: Wrapping::None,
+    })
+    .min_bounds()
+    .width;
+    widths.insert(text.to_string(), width);
+    width
+  }
 
-    let word_size = make_paragraph(&word).min_bounds();
-    let space_size = make_paragraph(" ").min_bounds();
+  pub fn word(&mut self, word: String) {
+    let font = self.get_font(self.weight, self.style);
+    let space_width = self.measure(" ");
 
     if word.is_empty() {
-      self.cursor_x += space_size.width;
+      self.cursor_x += space_width;
       return;
     }
 
-    let space_advance = if self.needs_space {
-      space_size.width
-    } else {
-      0.0
-    };
+    let word_width = self.measure(&word);
+    let space_advance = if self.needs_space { space_width } else { 0.0 };
 
-    if !self.is_preformatted && self.cursor_x + space_advance + word_size.width > self.width - HSTEP
-    {
+    if !self.is_preformatted && self.cursor_x + space_advance + word_width > self.width - HSTEP {
       self.flush();
 
       self.line.push(LineItem {
         x: if self.is_superscript {
-          self.cursor_x - space_size.width
+          self.cursor_x - space_width
         } else {
           self.cursor_x
         },
+        width: word_width,
         word,
         font,
         size: self.size,
         is_superscript: self.is_superscript,
+        link: self.link.clone(),
       });
 
-      self.cursor_x += word_size.width;
+      self.cursor_x += word_width;
     } else {
       self.line.push(LineItem {
         x: if self.is_superscript {
-          self.cursor_x + space_advance - space_size.width
+          self.cursor_x + space_advance - space_width
         } else {
           self.cursor_x + space_advance
         },
+        width: word_width,
         word,
         font,
         size: self.size,
         is_superscript: self.is_superscript,
+        link: self.link.clone(),
       });
-      self.cursor_x += space_advance + word_size.width;
+      self.cursor_x += space_advance + word_width;
     }
 
     self.needs_space = true;
@@ -250,6 +328,7 @@ impl Layout {
 
   pub fn close_tag(&mut self, tag: &str) {
     match tag {
+      "a" => self.link = None,
       "i" => self.style = Style::Normal,
       "b" => self.weight = Weight::Normal,
       "small" => self.size += 4.0,
@@ -274,74 +353,3 @@ impl Layout {
     }
   }
 }
-
-fn decode_entities(text: &str) -> String {
-  let mut result = String::with_capacity(text.len());
-  let mut chars = text.chars().peekable();
-
-  while let Some(c) = chars.next() {
-    if c != '&' {
-      result.push(c);
-      continue;
-    }
-
-    let mut entity = String::new();
-    let mut terminated = false;
-
-    for nc in chars.by_ref() {
-      if nc == ';' {
-        terminated = true;
-        break;
-      } else if nc.is_whitespace() {
-        entity.push(nc);
-        break;
-      } else {
-        entity.push(nc);
-      }
-    }
-
-    if terminated {
-      let replacement = match entity.as_str() {
-        "lt" => Some("<"),
-        "gt" => Some(">"),
-        "amp" => Some("&"),
-        "quot" => Some("\""),
-        "apos" => Some("'"),
-        "copy" => Some("©"),
-        _ => None,
-      };
-
-      if let Some(r) = replacement {
-        result.push_str(r);
-      } else if entity.starts_with('#') {
-        let code = if entity.starts_with("#x") || entity.starts_with("#X") {
-          u32::from_str_radix(&entity[2..], 16).ok()
-        } else {
-          entity[1..].parse::<u32>().ok()
-        };
-        if let Some(n) = code {
-          if let Some(ch) = char::from_u32(n) {
-            result.push(ch);
-          } else {
-            result.push('&');
-            result.push_str(&entity);
-            result.push(';');
-          }
-        } else {
-          result.push('&');
-          result.push_str(&entity);
-          result.push(';');
-        }
-      } else {
-        result.push('&');
-        result.push_str(&entity);
-        result.push(';');
-      }
-    } else {
-      result.push('&');
-      result.push_str(&entity);
-    }
-  }
-
-  result
-}
@@ -1,10 +1,25 @@
 mod display_list;
+mod entities;
 mod layout;
 mod parser;
+mod plain_text;
+mod query;
+mod quirks;
+mod serializer;
 mod syntax_highlight;
+mod tokenizer;
+mod xml_parser;
 
 pub use display_list::DisplayList;
 pub use layout::Layout;
 pub use parser::HTMLParser;
 pub use parser::print_tree;
+pub use plain_text::{PlainTextLayout, TextOptions};
+pub use query::{
+  Selector, SelectorError, get_element_by_id, get_elements_by_class_name, get_elements_by_tag_name,
+  query_selector, query_selector_all,
+};
+pub use serializer::{inner_html, outer_html, pretty_html};
 pub use syntax_highlight::syntax_highlight;
+pub use tokenizer::ParseError;
+pub use xml_parser::{XMLError, XMLParser, is_xml};
@@ -1,290 +1,2061 @@
-use crate::utils::{Element, Node, Text};
+use crate::rendering::quirks;
+use crate::rendering::tokenizer::{self, ParseError, State, Token, Tokenizer};
+use crate::utils::{
+  Comment, Doctype, Dom, Edge, Element, HTML_NAMESPACE, MATHML_NAMESPACE, Node, NodeId,
+  SVG_NAMESPACE, Span, Text,
+};
 
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::Rc;
+use std::mem;
 
 const SELF_CLOSING_TAGS: [&str; 14] = [
   "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
   "track", "wbr",
 ];
 
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+// start tags that close an open <p>
+const CLOSES_P: [&str; 36] = [
+  "address",
+  "article",
+  "aside",
+  "blockquote",
+  "center",
+  "details",
+  "dialog",
+  "dir",
+  "div",
+  "dl",
+  "fieldset",
+  "figcaption",
+  "figure",
+  "footer",
+  "form",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "header",
+  "hgroup",
+  "hr",
+  "listing",
+  "main",
+  "menu",
+  "nav",
+  "ol",
+  "p",
+  "pre",
+  "search",
+  "section",
+  "summary",
+  "table",
+  "ul",
+];
+
+// end tags that close everything opened inside the element
+const BLOCK_TAGS: [&str; 29] = [
+  "address",
+  "article",
+  "aside",
+  "blockquote",
+  "button",
+  "center",
+  "details",
+  "dialog",
+  "dir",
+  "div",
+  "dl",
+  "fieldset",
+  "figcaption",
+  "figure",
+  "footer",
+  "form",
+  "header",
+  "hgroup",
+  "listing",
+  "main",
+  "menu",
+  "nav",
+  "ol",
+  "pre",
+  "search",
+  "section",
+  "select",
+  "summary",
+  "ul",
+];
+
+const IMPLIED_END_TAGS: [&str; 10] = [
+  "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+// https://html.spec.whatwg.org/multipage/parsing.html#special
+const SPECIAL_TAGS: [&str; 83] = [
+  "address",
+  "applet",
+  "area",
+  "article",
+  "aside",
+  "base",
+  "basefont",
+  "bgsound",
+  "blockquote",
+  "body",
+  "br",
+  "button",
+  "caption",
+  "center",
+  "col",
+  "colgroup",
+  "dd",
+  "details",
+  "dir",
+  "div",
+  "dl",
+  "dt",
+  "embed",
+  "fieldset",
+  "figcaption",
+  "figure",
+  "footer",
+  "form",
+  "frame",
+  "frameset",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "head",
+  "header",
+  "hgroup",
+  "hr",
+  "html",
+  "iframe",
+  "img",
+  "input",
+  "keygen",
+  "li",
+  "link",
+  "listing",
+  "main",
+  "marquee",
+  "menu",
+  "meta",
+  "nav",
+  "noembed",
+  "noframes",
+  "noscript",
+  "object",
+  "ol",
+  "p",
+  "param",
+  "plaintext",
+  "pre",
+  "script",
+  "search",
+  "section",
+  "select",
+  "source",
+  "style",
+  "summary",
+  "table",
+  "tbody",
+  "td",
+  "template",
+  "textarea",
+  "tfoot",
+  "th",
+  "thead",
+  "title",
+  "tr",
+  "track",
+  "ul",
+  "wbr",
+  "xmp",
+];
+
+const DEFAULT_SCOPE: &[&str] = &[
+  "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
+const BUTTON_SCOPE: &[&str] = &[
+  "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template", "button",
+];
+const LIST_ITEM_SCOPE: &[&str] = &[
+  "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template", "ol", "ul",
+];
+const TABLE_SCOPE: &[&str] = &["html", "table", "template"];
+
+const TABLE_SECTIONS: [&str; 3] = ["tbody", "thead", "tfoot"];
+
+// elements whose children may only be table parts, anything else inserted
+// into them is foster parented
+const TABLE_CONTEXT: [&str; 5] = ["table", "tbody", "tfoot", "thead", "tr"];
+
+// what a table may hold directly
+const TABLE_CONTENT: [&str; 14] = [
+  "caption", "col", "colgroup", "form", "script", "style", "table", "tbody", "td", "template",
+  "tfoot", "th", "thead", "tr",
+];
+
+const FORMATTING_TAGS: [&str; 14] = [
+  "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+// elements that put a marker on the list of active formatting elements, so
+// formatting from outside doesn't leak into them
+const MARKER_TAGS: [&str; 7] = [
+  "applet", "caption", "marquee", "object", "td", "template", "th",
+];
+
+// special elements that still reopen formatting before they are inserted
+const INLINE_SPECIAL_TAGS: [&str; 7] = ["area", "br", "embed", "img", "input", "keygen", "wbr"];
+
+// elements that may be left open at the end of the document without it
+// being an error
+const OPTIONAL_END_TAGS: [&str; 18] = [
+  "body", "dd", "dt", "html", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody",
+  "td", "tfoot", "th", "thead", "tr",
+];
+
 const HEAD_TAGS: [&str; 9] = [
   "base", "basefont", "bgsound", "noscript", "link", "meta", "title", "style", "script",
 ];
 
+// https://html.spec.whatwg.org/multipage/parsing.html#parsing-main-inforeign
+
+// html start tags that close the svg or mathml they appear in
+const BREAKOUT_TAGS: [&str; 44] = [
+  "b",
+  "big",
+  "blockquote",
+  "body",
+  "br",
+  "center",
+  "code",
+  "dd",
+  "div",
+  "dl",
+  "dt",
+  "em",
+  "embed",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "head",
+  "hr",
+  "i",
+  "img",
+  "li",
+  "listing",
+  "menu",
+  "meta",
+  "nobr",
+  "ol",
+  "p",
+  "pre",
+  "ruby",
+  "s",
+  "small",
+  "span",
+  "strong",
+  "strike",
+  "sub",
+  "sup",
+  "table",
+  "tt",
+  "u",
+  "ul",
+  "var",
+];
+
+// foreign elements that hold html-like content; they are special and end
+// every scope except table scope
+const MATHML_BOUNDARY_TAGS: [&str; 6] = ["mi", "mo", "mn", "ms", "mtext", "annotation-xml"];
+const SVG_BOUNDARY_TAGS: [&str; 3] = ["foreignObject", "desc", "title"];
+
+// the tokenizer lowercases names, svg wants some of them in camel case
+const SVG_TAG_NAMES: [(&str, &str); 37] = [
+  ("altglyph", "altGlyph"),
+  ("altglyphdef", "altGlyphDef"),
+  ("altglyphitem", "altGlyphItem"),
+  ("animatecolor", "animateColor"),
+  ("animatemotion", "animateMotion"),
+  ("animatetransform", "animateTransform"),
+  ("clippath", "clipPath"),
+  ("feblend", "feBlend"),
+  ("fecolormatrix", "feColorMatrix"),
+  ("fecomponenttransfer", "feComponentTransfer"),
+  ("fecomposite", "feComposite"),
+  ("feconvolvematrix", "feConvolveMatrix"),
+  ("fediffuselighting", "feDiffuseLighting"),
+  ("fedisplacementmap", "feDisplacementMap"),
+  ("fedistantlight", "feDistantLight"),
+  ("fedropshadow", "feDropShadow"),
+  ("feflood", "feFlood"),
+  ("fefunca", "feFuncA"),
+  ("fefuncb", "feFuncB"),
+  ("fefuncg", "feFuncG"),
+  ("fefuncr", "feFuncR"),
+  ("fegaussianblur", "feGaussianBlur"),
+  ("feimage", "feImage"),
+  ("femerge", "feMerge"),
+  ("femergenode", "feMergeNode"),
+  ("femorphology", "feMorphology"),
+  ("feoffset", "feOffset"),
+  ("fepointlight", "fePointLight"),
+  ("fespecularlighting", "feSpecularLighting"),
+  ("fespotlight", "feSpotLight"),
+  ("fetile", "feTile"),
+  ("feturbulence", "feTurbulence"),
+  ("foreignobject", "foreignObject"),
+  ("glyphref", "glyphRef"),
+  ("lineargradient", "linearGradient"),
+  ("radialgradient", "radialGradient"),
+  ("textpath", "textPath"),
+];
+
+const SVG_ATTRIBUTE_NAMES: [(&str, &str); 58] = [
+  ("attributename", "attributeName"),
+  ("attributetype", "attributeType"),
+  ("basefrequency", "baseFrequency"),
+  ("baseprofile", "baseProfile"),
+  ("calcmode", "calcMode"),
+  ("clippathunits", "clipPathUnits"),
+  ("diffuseconstant", "diffuseConstant"),
+  ("edgemode", "edgeMode"),
+  ("filterunits", "filterUnits"),
+  ("glyphref", "glyphRef"),
+  ("gradienttransform", "gradientTransform"),
+  ("gradientunits", "gradientUnits"),
+  ("kernelmatrix", "kernelMatrix"),
+  ("kernelunitlength", "kernelUnitLength"),
+  ("keypoints", "keyPoints"),
+  ("keysplines", "keySplines"),
+  ("keytimes", "keyTimes"),
+  ("lengthadjust", "lengthAdjust"),
+  ("limitingconeangle", "limitingConeAngle"),
+  ("markerheight", "markerHeight"),
+  ("markerunits", "markerUnits"),
+  ("markerwidth", "markerWidth"),
+  ("maskcontentunits", "maskContentUnits"),
+  ("maskunits", "maskUnits"),
+  ("numoctaves", "numOctaves"),
+  ("pathlength", "pathLength"),
+  ("patterncontentunits", "patternContentUnits"),
+  ("patterntransform", "patternTransform"),
+  ("patternunits", "patternUnits"),
+  ("pointsatx", "pointsAtX"),
+  ("pointsaty", "pointsAtY"),
+  ("pointsatz", "pointsAtZ"),
+  ("preservealpha", "preserveAlpha"),
+  ("preserveaspectratio", "preserveAspectRatio"),
+  ("primitiveunits", "primitiveUnits"),
+  ("refx", "refX"),
+  ("refy", "refY"),
+  ("repeatcount", "repeatCount"),
+  ("repeatdur", "repeatDur"),
+  ("requiredextensions", "requiredExtensions"),
+  ("requiredfeatures", "requiredFeatures"),
+  ("specularconstant", "specularConstant"),
+  ("specularexponent", "specularExponent"),
+  ("spreadmethod", "spreadMethod"),
+  ("startoffset", "startOffset"),
+  ("stddeviation", "stdDeviation"),
+  ("stitchtiles", "stitchTiles"),
+  ("surfacescale", "surfaceScale"),
+  ("systemlanguage", "systemLanguage"),
+  ("tablevalues", "tableValues"),
+  ("targetx", "targetX"),
+  ("targety", "targetY"),
+  ("textlength", "textLength"),
+  ("viewbox", "viewBox"),
+  ("viewtarget", "viewTarget"),
+  ("xchannelselector", "xChannelSelector"),
+  ("ychannelselector", "yChannelSelector"),
+  ("zoomandpan", "zoomAndPan"),
+];
+
 pub struct HTMLParser {
-  body: String,
-  unfinished: Vec<Rc<RefCell<Node>>>,
+  dom: Dom,
+  doctype: Option<tokenizer::Doctype>,
+  unfinished: Vec<NodeId>,
+  // reused by build() for a copy of the stack, so tokens don't allocate one
+  open: Vec<NodeId>,
   head_closed: bool,
+  // a </body> or </html> was the last thing seen, comments after them go
+  // to the root or the document
+  after_body: bool,
+  after_html: bool,
+  tokenizer: Tokenizer,
+  // the start of a utf-8 sequence split across chunks
+  pending: Vec<u8>,
+  // the list of active formatting elements; None is a scope marker
+  formatting: Vec<Option<NodeId>>,
+  scripting: bool,
+  skip_newline: bool,
+  // whether the start tag being processed ended in "/>"
+  self_closing: bool,
+  // the source span of the token being processed
+  span: Span,
+  errors: Vec<ParseError>,
+}
+
+impl Default for HTMLParser {
+  fn default() -> Self {
+    Self::streaming()
+  }
 }
 
 impl HTMLParser {
   pub fn new(body: String) -> Self {
+    let mut parser = Self::streaming();
+    parser.tokenizer.feed(&body);
+    parser
+  }
+
+  // a parser without input yet, fed chunk by chunk as the page arrives
+  pub fn streaming() -> Self {
     HTMLParser {
-      body,
+      dom: Dom::new(),
+      doctype: None,
       unfinished: vec![],
+      open: vec![],
       head_closed: false,
+      after_body: false,
+      after_html: false,
+      tokenizer: Tokenizer::new(),
+      pending: vec![],
+      formatting: vec![],
+      scripting: false,
+      skip_newline: false,
+      self_closing: false,
+      span: Span::default(),
+      errors: vec![],
     }
   }
 
-  pub fn parse(&mut self) -> Rc<RefCell<Node>> {
-    let body = self.body.clone();
-    let mut text = String::new();
-    let mut in_tag = false;
-    let mut in_comment = false;
-    let mut in_script = false;
+  // parses whatever of the input is left and hands over the finished tree
+  pub fn parse(&mut self) -> Dom {
+    self.end();
+    self.finish()
+  }
 
-    let chars: Vec<char> = body.chars().collect();
-    let mut i = 0;
+  // builds as much of the tree as the bytes received so far allow; invalid
+  // utf-8 becomes U+FFFD like in String::from_utf8_lossy
+  pub fn feed(&mut self, bytes: &[u8]) {
+    self.pending.extend_from_slice(bytes);
 
-    while i < chars.len() {
-      if in_comment {
-        if chars[i] == '-' && chars.get(i + 1) == Some(&'-') && chars.get(i + 2) == Some(&'>') {
-          in_comment = false;
-          i += 3;
-        } else {
-          i += 1;
+    let mut start = 0;
+    loop {
+      let (valid, invalid) = match std::str::from_utf8(&self.pending[start..]) {
+        Ok(_) => (self.pending.len(), None),
+        Err(error) => (start + error.valid_up_to(), error.error_len()),
+      };
+      let text = std::str::from_utf8(&self.pending[start..valid]).unwrap_or_default();
+      self.tokenizer.feed(text);
+
+      match invalid {
+        Some(length) => {
+          self.tokenizer.feed("\u{FFFD}");
+          start = valid + length;
         }
-      } else if in_script {
-        let close_tag = "</script>";
-        let remaining_len = chars.len() - i;
+        // all decoded, or the rest of a sequence is in the next chunk
+        None => {
+          start = valid;
+          break;
+        }
+      }
+    }
+    self.pending.drain(..start);
 
-        if remaining_len >= close_tag.len() {
-          let slice: String = chars[i..i + close_tag.len()].iter().collect();
+    self.build();
+  }
 
-          if slice.to_lowercase() == close_tag {
-            if !text.is_empty() {
-              self.add_text(text.clone());
-              text.clear();
-            }
-            self.add_tag("/script".to_string());
-            in_script = false;
-            i += close_tag.len();
-            continue;
-          }
-        }
+  // no more input is coming
+  pub fn end(&mut self) {
+    if !self.pending.is_empty() {
+      let rest = String::from_utf8_lossy(&self.pending).to_string();
+      self.tokenizer.feed(&rest);
+      self.pending.clear();
+    }
+    self.tokenizer.end();
+    self.build();
+  }
 
-        text.push(chars[i]);
-        i += 1;
-      } else if !in_tag && chars[i] == '<' {
-        if chars.get(i + 1) == Some(&'!')
-          && chars.get(i + 2) == Some(&'-')
-          && chars.get(i + 3) == Some(&'-')
-        {
-          if !text.is_empty() {
-            self.add_text(text.clone());
-            text.clear();
+  // the tree built so far; until the input ends, elements may still be open
+  // and get more children
+  pub fn dom(&self) -> &Dom {
+    &self.dom
+  }
+
+  // runs the tree builder over every token the tokenizer has ready
+  fn build(&mut self) {
+    while let Some((token, span)) = self.tokenizer.next_token() {
+      self.errors.append(&mut self.tokenizer.take_errors());
+      self.span = span;
+
+      let foreign = self.foreign_content(&token);
+
+      let missing_doctype = match &token {
+        Token::Text(text) => !text.trim().is_empty(),
+        Token::Doctype(_) | Token::Comment(_) => false,
+        _ => true,
+      };
+      if missing_doctype && self.unfinished.is_empty() && self.doctype.is_none() {
+        self.error("missing-doctype");
+      }
+
+      // the stack before this token, to find the elements it closed
+      let mut open = mem::take(&mut self.open);
+      open.clone_from(&self.unfinished);
+      let end_tag = match &token {
+        Token::EndTag { name } => Some(name.clone()),
+        _ => None,
+      };
+
+      // anything but a comment or whitespace after </body> puts it back in
+      // the body, like the "after body" insertion modes do
+      match &token {
+        Token::Comment(_) | Token::Eof => (),
+        Token::Text(text) if text.trim().is_empty() => (),
+        _ => (self.after_body, self.after_html) = (false, false),
+      }
+
+      match token {
+        Token::Text(mut text) => {
+          // a newline right after <pre> or <textarea> is not content
+          if self.skip_newline && text.starts_with('\n') {
+            text.remove(0);
+            self.span.start.line += 1;
+            self.span.start.column = 1;
           }
-          in_comment = true;
-          i += 4;
-        } else {
-          in_tag = true;
-          if !text.is_empty() {
-            self.add_text(text.clone());
+          self.skip_newline = false;
+          if foreign {
+            self.insert_text(text);
+          } else {
+            self.add_text(text);
           }
-          text.clear();
-          i += 1;
         }
-      } else if in_tag && chars[i] == '>' {
-        in_tag = false;
-        let tag_content = text.clone();
-        text.clear();
+        Token::StartTag {
+          name,
+          attributes,
+          self_closing,
+        } => {
+          self.self_closing = self_closing;
+          self.skip_newline = false;
 
-        self.add_tag(tag_content.clone());
+          if foreign && !breaks_out(&name, &attributes) {
+            self.foreign_start_tag(name, attributes);
+          } else {
+            if foreign {
+              self.error("unexpected-start-tag");
+              self.leave_foreign_content();
+            }
+
+            if let Some(state) = self.text_state(&name) {
+              self.tokenizer.set_state(state);
+            }
+            self.skip_newline = ["pre", "listing", "textarea"].contains(&name.as_str());
 
-        let trimmed = tag_content.trim().to_lowercase();
-        if trimmed == "script" || trimmed.starts_with("script ") {
-          in_script = true;
+            self.add_tag(name, attributes);
+          }
         }
+        Token::EndTag { name } => {
+          self.skip_newline = false;
 
-        i += 1;
-      } else {
-        text.push(chars[i]);
-        i += 1;
+          let html = if !foreign {
+            true
+          } else if name == "br" || name == "p" {
+            self.error("unexpected-end-tag");
+            self.leave_foreign_content();
+            true
+          } else {
+            !self.foreign_end_tag(&name)
+          };
+          if html {
+            match name.as_str() {
+              "body" => self.after_body = true,
+              "html" => self.after_html = true,
+              _ => (),
+            }
+            self.add_tag(format!("/{name}"), Vec::new());
+          }
+        }
+        Token::Doctype(doctype) => {
+          self.skip_newline = false;
+          self.add_doctype(doctype);
+        }
+        Token::Comment(text) => {
+          self.skip_newline = false;
+          self.add_comment(text);
+        }
+        Token::Eof => break,
       }
+
+      self.close_spans(&open, end_tag.as_deref());
+      self.open = open;
+
+      let cdata_allowed = self.unfinished.last().is_some_and(
+        |&node| matches!(&self.dom[node], Node::Element(e) if e.namespace != HTML_NAMESPACE),
+      );
+      self.tokenizer.set_cdata_allowed(cdata_allowed);
     }
 
-    if !in_tag && !text.is_empty() {
-      self.add_text(text);
+    // the mode is known once the doctype had its chance, a partial tree is
+    // laid out with it too
+    if !self.unfinished.is_empty() {
+      self.set_mode();
     }
+  }
 
-    self.finish()
+  fn set_mode(&mut self) {
+    let document = self.dom.document();
+    if let Node::Document(document) = &mut self.dom[document] {
+      document.mode = quirks::document_mode(self.doctype.as_ref());
+    }
   }
 
-  fn add_text(&mut self, text: String) {
-    if text.trim().is_empty() {
+  pub fn errors(&self) -> &[ParseError] {
+    &self.errors
+  }
+
+  fn error(&mut self, code: &'static str) {
+    self.errors.push(ParseError {
+      code,
+      position: self.span.start,
+    });
+  }
+
+  // elements that left the stack while handling a token end where their own
+  // end tag ends, or where the token that closed them implicitly starts
+  fn close_spans(&mut self, open: &[NodeId], end_tag: Option<&str>) {
+    let common = open
+      .iter()
+      .zip(&self.unfinished)
+      .take_while(|(a, b)| a == b)
+      .count();
+
+    for &node in &open[common..] {
+      if self.unfinished[common..].contains(&node) {
+        continue;
+      }
+
+      if let Node::Element(element) = &mut self.dom[node] {
+        element.span.end = if end_tag.is_some_and(|tag| element.tag.eq_ignore_ascii_case(tag)) {
+          self.span.end
+        } else {
+          self.span.start
+        };
+      }
+    }
+  }
+
+  // the tokenizer state the contents of an element are read in
+  fn text_state(&self, tag: &str) -> Option<State> {
+    match tag {
+      "title" | "textarea" => Some(State::RcData),
+      "style" | "xmp" | "iframe" | "noembed" | "noframes" => Some(State::RawText),
+      // without scripting <noscript> holds markup that should be shown
+      "noscript" if self.scripting => Some(State::RawText),
+      "script" => Some(State::ScriptData),
+      "plaintext" => Some(State::PlainText),
+      _ => None,
+    }
+  }
+
+  // only the first doctype before any content counts
+  fn add_doctype(&mut self, doctype: tokenizer::Doctype) {
+    if self.doctype.is_some() || !self.unfinished.is_empty() {
+      self.error("unexpected-doctype");
       return;
     }
 
-    self.implicit_tags(None);
+    let legacy_compat = doctype
+      .system_id
+      .as_deref()
+      .is_none_or(|id| id == "about:legacy-compat");
+    if doctype.name.as_deref() != Some("html") || doctype.public_id.is_some() || !legacy_compat {
+      self.error("non-conforming-doctype");
+    }
 
-    let parent_rc = self.unfinished.last().unwrap().clone();
-    let parent_weak = Rc::downgrade(&parent_rc);
+    let node = self.dom.create(Node::Doctype(Doctype {
+      name: doctype.name.clone().unwrap_or_default(),
+      public_id: doctype.public_id.clone().unwrap_or_default(),
+      system_id: doctype.system_id.clone().unwrap_or_default(),
+      span: self.span,
+    }));
+    let document = self.dom.document();
+    self.dom.append(document, node);
+    self.doctype = Some(doctype);
+  }
 
-    let node = Rc::new(RefCell::new(Node::Text(Text {
+  // https://html.spec.whatwg.org/multipage/parsing.html#insert-a-comment
+  fn add_comment(&mut self, text: String) {
+    let document = self.dom.document();
+    let parent = match self.unfinished.first() {
+      // before <html> and after </html> comments belong to the document
+      None => document,
+      Some(_) if self.after_html => document,
+      // after </body> they go in <html>, next to the body
+      Some(&root) if self.after_body => root,
+      Some(_) => *self.unfinished.last().unwrap(),
+    };
+
+    let node = self.dom.create(Node::Comment(Comment {
       text,
-      parent: Some(parent_weak),
-      children: vec![],
-    })));
+      span: self.span,
+    }));
+    self.dom.append(parent, node);
+  }
 
-    parent_rc.borrow_mut().children_mut().push(node);
+  pub fn set_scripting(&mut self, enabled: bool) {
+    self.scripting = enabled;
   }
 
-  fn add_tag(&mut self, tag: String) {
-    let (tag, attributes) = self.get_attributes(&tag);
+  fn add_text(&mut self, text: String) {
+    if !text.trim().is_empty() {
+      self.reconstruct_formatting();
+    }
+    self.insert_text(text);
+  }
+
+  fn insert_text(&mut self, text: String) {
+    // whitespace stays in a table, other text is foster parented
+    let whitespace = text.trim().is_empty();
+    let (parent, before) = match self.unfinished.last() {
+      Some(&current) if !whitespace => self.insertion_place(current, None),
+      Some(&current) => (current, None),
+      None => (self.dom.document(), None),
+    };
 
-    if tag.starts_with('!') {
+    // text split across tokens, e.g. by a character reference, continues
+    // the previous text node
+    let previous = match before {
+      Some(before) => self.dom.previous_sibling(before),
+      None => self.dom.last_child(parent),
+    };
+    if let Some(previous) = previous
+      && let Node::Text(previous) = &mut self.dom[previous]
+    {
+      previous.text.push_str(&text);
+      previous.span.end = self.span.end;
       return;
     }
 
+    if whitespace {
+      return;
+    }
+
+    self.implicit_tags(None);
+
+    let (parent, before) = self.insertion_place(self.current_node(), None);
+    let node = self.dom.create(Node::Text(Text {
+      text,
+      span: self.span,
+    }));
+    self.dom.insert(parent, node, before);
+  }
+
+  fn add_tag(&mut self, tag: String, attributes: Vec<(String, String)>) {
     if tag == "/head" {
       self.head_closed = true;
 
       let body_open = self
         .unfinished
         .iter()
-        .any(|n| n.borrow().tag().map(|t| t == "body").unwrap_or(false));
+        .any(|&node| self.dom[node].tag() == Some("body"));
       if body_open {
         return;
       }
     }
 
-    self.implicit_tags(Some(&tag.clone()));
+    self.implicit_tags(Some(&tag));
+
+    match tag.strip_prefix('/') {
+      Some(name) => self.end_tag(name),
+      None => self.start_tag(tag, attributes),
+    }
+  }
+
+  fn start_tag(&mut self, tag: String, attributes: Vec<(String, String)>) {
+    if tag == "svg" || tag == "math" {
+      self.reconstruct_formatting();
+      let namespace = if tag == "svg" {
+        SVG_NAMESPACE
+      } else {
+        MATHML_NAMESPACE
+      };
+      self.insert_foreign_element(tag, namespace, attributes);
+      return;
+    }
+
+    match tag.as_str() {
+      "li" => self.close_list_item(&["li"]),
+      "dd" | "dt" => self.close_list_item(&["dd", "dt"]),
+      "option" | "optgroup" if self.current_tag() == Some("option") => {
+        self.unfinished.pop();
+      }
+      "td" | "th" => self.close_cell(),
+      "a" => {
+        // an <a> never nests, an open one is closed first
+        if let Some(open) = self.formatting_element("a") {
+          self.error("nested-anchor");
+          self.adoption_agency("a");
+          self.remove_formatting(open);
+          self.unfinished.retain(|&node| node != open);
+        }
+      }
+      "nobr" if self.in_scope("nobr", DEFAULT_SCOPE) => {
+        self.error("unexpected-start-tag");
+        self.adoption_agency("nobr");
+      }
+      "tr" => {
+        self.close_cell();
+        if self.in_scope("tr", TABLE_SCOPE) {
+          self.pop_until(&["tr"]);
+        }
+      }
+      "tbody" | "thead" | "tfoot" => {
+        self.close_cell();
+        if self.in_scope("tr", TABLE_SCOPE) {
+          self.pop_until(&["tr"]);
+        }
+        for section in TABLE_SECTIONS {
+          if self.in_scope(section, TABLE_SCOPE) {
+            self.pop_until(&[section]);
+          }
+        }
+      }
+      _ => (),
+    }
+
+    if CLOSES_P.contains(&tag.as_str()) {
+      self.close_p();
+    }
+
+    // headings don't nest, <h1>a<h2>b closes the h1
+    if HEADINGS.contains(&tag.as_str())
+      && self
+        .current_tag()
+        .is_some_and(|current| HEADINGS.contains(&current))
+    {
+      self.error("unexpected-start-tag");
+      self.unfinished.pop();
+    }
+
+    if !SPECIAL_TAGS.contains(&tag.as_str()) || INLINE_SPECIAL_TAGS.contains(&tag.as_str()) {
+      self.reconstruct_formatting();
+    }
+
+    let node = self.insert_element(tag.clone(), attributes, self.span);
+
+    // a form straight in a table holds nothing, what follows stays in the table
+    if tag == "form"
+      && self
+        .dom
+        .parent(node)
+        .and_then(|parent| html_tag(&self.dom[parent]))
+        .is_some_and(|parent| TABLE_CONTEXT.contains(&parent))
+    {
+      self.unfinished.pop();
+    }
+
+    if FORMATTING_TAGS.contains(&tag.as_str()) {
+      self.push_formatting(node);
+    } else if MARKER_TAGS.contains(&tag.as_str()) {
+      self.formatting.push(None);
+    }
+  }
+
+  fn insert_element(
+    &mut self,
+    tag: String,
+    attributes: Vec<(String, String)>,
+    span: Span,
+  ) -> NodeId {
+    let void = SELF_CLOSING_TAGS.contains(&tag.as_str());
+    let node = self.new_element(tag, HTML_NAMESPACE, attributes, span);
+    self.append(node);
+
+    if !void {
+      self.unfinished.push(node);
+    }
+    node
+  }
+
+  fn new_element(
+    &mut self,
+    tag: String,
+    namespace: &str,
+    attributes: Vec<(String, String)>,
+    span: Span,
+  ) -> NodeId {
+    self.dom.create(Node::Element(Element {
+      tag,
+      namespace: namespace.to_string(),
+      attributes,
+      span,
+    }))
+  }
+
+  // adds the node to the current element
+  fn append(&mut self, node: NodeId) {
+    let (parent, before) = self.insertion_place(self.current_node(), Some(node));
+    self.dom.insert(parent, node, before);
+  }
+
+  fn current_node(&self) -> NodeId {
+    self
+      .unfinished
+      .last()
+      .copied()
+      .unwrap_or(self.dom.document())
+  }
+
+  // where a node goes when it's added to `target`: content that can't be
+  // directly in a table is foster parented, it goes right before the table
+  // instead; `node` is None for text
+  // https://html.spec.whatwg.org/multipage/parsing.html#appropriate-place-for-inserting-a-node
+  fn insertion_place(&self, target: NodeId, node: Option<NodeId>) -> (NodeId, Option<NodeId>) {
+    let in_table = html_tag(&self.dom[target]).is_some_and(|tag| TABLE_CONTEXT.contains(&tag));
+    if !in_table || node.is_some_and(|node| is_table_content(&self.dom[node])) {
+      return (target, None);
+    }
+
+    let Some(index) = self
+      .unfinished
+      .iter()
+      .rposition(|&open| html_tag(&self.dom[open]) == Some("table"))
+    else {
+      return (target, None);
+    };
+    let table = self.unfinished[index];
+
+    match self.dom.parent(table) {
+      Some(parent) => (parent, Some(table)),
+      None => (self.unfinished[index.saturating_sub(1)], None),
+    }
+  }
+
+  // https://html.spec.whatwg.org/multipage/parsing.html#tree-construction-dispatcher
+  fn foreign_content(&self, token: &Token) -> bool {
+    let Some(&current) = self.unfinished.last() else {
+      return false;
+    };
+    let Node::Element(element) = &self.dom[current] else {
+      return false;
+    };
+    if element.namespace == HTML_NAMESPACE {
+      return false;
+    }
+
+    // integration points take html again, e.g. inside <foreignObject>
+    match token {
+      Token::StartTag { name, .. } => {
+        !(is_html_integration_point(element)
+          || is_mathml_text_integration_point(element) && name != "mglyph" && name != "malignmark"
+          || element.namespace == MATHML_NAMESPACE
+            && element.tag == "annotation-xml"
+            && name == "svg")
+      }
+      Token::Text(_) => {
+        !(is_html_integration_point(element) || is_mathml_text_integration_point(element))
+      }
+      Token::Eof => false,
+      _ => true,
+    }
+  }
+
+  // html like <p> inside <svg> closes the foreign elements around it
+  fn leave_foreign_content(&mut self) {
+    while let Some(&current) = self.unfinished.last() {
+      let done = match &self.dom[current] {
+        Node::Element(element) => {
+          element.namespace == HTML_NAMESPACE
+            || is_html_integration_point(element)
+            || is_mathml_text_integration_point(element)
+        }
+        _ => true,
+      };
+      if done {
+        break;
+      }
+      self.unfinished.pop();
+    }
+  }
+
+  fn foreign_start_tag(&mut self, tag: String, attributes: Vec<(String, String)>) {
+    let namespace = match &self.dom[*self.unfinished.last().unwrap()] {
+      Node::Element(element) if element.namespace == SVG_NAMESPACE => SVG_NAMESPACE,
+      _ => MATHML_NAMESPACE,
+    };
+    self.insert_foreign_element(tag, namespace, attributes);
+  }
+
+  // unlike html, any foreign element can close itself with "/>"
+  fn insert_foreign_element(
+    &mut self,
+    tag: String,
+    namespace: &str,
+    attributes: Vec<(String, String)>,
+  ) {
+    let tag = match namespace {
+      SVG_NAMESPACE => adjust_name(tag, &SVG_TAG_NAMES),
+      _ => tag,
+    };
+    let attributes = attributes
+      .into_iter()
+      .map(|(name, value)| match namespace {
+        SVG_NAMESPACE => (adjust_name(name, &SVG_ATTRIBUTE_NAMES), value),
+        MATHML_NAMESPACE if name == "definitionurl" => (String::from("definitionURL"), value),
+        _ => (name, value),
+      })
+      .collect();
+
+    let node = self.new_element(tag, namespace, attributes, self.span);
+    self.append(node);
+
+    if !self.self_closing {
+      self.unfinished.push(node);
+    }
+  }
+
+  // closes the nearest open element with this name, compared ignoring case;
+  // false means the tag reached html content and is handled there
+  fn foreign_end_tag(&mut self, tag: &str) -> bool {
+    let matches = |node: &Node| {
+      node
+        .tag()
+        .is_some_and(|name| name.eq_ignore_ascii_case(tag))
+    };
+
+    let mut index = self.unfinished.len() - 1;
+    if !matches(&self.dom[self.unfinished[index]]) {
+      self.error("unexpected-end-tag");
+    }
+
+    while index > 0 {
+      if matches(&self.dom[self.unfinished[index]]) {
+        self.unfinished.truncate(index);
+        return true;
+      }
+
+      index -= 1;
+      if html_tag(&self.dom[self.unfinished[index]]).is_some() {
+        return false;
+      }
+    }
+
+    true
+  }
+
+  fn end_tag(&mut self, tag: &str) {
+    match tag {
+      // the root and body stay open until the end of the document
+      "html" | "body" => (),
+      _ if FORMATTING_TAGS.contains(&tag) => {
+        if !self.adoption_agency(tag) {
+          self.any_other_end_tag(tag);
+        }
+      }
+      "applet" | "marquee" | "object" => {
+        if self.in_scope(tag, DEFAULT_SCOPE) {
+          self.generate_implied_end_tags(None);
+          self.pop_until(&[tag]);
+          self.clear_formatting_to_marker();
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      // </br> is read as <br>, like other browsers
+      "br" => {
+        self.error("unexpected-end-tag");
+        self.start_tag(String::from("br"), Vec::new());
+      }
+      "p" => {
+        // a stray </p> produces an empty paragraph, like other browsers
+        if !self.in_scope("p", BUTTON_SCOPE) {
+          self.error("unexpected-end-tag");
+          self.start_tag(String::from("p"), Vec::new());
+        }
+        self.close_p();
+      }
+      "li" => {
+        if self.in_scope("li", LIST_ITEM_SCOPE) {
+          self.generate_implied_end_tags(Some("li"));
+          self.pop_until(&["li"]);
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      "dd" | "dt" => {
+        if self.in_scope(tag, DEFAULT_SCOPE) {
+          self.generate_implied_end_tags(Some(tag));
+          self.pop_until(&[tag]);
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      "td" | "th" | "tr" | "tbody" | "thead" | "tfoot" | "table" | "caption" => {
+        if self.in_scope(tag, TABLE_SCOPE) {
+          self.generate_implied_end_tags(None);
+          self.pop_until(&[tag]);
+          if ["td", "th", "caption"].contains(&tag) {
+            self.clear_formatting_to_marker();
+          }
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      _ if BLOCK_TAGS.contains(&tag) => {
+        if self.in_scope(tag, DEFAULT_SCOPE) {
+          self.generate_implied_end_tags(None);
+          self.pop_until(&[tag]);
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      _ if HEADINGS.contains(&tag) => {
+        if HEADINGS.iter().any(|h| self.in_scope(h, DEFAULT_SCOPE)) {
+          self.generate_implied_end_tags(None);
+          self.pop_until(&HEADINGS);
+        } else {
+          self.error("unexpected-end-tag");
+        }
+      }
+      _ => self.any_other_end_tag(tag),
+    }
+  }
+
+  fn any_other_end_tag(&mut self, tag: &str) {
+    // walk up to the matching element, but never past a special one:
+    // in <span><p>x</span> the </span> is ignored instead of closing the <p>
+    for index in (1..self.unfinished.len()).rev() {
+      let node = &self.dom[self.unfinished[index]];
+      let (name, special) = (html_tag(node).unwrap_or("").to_string(), is_special(node));
 
-    if tag.starts_with('/') {
-      if self.unfinished.len() == 1 {
+      if name == tag {
+        self.generate_implied_end_tags(Some(tag));
+        if index != self.unfinished.len() - 1 {
+          self.error("unclosed-element");
+        }
+        self.unfinished.truncate(index);
         return;
       }
 
+      if special {
+        break;
+      }
+    }
+
+    self.error("unexpected-end-tag");
+  }
+
+  fn current_tag(&self) -> Option<&str> {
+    self
+      .unfinished
+      .last()
+      .and_then(|&node| html_tag(&self.dom[node]))
+  }
+
+  // https://html.spec.whatwg.org/multipage/parsing.html#has-an-element-in-scope
+  fn in_scope(&self, tag: &str, boundaries: &[&str]) -> bool {
+    for &node in self.unfinished.iter().rev() {
+      let node = &self.dom[node];
+      let Some(name) = html_tag(node) else {
+        if is_special(node) && boundaries != TABLE_SCOPE {
+          return false;
+        }
+        continue;
+      };
+
+      if name == tag {
+        return true;
+      }
+      if boundaries.contains(&name) {
+        return false;
+      }
+    }
+
+    false
+  }
+
+  fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+    while let Some(current) = self.current_tag() {
+      if Some(current) == except || !IMPLIED_END_TAGS.contains(&current) {
+        break;
+      }
+      self.unfinished.pop();
+    }
+  }
+
+  // pops elements up to and including the first one named in `tags`; the
+  // root element is never popped
+  fn pop_until(&mut self, tags: &[&str]) {
+    if !self
+      .current_tag()
+      .is_some_and(|current| tags.contains(&current))
+    {
+      self.error("unclosed-element");
+    }
+
+    while self.unfinished.len() > 1 {
       let node = self.unfinished.pop().unwrap();
-      let parent_rc = self.unfinished.last().unwrap().clone();
-      parent_rc.borrow_mut().children_mut().push(node);
-    } else if SELF_CLOSING_TAGS.contains(&tag.as_str()) {
-      let parent_rc = self.unfinished.last().unwrap().clone();
-      let parent_weak = Rc::downgrade(&parent_rc);
-
-      let node = Rc::new(RefCell::new(Node::Element(Element {
-        tag,
-        attributes,
-        parent: Some(parent_weak),
-        children: vec![],
-      })));
-
-      parent_rc.borrow_mut().children_mut().push(node);
-    } else {
-      let parent_weak = self.unfinished.last().map(|p| Rc::downgrade(p));
-
-      let node = Rc::new(RefCell::new(Node::Element(Element {
-        tag,
-        attributes,
-        parent: parent_weak,
-        children: vec![],
-      })));
+      if html_tag(&self.dom[node]).is_some_and(|name| tags.contains(&name)) {
+        break;
+      }
+    }
+  }
 
-      self.unfinished.push(node);
+  fn close_p(&mut self) {
+    if self.in_scope("p", BUTTON_SCOPE) {
+      self.generate_implied_end_tags(Some("p"));
+      self.pop_until(&["p"]);
     }
   }
 
-  fn get_attributes(&self, text: &str) -> (String, HashMap<String, String>) {
-    let parts: Vec<&str> = text.split_whitespace().collect();
+  // a new <li> closes the open one, unless a block like <div> or a nested
+  // list sits in between
+  fn close_list_item(&mut self, items: &[&str]) {
+    for index in (0..self.unfinished.len()).rev() {
+      let node = &self.dom[self.unfinished[index]];
+      let (name, special) = (html_tag(node).unwrap_or("").to_string(), is_special(node));
 
-    if parts.is_empty() {
-      return (String::new(), HashMap::new());
+      if items.contains(&name.as_str()) {
+        self.generate_implied_end_tags(Some(&name));
+        self.pop_until(&[&name]);
+        break;
+      }
+
+      if special && !["address", "div", "p"].contains(&name.as_str()) {
+        break;
+      }
     }
 
-    let tag = parts[0].to_lowercase();
-    let mut attributes = HashMap::new();
+    self.close_p();
+  }
 
-    for attrpair in &parts[1..] {
-      if let Some(pos) = attrpair.find('=') {
-        let key = attrpair[..pos].to_lowercase();
-        let mut value = attrpair[pos + 1..].to_string();
+  fn close_cell(&mut self) {
+    for cell in ["td", "th"] {
+      if self.in_scope(cell, TABLE_SCOPE) {
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+      }
+    }
+  }
 
-        if value.len() > 2 && (value.starts_with('"') || value.starts_with('\'')) {
-          value = value[1..value.len() - 1].to_string();
+  fn is_open(&self, node: NodeId) -> bool {
+    self.unfinished.contains(&node)
+  }
+
+  // the last formatting element with this tag since the last marker
+  fn formatting_element(&self, tag: &str) -> Option<NodeId> {
+    self
+      .formatting
+      .iter()
+      .rev()
+      .map_while(|&entry| entry)
+      .find(|&node| self.dom[node].tag() == Some(tag))
+  }
+
+  fn formatting_position(&self, node: NodeId) -> Option<usize> {
+    self
+      .formatting
+      .iter()
+      .position(|&entry| entry == Some(node))
+  }
+
+  fn remove_formatting(&mut self, node: NodeId) {
+    if let Some(index) = self.formatting_position(node) {
+      self.formatting.remove(index);
+    }
+  }
+
+  fn push_formatting(&mut self, node: NodeId) {
+    // Noah's Ark clause: at most three identical entries since the last marker
+    let identical: Vec<usize> = self
+      .formatting
+      .iter()
+      .enumerate()
+      .rev()
+      .map_while(|(index, &entry)| entry.map(|entry| (index, entry)))
+      .filter(|&(_, entry)| same_element(&self.dom[entry], &self.dom[node]))
+      .map(|(index, _)| index)
+      .collect();
+
+    if identical.len() >= 3 {
+      self.formatting.remove(*identical.last().unwrap());
+    }
+
+    self.formatting.push(Some(node));
+  }
+
+  fn clear_formatting_to_marker(&mut self) {
+    while let Some(entry) = self.formatting.pop() {
+      if entry.is_none() {
+        break;
+      }
+    }
+  }
+
+  // reopens formatting elements that were closed implicitly, so the b in
+  // <p><b>x<p>y is still bold
+  // https://html.spec.whatwg.org/multipage/parsing.html#reconstruct-the-active-formatting-elements
+  fn reconstruct_formatting(&mut self) {
+    let Some(&Some(last)) = self.formatting.last() else {
+      return;
+    };
+    if self.is_open(last) {
+      return;
+    }
+
+    let mut index = self.formatting.len() - 1;
+    while index > 0 {
+      match self.formatting[index - 1] {
+        Some(entry) if !self.is_open(entry) => index -= 1,
+        _ => break,
+      }
+    }
+
+    // the copies point back at the tag they were reopened from
+    for entry in index..self.formatting.len() {
+      let original = &self.dom[self.formatting[entry].unwrap()];
+      let (tag, attributes) = element_token(original);
+      let span = original.span().unwrap_or_default();
+      let node = self.insert_element(tag, attributes, span);
+      self.formatting[entry] = Some(node);
+    }
+  }
+
+  // returns false when there is no formatting element to adopt, and the end
+  // tag should get the ordinary treatment instead
+  // https://html.spec.whatwg.org/multipage/parsing.html#adoption-agency-algorithm
+  fn adoption_agency(&mut self, tag: &str) -> bool {
+    if let Some(&current) = self.unfinished.last()
+      && self.dom[current].tag() == Some(tag)
+      && self.formatting_position(current).is_none()
+    {
+      self.unfinished.pop();
+      return true;
+    }
+
+    for round in 0..8 {
+      let Some(formatting_element) = self.formatting_element(tag) else {
+        return false;
+      };
+
+      let Some(stack_index) = self
+        .unfinished
+        .iter()
+        .position(|&node| node == formatting_element)
+      else {
+        self.error("unexpected-end-tag");
+        self.remove_formatting(formatting_element);
+        return true;
+      };
+
+      if !self.in_scope(tag, DEFAULT_SCOPE) {
+        self.error("unexpected-end-tag");
+        return true;
+      }
+
+      if round == 0 && stack_index != self.unfinished.len() - 1 {
+        self.error("misnested-tags");
+      }
+
+      let furthest_block = (stack_index + 1..self.unfinished.len())
+        .find(|&index| is_special(&self.dom[self.unfinished[index]]));
+
+      let Some(mut block_index) = furthest_block else {
+        self.unfinished.truncate(stack_index);
+        self.remove_formatting(formatting_element);
+        return true;
+      };
+
+      let furthest_block = self.unfinished[block_index];
+      let common_ancestor = self.unfinished[stack_index - 1];
+      let mut bookmark = self.formatting_position(formatting_element).unwrap();
+
+      let mut last_node = furthest_block;
+      let mut node_index = block_index;
+      let mut inner = 0;
+
+      loop {
+        inner += 1;
+        node_index -= 1;
+        let node = self.unfinished[node_index];
+
+        if node == formatting_element {
+          break;
         }
 
-        attributes.insert(key, value);
-      } else {
-        attributes.insert(attrpair.to_lowercase(), String::new());
+        let mut position = self.formatting_position(node);
+        if inner > 3
+          && let Some(index) = position.take()
+        {
+          self.formatting.remove(index);
+          if index < bookmark {
+            bookmark -= 1;
+          }
+        }
+
+        let Some(position) = position else {
+          self.unfinished.remove(node_index);
+          block_index -= 1;
+          continue;
+        };
+
+        let (node_tag, attributes) = element_token(&self.dom[node]);
+        let span = self.dom[node].span().unwrap_or_default();
+        let replacement = self.new_element(node_tag, HTML_NAMESPACE, attributes, span);
+        self.formatting[position] = Some(replacement);
+        self.unfinished[node_index] = replacement;
+
+        if last_node == furthest_block {
+          bookmark = position + 1;
+        }
+
+        self.dom.append(replacement, last_node);
+        last_node = replacement;
+      }
+
+      let (parent, before) = self.insertion_place(common_ancestor, Some(last_node));
+      self.dom.insert(parent, last_node, before);
+
+      let (formatting_tag, attributes) = element_token(&self.dom[formatting_element]);
+      let span = self.dom[formatting_element].span().unwrap_or_default();
+      let replacement = self.new_element(formatting_tag, HTML_NAMESPACE, attributes, span);
+
+      let children: Vec<NodeId> = self.dom.children(furthest_block).collect();
+      for child in children {
+        self.dom.append(replacement, child);
       }
+      self.dom.append(furthest_block, replacement);
+
+      let position = self.formatting_position(formatting_element).unwrap();
+      self.formatting.remove(position);
+      if position < bookmark {
+        bookmark -= 1;
+      }
+      self
+        .formatting
+        .insert(bookmark.min(self.formatting.len()), Some(replacement));
+
+      self.unfinished.remove(stack_index);
+      block_index -= 1;
+      self.unfinished.insert(block_index + 1, replacement);
     }
 
-    (tag, attributes)
+    true
   }
 
-  fn finish(&mut self) -> Rc<RefCell<Node>> {
+  fn finish(&mut self) -> Dom {
     if self.unfinished.is_empty() {
       self.implicit_tags(None);
     }
 
-    while self.unfinished.len() > 1 {
-      let node = self.unfinished.pop().unwrap();
-      let parent_rc = self.unfinished.last().unwrap().clone();
-      parent_rc.borrow_mut().children_mut().push(node);
+    let unclosed = self.unfinished.iter().any(|&node| {
+      self.dom[node]
+        .tag()
+        .is_some_and(|tag| !OPTIONAL_END_TAGS.contains(&tag))
+    });
+    if unclosed {
+      self.error("eof-with-unclosed-elements");
     }
 
-    self.unfinished.pop().unwrap()
+    for &node in &self.unfinished {
+      if let Node::Element(element) = &mut self.dom[node] {
+        element.span.end = self.span.end;
+      }
+    }
+
+    // every node is already attached to its parent, the dom is all we need
+    self.unfinished.clear();
+    self.formatting.clear();
+    self.set_mode();
+    std::mem::take(&mut self.dom)
   }
 
+  // the elements added here aren't in the source, they get an empty span
+  // where the token that needed them starts
   fn implicit_tags(&mut self, tag: Option<&str>) {
+    let span = self.span;
+    self.span.end = self.span.start;
+
     loop {
-      let open_tags: Vec<String> = self
-        .unfinished
-        .iter()
-        .filter_map(|n| n.borrow().tag().map(|t| t.to_string()))
-        .collect();
+      // only the bottom of the stack matters, and only while it's short
+      let open_tags = match self.unfinished[..] {
+        [] => [None, None],
+        [html] => [self.dom[html].tag(), None],
+        [html, head] => [self.dom[html].tag(), self.dom[head].tag()],
+        _ => break,
+      };
 
-      if open_tags.is_empty() && tag != Some("html") {
-        self.add_tag("html".to_string());
-      } else if open_tags == vec!["html"]
+      let missing = if open_tags == [None, None] && tag != Some("html") {
+        "html"
+      } else if open_tags == [Some("html"), None]
         && !matches!(tag, Some("head") | Some("body") | Some("/html"))
       {
         if tag.map(|t| HEAD_TAGS.contains(&t)).unwrap_or(false) && !self.head_closed {
-          self.add_tag("head".to_string());
+          "head"
         } else {
-          self.add_tag("body".to_string());
+          "body"
         }
-      } else if open_tags == vec!["html", "head"]
+      } else if open_tags == [Some("html"), Some("head")]
         && !matches!(tag, Some("/head"))
         && !tag.map(|t| HEAD_TAGS.contains(&t)).unwrap_or(false)
       {
-        self.add_tag("/head".to_string());
+        "/head"
       } else {
         break;
-      }
+      };
+      self.add_tag(missing.to_string(), Vec::new());
     }
+
+    self.span = span;
   }
 }
 
-pub fn print_tree(node: &Rc<RefCell<Node>>, indent: usize) {
-  let padding = " ".repeat(indent);
-  let borrowed = node.borrow();
+// the tag of an html element; foreign elements never match html tag names
+fn html_tag(node: &Node) -> Option<&str> {
+  match node {
+    Node::Element(element) if element.namespace == HTML_NAMESPACE => Some(&element.tag),
+    _ => None,
+  }
+}
+
+// a hidden input is no more visible in a table than outside it, it's
+// left where it is
+fn is_table_content(node: &Node) -> bool {
+  match node {
+    Node::Element(element) if element.namespace == HTML_NAMESPACE => {
+      TABLE_CONTENT.contains(&element.tag.as_str())
+        || element.tag == "input"
+          && element
+            .attribute("type")
+            .is_some_and(|kind| kind.eq_ignore_ascii_case("hidden"))
+    }
+    _ => false,
+  }
+}
+
+fn is_special(node: &Node) -> bool {
+  let Node::Element(element) = node else {
+    return false;
+  };
+  match element.namespace.as_str() {
+    HTML_NAMESPACE => SPECIAL_TAGS.contains(&element.tag.as_str()),
+    MATHML_NAMESPACE => MATHML_BOUNDARY_TAGS.contains(&element.tag.as_str()),
+    SVG_NAMESPACE => SVG_BOUNDARY_TAGS.contains(&element.tag.as_str()),
+    _ => false,
+  }
+}
+
+fn is_mathml_text_integration_point(element: &Element) -> bool {
+  element.namespace == MATHML_NAMESPACE
+    && ["mi", "mo", "mn", "ms", "mtext"].contains(&element.tag.as_str())
+}
+
+fn is_html_integration_point(element: &Element) -> bool {
+  match element.namespace.as_str() {
+    MATHML_NAMESPACE => {
+      element.tag == "annotation-xml"
+        && element.attribute("encoding").is_some_and(|encoding| {
+          encoding.eq_ignore_ascii_case("text/html")
+            || encoding.eq_ignore_ascii_case("application/xhtml+xml")
+        })
+    }
+    SVG_NAMESPACE => SVG_BOUNDARY_TAGS.contains(&element.tag.as_str()),
+    _ => false,
+  }
+}
+
+fn breaks_out(tag: &str, attributes: &[(String, String)]) -> bool {
+  BREAKOUT_TAGS.contains(&tag)
+    || tag == "font"
+      && ["color", "face", "size"]
+        .iter()
+        .any(|name| attributes.iter().any(|(key, _)| key == name))
+}
+
+fn adjust_name(name: String, table: &[(&str, &str)]) -> String {
+  match table.iter().find(|(lower, _)| *lower == name) {
+    Some((_, adjusted)) => adjusted.to_string(),
+    None => name,
+  }
+}
+
+fn element_token(node: &Node) -> (String, Vec<(String, String)>) {
+  match node {
+    Node::Element(element) => (element.tag.clone(), element.attributes.clone()),
+    _ => (String::new(), Vec::new()),
+  }
+}
+
+fn same_element(a: &Node, b: &Node) -> bool {
+  match (a, b) {
+    // the same attributes in any order
+    (Node::Element(a), Node::Element(b)) => {
+      a.tag == b.tag
+        && a.namespace == b.namespace
+        && a.attributes.len() == b.attributes.len()
+        && a
+          .attributes
+          .iter()
+          .all(|(name, value)| b.attribute(name) == Some(value.as_str()))
+    }
+    _ => false,
+  }
+}
+
+pub fn print_tree(dom: &Dom, node: NodeId, indent: usize) {
+  let mut depth = indent;
 
-  match &*borrowed {
-    Node::Text(t) => println!("{}{:?}", padding, t.text),
-    Node::Element(e) => {
-      let mut s = String::new();
-      s.push_str(&format!("{}<{}", padding, e.tag));
+  for edge in dom.traverse(node) {
+    let padding = " ".repeat(depth);
+    match edge {
+      Edge::Open(node) => {
+        match &dom[node] {
+          Node::Document(d) => println!("{}#document ({:?})", padding, d.mode),
+          Node::Doctype(d) => println!(
+            "{}<!DOCTYPE {} \"{}\" \"{}\">",
+            padding, d.name, d.public_id, d.system_id
+          ),
+          Node::Text(t) => println!("{}{:?}", padding, t.text),
+          Node::Comment(c) => println!("{}<!-- {} -->", padding, c.text),
+          Node::ProcessingInstruction(p) => println!("{}<?{} {}>", padding, p.target, p.data),
+          Node::Element(e) => {
+            let mut s = String::new();
+            s.push_str(&format!("{}<{}", padding, tree_name(e)));
+
+            for (key, value) in &e.attributes {
+              s.push_str(&format!(" {}=\"{}\"", key, value));
+            }
 
-      for (key, value) in &e.attributes {
-        s.push_str(&format!(" {}=\"{}\"", key, value));
+            s.push('>');
+            println!("{}", s);
+          }
+        }
+        depth += 2;
+      }
+      Edge::Close(node) => {
+        depth -= 2;
+        if let Node::Element(e) = &dom[node] {
+          println!("{}</{}>", " ".repeat(depth), tree_name(e));
+        }
       }
+    }
+  }
+}
+
+// foreign elements are prefixed like in the html5lib tests, <svg circle>
+fn tree_name(e: &Element) -> String {
+  match e.namespace.as_str() {
+    SVG_NAMESPACE => format!("svg {}", e.tag),
+    MATHML_NAMESPACE => format!("math {}", e.tag),
+    _ => e.tag.clone(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::rendering::outer_html;
+
+  // the serialized tree and the error codes
+  fn parse(input: &str) -> (String, Vec<&'static str>) {
+    let mut parser = HTMLParser::new(input.to_string());
+    let dom = parser.parse();
+    let errors = parser.errors().iter().map(|error| error.code).collect();
+    (outer_html(&dom, dom.document()), errors)
+  }
+
+  fn body(input: &str) -> String {
+    let (html, _) = parse(&format!("<!DOCTYPE html>{input}"));
+    let html = html.strip_prefix("<!DOCTYPE html><html>").unwrap();
+    html.strip_suffix("</html>").unwrap().to_string()
+  }
+
+  #[test]
+  fn implicit_end_tags() {
+    assert_eq!(body("<p>a<p>b"), "<body><p>a</p><p>b</p></body>");
+    assert_eq!(
+      body("<ul><li>a<li>b</ul>"),
+      "<body><ul><li>a</li><li>b</li></ul></body>"
+    );
+    assert_eq!(
+      body("<dl><dt>a<dd>b<dt>c</dl>"),
+      "<body><dl><dt>a</dt><dd>b</dd><dt>c</dt></dl></body>"
+    );
+    assert_eq!(
+      body("<table><tr><td>a<td>b<tr><td>c</table>"),
+      "<body><table><tr><td>a</td><td>b</td></tr><tr><td>c</td></tr></table></body>"
+    );
+    assert_eq!(
+      body("<select><option>a<option>b</select>"),
+      "<body><select><option>a</option><option>b</option></select></body>"
+    );
+    assert_eq!(
+      body("<p>a<div>b</div>"),
+      "<body><p>a</p><div>b</div></body>"
+    );
+  }
+
+  #[test]
+  fn end_tags_match_up_the_stack() {
+    assert_eq!(
+      body("<div><span>a</div>b"),
+      "<body><div><span>a</span></div>b</body>"
+    );
+    // a special element stops the search, the </span> is ignored
+    assert_eq!(
+      body("<span><p>a</span>b"),
+      "<body><span><p>ab</p></span></body>"
+    );
+  }
+
+  #[test]
+  fn head_and_body_are_implied() {
+    assert_eq!(
+      body("<title>t</title><p>x"),
+      "<head><title>t</title></head><body><p>x</p></body>"
+    );
+  }
+
+  #[test]
+  fn misnested_formatting() {
+    assert_eq!(
+      body("<b>1<i>2</b>3</i>"),
+      "<body><b>1<i>2</i></b><i>3</i></body>"
+    );
+    assert_eq!(
+      body("<p>1<b>2<p>3</b>4"),
+      "<body><p>1<b>2</b></p><p><b>3</b>4</p></body>"
+    );
+    assert_eq!(
+      body("<b><div>x</b>y</div>"),
+      "<body><b></b><div><b>x</b>y</div></body>"
+    );
+    assert_eq!(body("<a>1<a>2"), "<body><a>1</a><a>2</a></body>");
+  }
+
+  #[test]
+  fn at_most_three_equal_formatting_elements_reopen() {
+    assert_eq!(
+      body("<p><b><b><b><b>x<p>y"),
+      "<body><p><b><b><b><b>x</b></b></b></b></p><p><b><b><b>y</b></b></b></p></body>"
+    );
+  }
+
+  #[test]
+  fn raw_text_and_rcdata() {
+    assert_eq!(
+      body("<title>a<b>&amp;</title>"),
+      "<head><title>a&lt;b&gt;&amp;</title></head>"
+    );
+    assert_eq!(
+      body("<style>a<b>&amp;</STYLE >"),
+      "<head><style>a<b>&amp;</style></head>"
+    );
+    assert_eq!(
+      body("<textarea>\nx</textarea2></textarea>"),
+      "<body><textarea>x&lt;/textarea2&gt;</textarea></body>"
+    );
+    assert_eq!(
+      body("<xmp><p>&lt;</xmp>"),
+      "<body><xmp><p>&lt;</xmp></body>"
+    );
+    assert_eq!(
+      body("<plaintext><p></plaintext>"),
+      "<body><plaintext><p></plaintext></plaintext></body>"
+    );
+  }
+
+  #[test]
+  fn escaped_script_data() {
+    assert_eq!(
+      body("<script><!--<script>a</script>--></script>x"),
+      "<head><script><!--<script>a</script>--></script></head><body>x</body>"
+    );
+    assert_eq!(
+      body("<script><!--a</script>x"),
+      "<head><script><!--a</script></head><body>x</body>"
+    );
+  }
+
+  #[test]
+  fn noscript_depends_on_scripting() {
+    let input = "<body><noscript><p>a</p></noscript>";
+    assert_eq!(body(input), "<body><noscript><p>a</p></noscript></body>");
+
+    let mut parser = HTMLParser::new(input.to_string());
+    parser.set_scripting(true);
+    // with scripting the content is text
+    let dom = parser.parse();
+    assert!(outer_html(&dom, dom.document()).contains("<noscript>&lt;p&gt;a&lt;/p&gt;</noscript>"));
+  }
+
+  #[test]
+  fn comments_stay_where_they_were() {
+    let (html, _) = parse("<!--a--><!DOCTYPE html><p><!--b--></p></body><!--c--></html><!--d-->");
+    assert_eq!(
+      html,
+      "<!--a--><!DOCTYPE html><html><body><p><!--b--></p></body><!--c--></html><!--d-->"
+    );
+  }
+
+  #[test]
+  fn doctype_belongs_to_the_document() {
+    let dom = HTMLParser::new("<!DOCTYPE html><p>a".to_string()).parse();
+    assert_eq!(
+      outer_html(&dom, dom.document()),
+      "<!DOCTYPE html><html><body><p>a</p></body></html>"
+    );
+  }
 
-      s.push('>');
-      println!("{}", s);
+  // the first element with this tag
+  fn find(dom: &Dom, node: NodeId, tag: &str) -> Option<NodeId> {
+    if matches!(&dom[node], Node::Element(e) if e.tag == tag) {
+      return Some(node);
+    }
+    dom.children(node).find_map(|child| find(dom, child, tag))
+  }
+
+  // start and end of the first element with this tag, as (line, column)
+  fn element_span(dom: &Dom, tag: &str) -> ((usize, usize), (usize, usize)) {
+    let node = find(dom, dom.document(), tag).unwrap();
+    let Node::Element(element) = &dom[node] else {
+      unreachable!()
+    };
+    let span = element.span;
+    (
+      (span.start.line, span.start.column),
+      (span.end.line, span.end.column),
+    )
+  }
+
+  #[test]
+  fn elements_know_where_they_came_from() {
+    let dom = HTMLParser::new("<!DOCTYPE html>\n<p id=a>ab\n<b>c</b></p>\n".to_string()).parse();
+    assert_eq!(element_span(&dom, "p"), ((2, 1), (3, 13)));
+    assert_eq!(element_span(&dom, "b"), ((3, 1), (3, 9)));
+
+    // closed implicitly, it ends where the closing tag starts
+    let dom = HTMLParser::new("<!DOCTYPE html><ul><li>a<li>b</ul>".to_string()).parse();
+    assert_eq!(element_span(&dom, "li"), ((1, 20), (1, 25)));
+  }
+
+  #[test]
+  fn errors_have_positions() {
+    let mut parser = HTMLParser::new("<p>a</p>\n<div><span>b</div>".to_string());
+    parser.parse();
+    let errors: Vec<_> = parser
+      .errors()
+      .iter()
+      .map(|error| (error.code, error.position.line, error.position.column))
+      .collect();
+    assert_eq!(
+      errors,
+      vec![("missing-doctype", 1, 1), ("unclosed-element", 2, 13)]
+    );
+
+    let (_, errors) = parse("<!DOCTYPE html><b>1<i>2</b>3</i>");
+    assert_eq!(errors, vec!["misnested-tags"]);
+  }
+
+  // foreign elements are prefixed with their namespace, like in the html5lib
+  // tests
+  fn tree_name(e: &Element) -> String {
+    match e.namespace.as_str() {
+      SVG_NAMESPACE => format!("svg {}", e.tag),
+      MATHML_NAMESPACE => format!("math {}", e.tag),
+      _ => e.tag.clone(),
+    }
+  }
 
-      for child in &e.children {
-        print_tree(child, indent + 2);
+  fn write_tree(dom: &Dom, node: NodeId, depth: usize, lines: &mut Vec<String>) {
+    let padding = "  ".repeat(depth);
+    match &dom[node] {
+      Node::Element(e) => {
+        lines.push(format!("| {padding}<{}>", tree_name(e)));
+        for (name, value) in &e.attributes {
+          lines.push(format!("|   {padding}{name}=\"{value}\""));
+        }
       }
+      Node::Text(t) => lines.push(format!("| {padding}\"{}\"", t.text)),
+      _ => {}
+    }
+    for child in dom.children(node) {
+      write_tree(dom, child, depth + 1, lines);
+    }
+  }
 
-      println!("{}</{}>", padding, e.tag);
-      return;
+  // the elements and text of the body in the html5lib test format
+  fn tree(input: &str) -> String {
+    let dom = HTMLParser::new(format!("<!DOCTYPE html>{input}")).parse();
+    let body = find(&dom, dom.document(), "body").unwrap();
+
+    let mut lines = vec![];
+    write_tree(&dom, body, 0, &mut lines);
+    lines.join("\n")
+  }
+
+  #[test]
+  fn svg_names_are_adjusted() {
+    assert_eq!(
+      tree("<svg viewbox='0 0 1 1'><clippath/><lineargradient></lineargradient></svg>"),
+      "| <body>\n\
+       |   <svg svg>\n\
+       |     viewBox=\"0 0 1 1\"\n\
+       |     <svg clipPath>\n\
+       |     <svg linearGradient>"
+    );
+    assert_eq!(
+      tree("<math definitionurl=x><mi>1</mi></math>"),
+      "| <body>\n\
+       |   <math math>\n\
+       |     definitionURL=\"x\"\n\
+       |     <math mi>\n\
+       |       \"1\""
+    );
+  }
+
+  #[test]
+  fn html_inside_integration_points() {
+    assert_eq!(
+      tree("<svg><foreignobject><p>a</p></foreignobject><title><i>t</i></title></svg>"),
+      "| <body>\n\
+       |   <svg svg>\n\
+       |     <svg foreignObject>\n\
+       |       <p>\n\
+       |         \"a\"\n\
+       |     <svg title>\n\
+       |       <i>\n\
+       |         \"t\""
+    );
+    assert_eq!(
+      tree("<math><annotation-xml encoding='text/html'><div>d</div></annotation-xml></math>"),
+      "| <body>\n\
+       |   <math math>\n\
+       |     <math annotation-xml>\n\
+       |       encoding=\"text/html\"\n\
+       |       <div>\n\
+       |         \"d\""
+    );
+  }
+
+  #[test]
+  fn html_start_tags_break_out_of_svg() {
+    assert_eq!(
+      tree("<svg><g><p>x"),
+      "| <body>\n\
+       |   <svg svg>\n\
+       |     <svg g>\n\
+       |   <p>\n\
+       |     \"x\""
+    );
+  }
+
+  #[test]
+  fn cdata_only_in_foreign_content() {
+    assert_eq!(
+      tree("<svg><![CDATA[a<b]]></svg>"),
+      "| <body>\n|   <svg svg>\n|     \"a<b\""
+    );
+    assert_eq!(
+      body("<p><![CDATA[a]]>"),
+      "<body><p><!--[CDATA[a]]--></p></body>"
+    );
+  }
+
+  #[test]
+  fn foster_parenting() {
+    assert_eq!(
+      body("<table>a<tr><td>b</table>"),
+      "<body>a<table><tr><td>b</td></tr></table></body>"
+    );
+    assert_eq!(
+      body("<table><tr><p>x</p><td>y</table>"),
+      "<body><p>x</p><table><tr><td>y</td></tr></table></body>"
+    );
+    assert_eq!(
+      body("<table><input type=hidden><input></table>"),
+      "<body><input><table><input type=\"hidden\"></table></body>"
+    );
+  }
+
+  #[test]
+  fn end_br_is_a_line_break() {
+    let (html, errors) = parse("<!DOCTYPE html>a</br>b");
+    assert_eq!(html, "<!DOCTYPE html><html><body>a<br>b</body></html>");
+    assert_eq!(errors, vec!["unexpected-end-tag"]);
+  }
+
+  // everything a chunked parse has to agree on: the markup, where each node
+  // came from, the errors and the document mode
+  fn outcome(parser: &mut HTMLParser) -> (String, Vec<Option<Span>>, Vec<ParseError>, String) {
+    let dom = parser.parse();
+    let spans = dom
+      .descendants(dom.document())
+      .map(|node| dom[node].span())
+      .collect();
+    (
+      outer_html(&dom, dom.document()),
+      spans,
+      parser.errors().to_vec(),
+      format!("{:?}", dom.mode()),
+    )
+  }
+
+  #[test]
+  fn any_chunking_builds_the_same_tree() {
+    let input = "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">\r\n\
+      <html><head><title>caf\u{e9} &amp; cr\u{e8}me</title>\r\n\
+      <script>if (a < b) { document.write('</scr' + 'ipt>'); }</script>\n\
+      <style>p > a { color: red }</style></head>\n\
+      <body><!-- \u{1f600} comment --><p class=\"a b\" data-x='\u{2603}'>&copy &notin; &#x1F600; &#233;\n\
+      <b>1<i>2</b>3</i>    \t  <table>x<tr><td>\u{4e2d}\u{6587}<td>y</table>\n\
+      <ul><li>one<li>two</ul><svg viewbox='0 0 1 1'><![CDATA[<\u{fc}>]]><foreignObject><p>z</p></foreignObject></svg>\n\
+      <textarea>\n&lt;x&gt;</textarea><pre>\n\nkept</pre>a</br>b<p>\u{1f600}</body></html>\n<!--end-->";
+    let bytes = input.as_bytes();
+
+    let expected = outcome(&mut HTMLParser::new(input.to_string()));
+    assert!(!expected.2.is_empty());
+
+    for size in 1..=80 {
+      let mut parser = HTMLParser::streaming();
+      for chunk in bytes.chunks(size) {
+        parser.feed(chunk);
+        // the partial tree is usable between chunks
+        outer_html(parser.dom(), parser.dom().document());
+      }
+      assert_eq!(outcome(&mut parser), expected, "chunks of {size} bytes");
     }
   }
 
-  for child in borrowed.children() {
-    print_tree(child, indent + 2);
+  #[test]
+  fn split_utf8_sequences_decode_once() {
+    let input = "<p>\u{e9}\u{1f600}\u{2603}</p>".as_bytes();
+    let mut parser = HTMLParser::streaming();
+    for byte in input {
+      parser.feed(&[*byte]);
+    }
+    let (html, ..) = outcome(&mut parser);
+    assert!(html.contains("<p>\u{e9}\u{1f600}\u{2603}</p>"));
+
+    // a truncated sequence at the end is one replacement character
+    let mut parser = HTMLParser::streaming();
+    parser.feed(&input[..6]);
+    let (html, ..) = outcome(&mut parser);
+    assert!(html.contains("<p>\u{e9}\u{fffd}</p>"));
   }
 }
@@ -0,0 +1,345 @@
+use crate::rendering::DisplayList;
+
+use iced::advanced::graphics::text::Paragraph as GraphicsParagraph;
+use iced::advanced::text::Paragraph;
+use iced::advanced::text::Text as AdvancedText;
+use iced::alignment;
+use iced::font::Font;
+use iced::widget::text::Wrapping;
+use iced::widget::text::{LineHeight, Shaping};
+use iced::{Pixels, Size};
+
+use std::ops::Range;
+
+const HSTEP: f32 = 9.0;
+const VSTEP: f32 = 15.0;
+const FONT_SIZE: f32 = 14.0;
+const LINE_HEIGHT: f32 = FONT_SIZE * 1.4;
+const TAB_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextOptions {
+  pub wrap: bool,
+  pub line_numbers: bool,
+}
+
+// a plain text document is indexed once when it arrives; a resize or an
+// option toggle only recounts rows, and only the lines on screen are turned
+// into display items, so multi-megabyte logs stay fast
+pub struct PlainTextLayout {
+  text: String,
+  // byte ranges of the lines, without their line endings
+  lines: Vec<Range<usize>>,
+  // the width of each line in columns, once tabs are expanded
+  widths: Vec<usize>,
+  char_width: f32,
+  options: TextOptions,
+  text_x: f32,
+  columns: usize,
+  gutter_columns: usize,
+  // the first row of every line; a wrapped line takes several rows
+  first_rows: Vec<usize>,
+  rows: usize,
+}
+
+impl PlainTextLayout {
+  pub fn new(text: String) -> Self {
+    // every glyph of a monospace font has the same advance, so one measurement
+    // is enough to lay out the whole document without shaping each line
+    let char_width = GraphicsParagraph::with_text(AdvancedText {
+      content: "0",
+      bounds: Size::INFINITY,
+      size: Pixels(FONT_SIZE),
+      line_height: LineHeight::default(),
+      font: Font::MONOSPACE,
+      horizontal_alignment: alignment::Horizontal::Left,
+      vertical_alignment: alignment::Vertical::Top,
+      shaping: Shaping::Basic,
+      wrapping: Wrapping::None,
+    })
+    .min_bounds()
+    .width
+    .max(1.0);
+
+    Self::with_char_width(text, char_width)
+  }
+
+  fn with_char_width(text: String, char_width: f32) -> Self {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+      let content = line.strip_suffix('\n').unwrap_or(line);
+      let content = content.strip_suffix('\r').unwrap_or(content);
+      lines.push(start..start + content.len());
+      start += line.len();
+    }
+
+    let widths = lines
+      .iter()
+      .map(|range| expanded_width(&text[range.clone()]))
+      .collect();
+
+    Self {
+      text,
+      lines,
+      widths,
+      char_width,
+      options: TextOptions::default(),
+      text_x: HSTEP,
+      columns: 1,
+      gutter_columns: 0,
+      first_rows: Vec::new(),
+      rows: 0,
+    }
+  }
+
+  pub fn text(&self) -> &str {
+    &self.text
+  }
+
+  // counts the rows every line takes at this width
+  pub fn set_width(&mut self, width: f32, options: TextOptions) {
+    self.options = options;
+    self.gutter_columns = if options.line_numbers {
+      self.lines.len().max(1).to_string().len() + 2
+    } else {
+      0
+    };
+    self.text_x = HSTEP + self.gutter_columns as f32 * self.char_width;
+    self.columns = ((width - self.text_x - HSTEP) / self.char_width)
+      .floor()
+      .max(1.0) as usize;
+
+    self.first_rows.clear();
+    let mut row = 0;
+    for &width in &self.widths {
+      self.first_rows.push(row);
+      row += if options.wrap {
+        width.div_ceil(self.columns).max(1)
+      } else {
+        1
+      };
+    }
+    self.rows = row;
+  }
+
+  // the y of the last row, like the deepest item of a laid out page
+  pub fn max_y(&self) -> f32 {
+    VSTEP + self.rows.saturating_sub(1) as f32 * LINE_HEIGHT
+  }
+
+  // display items for the rows between `top` and `top + height`
+  pub fn display_list(&self, top: f32, height: f32) -> DisplayList {
+    let mut display_list = DisplayList::new();
+
+    let first_row = ((top - VSTEP) / LINE_HEIGHT).floor().max(0.0) as usize;
+    let last_row = ((top + height - VSTEP) / LINE_HEIGHT).ceil().max(0.0) as usize + 1;
+    let first_line = self
+      .first_rows
+      .partition_point(|&row| row <= first_row)
+      .saturating_sub(1);
+
+    for number in first_line..self.lines.len() {
+      let mut row = self.first_rows[number];
+      if row > last_row {
+        break;
+      }
+      let y = |row: usize| VSTEP + row as f32 * LINE_HEIGHT;
+
+      if self.options.line_numbers {
+        display_list.add_item(
+          HSTEP,
+          y(row),
+          format!("{:>width$}", number + 1, width = self.gutter_columns - 2),
+          Font::MONOSPACE,
+          FONT_SIZE,
+        );
+      }
+
+      let expanded = expand_tabs(&self.text[self.lines[number].clone()]);
+
+      if self.options.wrap && self.widths[number] > self.columns {
+        let chars: Vec<char> = expanded.chars().collect();
+        for segment in chars.chunks(self.columns) {
+          if (first_row..=last_row).contains(&row) {
+            display_list.add_item(
+              self.text_x,
+              y(row),
+              segment.iter().collect(),
+              Font::MONOSPACE,
+              FONT_SIZE,
+            );
+          }
+          row += 1;
+        }
+      } else if !expanded.is_empty() {
+        display_list.add_item(self.text_x, y(row), expanded, Font::MONOSPACE, FONT_SIZE);
+      }
+    }
+
+    display_list
+  }
+}
+
+fn expanded_width(line: &str) -> usize {
+  line.chars().fold(0, |column, c| {
+    if c == '\t' {
+      column + TAB_SIZE - column % TAB_SIZE
+    } else {
+      column + 1
+    }
+  })
+}
+
+fn expand_tabs(line: &str) -> String {
+  if !line.contains('\t') {
+    return line.to_string();
+  }
+
+  let mut expanded = String::with_capacity(line.len() + TAB_SIZE);
+  let mut column = 0;
+
+  for c in line.chars() {
+    if c == '\t' {
+      let spaces = TAB_SIZE - column % TAB_SIZE;
+      expanded.extend(std::iter::repeat_n(' ', spaces));
+      column += spaces;
+    } else {
+      expanded.push(c);
+      column += 1;
+    }
+  }
+
+  expanded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CHAR_WIDTH: f32 = 10.0;
+
+  // a layout `columns` characters wide, plus the gutter for line numbers
+  fn laid_out(text: &str, columns: usize, options: TextOptions) -> PlainTextLayout {
+    let mut layout = PlainTextLayout::with_char_width(text.to_string(), CHAR_WIDTH);
+    let gutter = if options.line_numbers {
+      text.lines().count().max(1).to_string().len() + 2
+    } else {
+      0
+    };
+    let width = 2.0 * HSTEP + (columns + gutter) as f32 * CHAR_WIDTH;
+    layout.set_width(width, options);
+    layout
+  }
+
+  // (row, x, text) of every item in the whole document
+  fn items(layout: &PlainTextLayout) -> Vec<(usize, f32, String)> {
+    layout
+      .display_list(0.0, layout.max_y() + LINE_HEIGHT)
+      .items()
+      .iter()
+      .map(|item| {
+        let row = ((item.y - VSTEP) / LINE_HEIGHT).round() as usize;
+        (row, item.x, item.word.clone())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn tabs_expand_to_the_next_stop() {
+    assert_eq!(expand_tabs("a\tb"), "a       b");
+    assert_eq!(expand_tabs("\t"), " ".repeat(8));
+    assert_eq!(expand_tabs("abcdefgh\tx"), "abcdefgh        x");
+    assert_eq!(expand_tabs("no tabs"), "no tabs");
+    assert_eq!(expanded_width("a\tb"), 9);
+    assert_eq!(expanded_width("\u{e9}\t"), 8);
+  }
+
+  #[test]
+  fn long_lines_wrap_at_the_column_limit() {
+    let options = TextOptions {
+      wrap: true,
+      line_numbers: false,
+    };
+    let layout = laid_out("abcdefghij\n\nxy\r\n", 4, options);
+    assert_eq!(
+      items(&layout),
+      vec![
+        (0, HSTEP, "abcd".to_string()),
+        (1, HSTEP, "efgh".to_string()),
+        (2, HSTEP, "ij".to_string()),
+        (4, HSTEP, "xy".to_string()),
+      ]
+    );
+    assert_eq!(layout.max_y(), VSTEP + 4.0 * LINE_HEIGHT);
+
+    // without wrapping every line is one row
+    let layout = laid_out("abcdefghij\n\nxy\r\n", 4, TextOptions::default());
+    assert_eq!(
+      items(&layout),
+      vec![
+        (0, HSTEP, "abcdefghij".to_string()),
+        (2, HSTEP, "xy".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn line_numbers_sit_in_a_gutter() {
+    let text: String = (1..=10).map(|n| format!("line {n}\n")).collect();
+    let options = TextOptions {
+      wrap: false,
+      line_numbers: true,
+    };
+    let numbered = items(&laid_out(&text, 80, options));
+
+    // two digits plus two columns of padding
+    let text_x = HSTEP + 4.0 * CHAR_WIDTH;
+    assert_eq!(numbered[0], (0, HSTEP, " 1".to_string()));
+    assert_eq!(numbered[1], (0, text_x, "line 1".to_string()));
+    assert_eq!(numbered[18], (9, HSTEP, "10".to_string()));
+
+    // a wrapped line is numbered once
+    let options = TextOptions {
+      wrap: true,
+      line_numbers: true,
+    };
+    let numbers: Vec<_> = items(&laid_out("abcdefgh\nx", 3, options))
+      .into_iter()
+      .filter(|(_, x, _)| *x == HSTEP)
+      .collect();
+    assert_eq!(
+      numbers,
+      vec![(0, HSTEP, "1".to_string()), (3, HSTEP, "2".to_string())]
+    );
+  }
+
+  #[test]
+  fn only_rows_on_screen_are_laid_out() {
+    let text: String = (0..100_000).map(|n| format!("{n}\n")).collect();
+    let mut layout = laid_out(&text, 80, TextOptions::default());
+    assert_eq!(layout.max_y(), VSTEP + 99_999.0 * LINE_HEIGHT);
+
+    let top = VSTEP + 5000.0 * LINE_HEIGHT;
+    let list = layout.display_list(top, 10.0 * LINE_HEIGHT);
+    let words: Vec<_> = list.items().iter().map(|item| item.word.as_str()).collect();
+    assert_eq!(words.first(), Some(&"5000"));
+    assert!(words.len() <= 12);
+
+    // toggling wrap only recounts rows
+    layout.set_width(
+      2.0 * HSTEP + 2.0 * CHAR_WIDTH,
+      TextOptions {
+        wrap: true,
+        line_numbers: false,
+      },
+    );
+    let list = layout.display_list(0.0, 3.0 * LINE_HEIGHT);
+    let words: Vec<_> = list.items().iter().map(|item| item.word.as_str()).collect();
+    assert_eq!(words[..4], ["0", "1", "2", "3"]);
+    let list = layout.display_list(VSTEP + 10.0 * LINE_HEIGHT, 0.0);
+    let words: Vec<_> = list.items().iter().map(|item| item.word.as_str()).collect();
+    // rows 0-9 hold 0-9, then 10 splits over rows 10 and 11
+    assert_eq!(words[..2], ["10", "11"]);
+  }
+}
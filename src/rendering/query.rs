@@ -0,0 +1,679 @@
+use crate::utils::{Dom, HTML_NAMESPACE, Node, NodeId};
+use std::fmt;
+
+// lookups over the parsed tree, like the DOM methods of the same names;
+// every search covers the descendants of the given node in document order
+
+pub fn get_element_by_id(dom: &Dom, root: NodeId, id: &str) -> Option<NodeId> {
+  dom
+    .elements(root)
+    .find(|&node| attribute(&dom[node], "id") == Some(id))
+}
+
+// "*" matches every element
+pub fn get_elements_by_tag_name(dom: &Dom, root: NodeId, tag: &str) -> Vec<NodeId> {
+  dom
+    .elements(root)
+    .filter(|&node| tag == "*" || has_tag(&dom[node], tag))
+    .collect()
+}
+
+// elements that have all of the space separated classes
+pub fn get_elements_by_class_name(dom: &Dom, root: NodeId, names: &str) -> Vec<NodeId> {
+  let names: Vec<&str> = names.split_ascii_whitespace().collect();
+  if names.is_empty() {
+    return vec![];
+  }
+
+  dom
+    .elements(root)
+    .filter(|&node| names.iter().all(|name| has_class(&dom[node], name)))
+    .collect()
+}
+
+pub fn query_selector(
+  dom: &Dom,
+  root: NodeId,
+  selectors: &str,
+) -> Result<Option<NodeId>, SelectorError> {
+  let selector = Selector::parse(selectors)?;
+  Ok(dom.elements(root).find(|&node| selector.matches(dom, node)))
+}
+
+pub fn query_selector_all(
+  dom: &Dom,
+  root: NodeId,
+  selectors: &str,
+) -> Result<Vec<NodeId>, SelectorError> {
+  let selector = Selector::parse(selectors)?;
+  Ok(
+    dom
+      .elements(root)
+      .filter(|&node| selector.matches(dom, node))
+      .collect(),
+  )
+}
+
+#[derive(Debug)]
+pub struct SelectorError {
+  pub message: String,
+  // in characters from the start of the selector
+  pub position: usize,
+}
+
+impl fmt::Display for SelectorError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} at position {}", self.message, self.position)
+  }
+}
+
+impl std::error::Error for SelectorError {}
+
+// a comma separated list of complex selectors, see
+// https://www.w3.org/TR/selectors-4/ for the grammar this is a subset of
+#[derive(Debug, Clone)]
+pub struct Selector {
+  alternatives: Vec<Complex>,
+}
+
+// compounds[i] and compounds[i + 1] are joined by combinators[i]
+#[derive(Debug, Clone)]
+struct Complex {
+  compounds: Vec<Compound>,
+  combinators: Vec<Combinator>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Combinator {
+  Descendant,
+  Child,
+  NextSibling,
+  SubsequentSibling,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Compound {
+  // None for "*" or a compound without a type selector
+  tag: Option<String>,
+  conditions: Vec<Condition>,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+  Id(String),
+  Class(String),
+  // the name, then the operator, the value and whether the value is
+  // compared ignoring ASCII case
+  Attribute(String, Option<(AttributeOperator, String, bool)>),
+  FirstChild,
+  // matches the (a * n + b)th child for some n >= 0
+  NthChild(i64, i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AttributeOperator {
+  Equals,
+  Includes,
+  DashMatch,
+  Prefix,
+  Suffix,
+  Substring,
+}
+
+impl Selector {
+  pub fn parse(selectors: &str) -> Result<Selector, SelectorError> {
+    SelectorParser {
+      input: selectors.chars().collect(),
+      index: 0,
+    }
+    .parse()
+  }
+
+  pub fn matches(&self, dom: &Dom, node: NodeId) -> bool {
+    self
+      .alternatives
+      .iter()
+      .any(|complex| complex.matches(dom, node, complex.compounds.len() - 1))
+  }
+}
+
+impl Complex {
+  // matched right to left, starting with the compound at `index`
+  fn matches(&self, dom: &Dom, node: NodeId, index: usize) -> bool {
+    if !self.compounds[index].matches(dom, node) {
+      return false;
+    }
+    if index == 0 {
+      return true;
+    }
+
+    match self.combinators[index - 1] {
+      Combinator::Child => {
+        parent_element(dom, node).is_some_and(|parent| self.matches(dom, parent, index - 1))
+      }
+      Combinator::Descendant => dom
+        .ancestors(node)
+        .take_while(|&ancestor| matches!(&dom[ancestor], Node::Element(_)))
+        .any(|ancestor| self.matches(dom, ancestor, index - 1)),
+      Combinator::NextSibling => previous_elements(dom, node)
+        .next()
+        .is_some_and(|sibling| self.matches(dom, sibling, index - 1)),
+      Combinator::SubsequentSibling => {
+        previous_elements(dom, node).any(|sibling| self.matches(dom, sibling, index - 1))
+      }
+    }
+  }
+}
+
+impl Compound {
+  fn matches(&self, dom: &Dom, id: NodeId) -> bool {
+    let node = &dom[id];
+    if !matches!(node, Node::Element(_)) {
+      return false;
+    }
+    if let Some(tag) = &self.tag
+      && !has_tag(node, tag)
+    {
+      return false;
+    }
+
+    self.conditions.iter().all(|condition| match condition {
+      Condition::Id(id) => attribute(node, "id") == Some(id.as_str()),
+      Condition::Class(name) => has_class(node, name),
+      Condition::Attribute(name, operator) => {
+        let Some(value) = attribute(node, name) else {
+          return false;
+        };
+        match operator {
+          None => true,
+          Some((operator, expected, ignore_case)) => {
+            let (value, expected) = if *ignore_case {
+              (value.to_ascii_lowercase(), expected.to_ascii_lowercase())
+            } else {
+              (value.to_string(), expected.clone())
+            };
+            match operator {
+              AttributeOperator::Equals => value == expected,
+              AttributeOperator::Includes => {
+                value.split_ascii_whitespace().any(|word| word == expected)
+              }
+              AttributeOperator::DashMatch => {
+                value == expected || value.starts_with(&format!("{expected}-"))
+              }
+              // an empty value never matches these three
+              AttributeOperator::Prefix => {
+                !expected.is_empty() && value.starts_with(expected.as_str())
+              }
+              AttributeOperator::Suffix => {
+                !expected.is_empty() && value.ends_with(expected.as_str())
+              }
+              AttributeOperator::Substring => {
+                !expected.is_empty() && value.contains(expected.as_str())
+              }
+            }
+          }
+        }
+      }
+      Condition::FirstChild => previous_elements(dom, id).next().is_none(),
+      Condition::NthChild(a, b) => {
+        let position = previous_elements(dom, id).count() as i64 + 1;
+        if *a == 0 {
+          position == *b
+        } else {
+          (position - b) % a == 0 && (position - b) / a >= 0
+        }
+      }
+    })
+  }
+}
+
+struct SelectorParser {
+  input: Vec<char>,
+  index: usize,
+}
+
+impl SelectorParser {
+  fn parse(&mut self) -> Result<Selector, SelectorError> {
+    let mut alternatives = vec![self.complex()?];
+    while self.next_is(',') {
+      self.index += 1;
+      alternatives.push(self.complex()?);
+    }
+
+    if self.index < self.input.len() {
+      return Err(self.error("unexpected character"));
+    }
+    Ok(Selector { alternatives })
+  }
+
+  fn complex(&mut self) -> Result<Complex, SelectorError> {
+    self.skip_whitespace();
+    let mut compounds = vec![self.compound()?];
+    let mut combinators = vec![];
+
+    loop {
+      let whitespace = self.skip_whitespace();
+      let combinator = match self.peek() {
+        Some('>') => Combinator::Child,
+        Some('+') => Combinator::NextSibling,
+        Some('~') => Combinator::SubsequentSibling,
+        None | Some(',') => break,
+        Some(_) if whitespace => {
+          combinators.push(Combinator::Descendant);
+          compounds.push(self.compound()?);
+          continue;
+        }
+        Some(_) => return Err(self.error("unexpected character")),
+      };
+
+      self.index += 1;
+      self.skip_whitespace();
+      combinators.push(combinator);
+      compounds.push(self.compound()?);
+    }
+
+    Ok(Complex {
+      compounds,
+      combinators,
+    })
+  }
+
+  fn compound(&mut self) -> Result<Compound, SelectorError> {
+    let mut compound = Compound::default();
+    let start = self.index;
+
+    if self.next_is('*') {
+      self.index += 1;
+    } else if self.peek().is_some_and(is_name_char) {
+      compound.tag = Some(self.identifier()?);
+    }
+
+    loop {
+      match self.peek() {
+        Some('#') => {
+          self.index += 1;
+          compound.conditions.push(Condition::Id(self.identifier()?));
+        }
+        Some('.') => {
+          self.index += 1;
+          compound
+            .conditions
+            .push(Condition::Class(self.identifier()?));
+        }
+        Some('[') => {
+          self.index += 1;
+          let condition = self.attribute()?;
+          compound.conditions.push(condition);
+        }
+        Some(':') => {
+          self.index += 1;
+          let condition = self.pseudo_class()?;
+          compound.conditions.push(condition);
+        }
+        _ => break,
+      }
+    }
+
+    if self.index == start {
+      return Err(self.error("expected a selector"));
+    }
+    Ok(compound)
+  }
+
+  // runs after the '['
+  fn attribute(&mut self) -> Result<Condition, SelectorError> {
+    self.skip_whitespace();
+    let name = self.identifier()?;
+    self.skip_whitespace();
+
+    let operator = match self.peek() {
+      Some(']') => {
+        self.index += 1;
+        return Ok(Condition::Attribute(name, None));
+      }
+      Some('=') => AttributeOperator::Equals,
+      Some(c) => {
+        let operator = match c {
+          '~' => AttributeOperator::Includes,
+          '|' => AttributeOperator::DashMatch,
+          '^' => AttributeOperator::Prefix,
+          '$' => AttributeOperator::Suffix,
+          '*' => AttributeOperator::Substring,
+          _ => return Err(self.error("expected an attribute operator")),
+        };
+        self.index += 1;
+        if !self.next_is('=') {
+          return Err(self.error("expected '='"));
+        }
+        operator
+      }
+      None => return Err(self.error("unclosed attribute selector")),
+    };
+    self.index += 1;
+    self.skip_whitespace();
+
+    let value = match self.peek() {
+      Some(quote @ ('"' | '\'')) => {
+        self.index += 1;
+        let mut value = String::new();
+        loop {
+          match self.peek() {
+            Some(c) if c == quote => break,
+            Some('\\') if self.index + 1 < self.input.len() => {
+              value.push(self.input[self.index + 1]);
+              self.index += 2;
+            }
+            Some(c) => {
+              value.push(c);
+              self.index += 1;
+            }
+            None => return Err(self.error("unclosed string")),
+          }
+        }
+        self.index += 1;
+        value
+      }
+      _ => self.identifier()?,
+    };
+
+    self.skip_whitespace();
+    // [lang=en i] matches "EN" too, an s flag keeps the default
+    let ignore_case = match self.peek() {
+      Some(flag @ ('i' | 'I' | 's' | 'S')) => {
+        self.index += 1;
+        self.skip_whitespace();
+        flag.eq_ignore_ascii_case(&'i')
+      }
+      _ => false,
+    };
+    if !self.next_is(']') {
+      return Err(self.error("unclosed attribute selector"));
+    }
+    self.index += 1;
+    Ok(Condition::Attribute(
+      name,
+      Some((operator, value, ignore_case)),
+    ))
+  }
+
+  // runs after the ':'
+  fn pseudo_class(&mut self) -> Result<Condition, SelectorError> {
+    let start = self.index;
+    let name = self.identifier()?.to_ascii_lowercase();
+
+    match name.as_str() {
+      "first-child" => Ok(Condition::FirstChild),
+      "nth-child" if self.next_is('(') => {
+        self.index += 1;
+        let argument_start = self.index;
+        while self.peek().is_some_and(|c| c != ')') {
+          self.index += 1;
+        }
+        if !self.next_is(')') {
+          return Err(self.error("unclosed :nth-child()"));
+        }
+
+        let argument: String = self.input[argument_start..self.index].iter().collect();
+        self.index += 1;
+        match parse_nth(&argument) {
+          Some((a, b)) => Ok(Condition::NthChild(a, b)),
+          None => {
+            self.index = argument_start;
+            Err(self.error("invalid :nth-child() argument"))
+          }
+        }
+      }
+      _ => {
+        self.index = start;
+        Err(self.error(&format!("unsupported pseudo-class :{name}")))
+      }
+    }
+  }
+
+  // a CSS identifier; backslash escapes take the next character as is
+  fn identifier(&mut self) -> Result<String, SelectorError> {
+    let mut name = String::new();
+    loop {
+      match self.peek() {
+        Some('\\') if self.index + 1 < self.input.len() => {
+          name.push(self.input[self.index + 1]);
+          self.index += 2;
+        }
+        Some(c) if is_name_char(c) => {
+          name.push(c);
+          self.index += 1;
+        }
+        _ => break,
+      }
+    }
+
+    if name.is_empty() {
+      return Err(self.error("expected a name"));
+    }
+    Ok(name)
+  }
+
+  fn skip_whitespace(&mut self) -> bool {
+    let start = self.index;
+    while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+      self.index += 1;
+    }
+    self.index > start
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.input.get(self.index).copied()
+  }
+
+  fn next_is(&self, c: char) -> bool {
+    self.peek() == Some(c)
+  }
+
+  fn error(&self, message: &str) -> SelectorError {
+    SelectorError {
+      message: message.to_string(),
+      position: self.index,
+    }
+  }
+}
+
+fn is_name_char(c: char) -> bool {
+  c.is_ascii_alphanumeric() || c == '-' || c == '_' || !c.is_ascii()
+}
+
+// the An+B notation: "odd", "even", "3", "2n+1", "-n+3"
+fn parse_nth(argument: &str) -> Option<(i64, i64)> {
+  let argument: String = argument
+    .chars()
+    .filter(|c| !c.is_ascii_whitespace())
+    .collect::<String>()
+    .to_ascii_lowercase();
+
+  match argument.as_str() {
+    "odd" => return Some((2, 1)),
+    "even" => return Some((2, 0)),
+    _ => (),
+  }
+
+  let Some((a, b)) = argument.split_once('n') else {
+    return Some((0, argument.parse().ok()?));
+  };
+
+  let a = match a {
+    "" | "+" => 1,
+    "-" => -1,
+    a => a.parse().ok()?,
+  };
+  let b = match b {
+    "" => 0,
+    b if b.starts_with(['+', '-']) => b.parse().ok()?,
+    _ => return None,
+  };
+  Some((a, b))
+}
+
+// the parser lowercases html attribute names, so selectors match them in
+// any case
+fn attribute<'a>(node: &'a Node, name: &str) -> Option<&'a str> {
+  match node {
+    Node::Element(element) if element.namespace == HTML_NAMESPACE => {
+      element.attribute(&name.to_ascii_lowercase())
+    }
+    Node::Element(element) => element.attribute(name),
+    _ => None,
+  }
+}
+
+// html tag names are case-insensitive, svg and mathml ones are not
+fn has_tag(node: &Node, tag: &str) -> bool {
+  match node {
+    Node::Element(element) if element.namespace == HTML_NAMESPACE => {
+      element.tag.eq_ignore_ascii_case(tag)
+    }
+    Node::Element(element) => element.tag == tag,
+    _ => false,
+  }
+}
+
+fn has_class(node: &Node, name: &str) -> bool {
+  attribute(node, "class")
+    .is_some_and(|classes| classes.split_ascii_whitespace().any(|class| class == name))
+}
+
+fn parent_element(dom: &Dom, node: NodeId) -> Option<NodeId> {
+  dom
+    .parent(node)
+    .filter(|&parent| matches!(&dom[parent], Node::Element(_)))
+}
+
+// the element siblings before the node, nearest first
+fn previous_elements(dom: &Dom, node: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+  dom
+    .previous_siblings(node)
+    .filter(|&sibling| matches!(&dom[sibling], Node::Element(_)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rendering::HTMLParser;
+
+  const PAGE: &str = "<div id=a class='x y'>\
+    <p id=p1 lang=en-US title='hello world' data-v=Abc>1</p>\
+    <p id=p2 lang=en>2</p>\
+    <span id=s1></span>\
+    <p id=p3 lang=english>3</p>\
+    <div id=b><p id=p4></p></div>\
+    </div>";
+
+  // the ids of the matches, in document order
+  fn ids(html: &str, selectors: &str) -> Vec<String> {
+    let dom = HTMLParser::new(html.to_string()).parse();
+    query_selector_all(&dom, dom.document(), selectors)
+      .unwrap()
+      .into_iter()
+      .filter_map(|node| attribute(&dom[node], "id").map(str::to_string))
+      .collect()
+  }
+
+  #[test]
+  fn combinators() {
+    let cases: [(&str, &[&str]); 9] = [
+      ("div p", &["p1", "p2", "p3", "p4"]),
+      ("#b p", &["p4"]),
+      ("#a > p", &["p1", "p2", "p3"]),
+      ("#a>p", &["p1", "p2", "p3"]),
+      ("body > div p", &["p1", "p2", "p3", "p4"]),
+      ("#p2 + span", &["s1"]),
+      ("#p2 + p", &[]),
+      ("#p1 ~ p", &["p2", "p3"]),
+      ("#p4, #s1", &["s1", "p4"]),
+    ];
+    for (selector, expected) in cases {
+      assert_eq!(ids(PAGE, selector), expected, "{selector}");
+    }
+  }
+
+  #[test]
+  fn attribute_operators() {
+    let cases: [(&str, &[&str]); 16] = [
+      ("[lang]", &["p1", "p2", "p3"]),
+      ("[lang=en]", &["p2"]),
+      ("p[lang='en']", &["p2"]),
+      ("[title~=world]", &["p1"]),
+      ("[title~=wor]", &[]),
+      ("[lang|=en]", &["p1", "p2"]),
+      ("[lang^=en]", &["p1", "p2", "p3"]),
+      ("[lang^='']", &[]),
+      ("[lang$=US]", &["p1"]),
+      ("[lang$=us]", &[]),
+      ("[title*='lo wo']", &["p1"]),
+      ("[data-v=abc]", &[]),
+      ("[data-v=abc i]", &["p1"]),
+      ("[data-v='abc' I ]", &["p1"]),
+      ("[data-v=abc s]", &[]),
+      ("[DATA-V=Abc]", &["p1"]),
+    ];
+    for (selector, expected) in cases {
+      assert_eq!(ids(PAGE, selector), expected, "{selector}");
+    }
+    assert_eq!(ids(PAGE, ".x.y"), ["a"]);
+    assert_eq!(ids(PAGE, "div.y#a"), ["a"]);
+  }
+
+  #[test]
+  fn nth_child_arguments() {
+    let cases = [
+      ("odd", Some((2, 1))),
+      ("even", Some((2, 0))),
+      ("EVEN", Some((2, 0))),
+      ("3", Some((0, 3))),
+      ("n", Some((1, 0))),
+      ("-n+3", Some((-1, 3))),
+      ("2n+1", Some((2, 1))),
+      (" 2n + 1 ", Some((2, 1))),
+      ("+n-1", Some((1, -1))),
+      ("2n1", None),
+      ("x", None),
+      ("", None),
+    ];
+    for (argument, expected) in cases {
+      assert_eq!(parse_nth(argument), expected, "{argument:?}");
+    }
+
+    let list = "<ul><li id=1><li id=2><li id=3><li id=4><li id=5></ul>";
+    assert_eq!(ids(list, "li:nth-child(odd)"), ["1", "3", "5"]);
+    assert_eq!(ids(list, "li:nth-child(2n+1)"), ["1", "3", "5"]);
+    assert_eq!(ids(list, "li:nth-child( 2n + 0 )"), ["2", "4"]);
+    assert_eq!(ids(list, "li:nth-child(-n+3)"), ["1", "2", "3"]);
+    assert_eq!(ids(list, "li:nth-child(4)"), ["4"]);
+    assert_eq!(ids(list, "li:first-child"), ["1"]);
+  }
+
+  #[test]
+  fn errors_point_at_the_problem() {
+    let cases = [
+      ("", "expected a selector", 0),
+      ("p >", "expected a selector", 3),
+      ("p,,a", "expected a selector", 2),
+      ("p)", "unexpected character", 1),
+      ("p..a", "expected a name", 2),
+      ("[a", "unclosed attribute selector", 2),
+      ("[a!=b]", "expected an attribute operator", 2),
+      ("[a~b]", "expected '='", 3),
+      ("[a='b]", "unclosed string", 6),
+      ("[a=b x]", "unclosed attribute selector", 5),
+      (":hover", "unsupported pseudo-class :hover", 1),
+      ("li:nth-child(x)", "invalid :nth-child() argument", 13),
+      ("li:nth-child(1", "unclosed :nth-child()", 14),
+    ];
+    for (selector, message, position) in cases {
+      let error = Selector::parse(selector).unwrap_err();
+      assert_eq!(
+        (error.message.as_str(), error.position),
+        (message, position),
+        "{selector:?}"
+      );
+    }
+  }
+}
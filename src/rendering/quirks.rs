@@ -0,0 +1,170 @@
+use crate::rendering::tokenizer::Doctype;
+use crate::utils::DocumentMode;
+
+// public identifiers that start with any of these put the document in quirks
+// mode, from the WHATWG "initial" insertion mode
+const QUIRKS_PUBLIC_PREFIXES: [&str; 55] = [
+  "+//silmaril//dtd html pro v0r11 19970101//",
+  "-//as//dtd html 3.0 aswedit + extensions//",
+  "-//advasoft ltd//dtd html 3.0 aswedit + extensions//",
+  "-//ietf//dtd html 2.0 level 1//",
+  "-//ietf//dtd html 2.0 level 2//",
+  "-//ietf//dtd html 2.0 strict level 1//",
+  "-//ietf//dtd html 2.0 strict level 2//",
+  "-//ietf//dtd html 2.0 strict//",
+  "-//ietf//dtd html 2.0//",
+  "-//ietf//dtd html 2.1e//",
+  "-//ietf//dtd html 3.0//",
+  "-//ietf//dtd html 3.2 final//",
+  "-//ietf//dtd html 3.2//",
+  "-//ietf//dtd html 3//",
+  "-//ietf//dtd html level 0//",
+  "-//ietf//dtd html level 1//",
+  "-//ietf//dtd html level 2//",
+  "-//ietf//dtd html level 3//",
+  "-//ietf//dtd html strict level 0//",
+  "-//ietf//dtd html strict level 1//",
+  "-//ietf//dtd html strict level 2//",
+  "-//ietf//dtd html strict level 3//",
+  "-//ietf//dtd html strict//",
+  "-//ietf//dtd html//",
+  "-//metrius//dtd metrius presentational//",
+  "-//microsoft//dtd internet explorer 2.0 html strict//",
+  "-//microsoft//dtd internet explorer 2.0 html//",
+  "-//microsoft//dtd internet explorer 2.0 tables//",
+  "-//microsoft//dtd internet explorer 3.0 html strict//",
+  "-//microsoft//dtd internet explorer 3.0 html//",
+  "-//microsoft//dtd internet explorer 3.0 tables//",
+  "-//netscape comm. corp.//dtd html//",
+  "-//netscape comm. corp.//dtd strict html//",
+  "-//o'reilly and associates//dtd html 2.0//",
+  "-//o'reilly and associates//dtd html extended 1.0//",
+  "-//o'reilly and associates//dtd html extended relaxed 1.0//",
+  "-//sq//dtd html 2.0 hotmetal + extensions//",
+  "-//softquad software//dtd hotmetal pro 6.0::19990601::extensions to html 4.0//",
+  "-//softquad//dtd hotmetal pro 4.0::19971010::extensions to html 4.0//",
+  "-//spyglass//dtd html 2.0 extended//",
+  "-//sun microsystems corp.//dtd hotjava html//",
+  "-//sun microsystems corp.//dtd hotjava strict html//",
+  "-//w3c//dtd html 3 1995-03-24//",
+  "-//w3c//dtd html 3.2 draft//",
+  "-//w3c//dtd html 3.2 final//",
+  "-//w3c//dtd html 3.2//",
+  "-//w3c//dtd html 3.2s draft//",
+  "-//w3c//dtd html 4.0 frameset//",
+  "-//w3c//dtd html 4.0 transitional//",
+  "-//w3c//dtd html experimental 19960712//",
+  "-//w3c//dtd html experimental 970421//",
+  "-//w3c//dtd w3 html//",
+  "-//w3o//dtd w3 html 3.0//",
+  "-//webtechs//dtd mozilla html 2.0//",
+  "-//webtechs//dtd mozilla html//",
+];
+
+const QUIRKS_PUBLIC_IDS: [&str; 3] = [
+  "-//w3o//dtd w3 html strict 3.0//en//",
+  "-/w3c/dtd html 4.0 transitional/en",
+  "html",
+];
+
+const QUIRKS_SYSTEM_ID: &str = "http://www.ibm.com/data/dtd/v11/ibmxhtml1-transitional.dtd";
+
+// quirks without a system identifier, limited quirks with one
+const HTML4_PUBLIC_PREFIXES: [&str; 2] = [
+  "-//w3c//dtd html 4.01 frameset//",
+  "-//w3c//dtd html 4.01 transitional//",
+];
+
+const LIMITED_QUIRKS_PUBLIC_PREFIXES: [&str; 2] = [
+  "-//w3c//dtd xhtml 1.0 frameset//",
+  "-//w3c//dtd xhtml 1.0 transitional//",
+];
+
+// a missing doctype is treated like an ancient one
+pub fn document_mode(doctype: Option<&Doctype>) -> DocumentMode {
+  let Some(doctype) = doctype else {
+    return DocumentMode::Quirks;
+  };
+
+  let public_id = doctype.public_id.as_deref().map(str::to_ascii_lowercase);
+  let system_id = doctype.system_id.as_deref().map(str::to_ascii_lowercase);
+  let public = public_id.as_deref().unwrap_or("");
+  let starts_with = |prefixes: &[&str]| prefixes.iter().any(|prefix| public.starts_with(prefix));
+
+  if doctype.force_quirks
+    || doctype.name.as_deref() != Some("html")
+    || QUIRKS_PUBLIC_IDS.contains(&public)
+    || system_id.as_deref() == Some(QUIRKS_SYSTEM_ID)
+    || starts_with(&QUIRKS_PUBLIC_PREFIXES)
+    || (system_id.is_none() && starts_with(&HTML4_PUBLIC_PREFIXES))
+  {
+    DocumentMode::Quirks
+  } else if starts_with(&LIMITED_QUIRKS_PUBLIC_PREFIXES)
+    || (system_id.is_some() && starts_with(&HTML4_PUBLIC_PREFIXES))
+  {
+    DocumentMode::LimitedQuirks
+  } else {
+    DocumentMode::NoQuirks
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rendering::HTMLParser;
+
+  fn mode(input: &str) -> DocumentMode {
+    HTMLParser::new(format!("{input}<p>x")).parse().mode()
+  }
+
+  #[test]
+  fn no_quirks() {
+    assert_eq!(mode("<!DOCTYPE html>"), DocumentMode::NoQuirks);
+    assert_eq!(mode("<!doctype HTML>"), DocumentMode::NoQuirks);
+    assert_eq!(
+      mode("<!DOCTYPE html SYSTEM \"about:legacy-compat\">"),
+      DocumentMode::NoQuirks
+    );
+    assert_eq!(
+      mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\">"),
+      DocumentMode::NoQuirks
+    );
+  }
+
+  #[test]
+  fn quirks() {
+    assert_eq!(mode(""), DocumentMode::Quirks);
+    assert_eq!(mode("<!DOCTYPE>"), DocumentMode::Quirks);
+    assert_eq!(mode("<!DOCTYPE svg>"), DocumentMode::Quirks);
+    assert_eq!(
+      mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 3.2 Final//EN\">"),
+      DocumentMode::Quirks
+    );
+    assert_eq!(
+      mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\">"),
+      DocumentMode::Quirks
+    );
+  }
+
+  #[test]
+  fn limited_quirks() {
+    assert_eq!(
+      mode(
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01 Transitional//EN\" \"http://www.w3.org/TR/html4/loose.dtd\">"
+      ),
+      DocumentMode::LimitedQuirks
+    );
+    assert_eq!(
+      mode("<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"x\">"),
+      DocumentMode::LimitedQuirks
+    );
+  }
+
+  #[test]
+  fn only_the_first_doctype_counts() {
+    assert_eq!(
+      mode("<!DOCTYPE html><!DOCTYPE html PUBLIC \"html\">"),
+      DocumentMode::NoQuirks
+    );
+  }
+}
@@ -0,0 +1,333 @@
+use crate::utils::{Dom, HTML_NAMESPACE, Node, NodeId};
+
+// https://html.spec.whatwg.org/multipage/parsing.html#serialising-html-fragments
+
+// written without children or an end tag
+const VOID_TAGS: [&str; 18] = [
+  "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+  "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+// their text is written out as-is, it was never decoded when parsing
+const RAW_TEXT_TAGS: [&str; 7] = [
+  "iframe",
+  "noembed",
+  "noframes",
+  "plaintext",
+  "script",
+  "style",
+  "xmp",
+];
+
+// the parser drops a newline right after these start tags, so a text child
+// starting with one needs an extra newline to survive a round trip
+const LEADING_NEWLINE_TAGS: [&str; 3] = ["listing", "pre", "textarea"];
+
+// whitespace between these and around them is not rendered, so pretty_html
+// may put them on lines of their own; anything else may be phrasing content
+const BLOCK_TAGS: [&str; 44] = [
+  "address",
+  "article",
+  "aside",
+  "blockquote",
+  "body",
+  "caption",
+  "colgroup",
+  "dd",
+  "details",
+  "dialog",
+  "div",
+  "dl",
+  "dt",
+  "fieldset",
+  "figcaption",
+  "figure",
+  "footer",
+  "form",
+  "h1",
+  "h2",
+  "h3",
+  "h4",
+  "h5",
+  "h6",
+  "head",
+  "header",
+  "hgroup",
+  "hr",
+  "html",
+  "li",
+  "main",
+  "menu",
+  "nav",
+  "ol",
+  "p",
+  "section",
+  "table",
+  "tbody",
+  "td",
+  "tfoot",
+  "th",
+  "thead",
+  "tr",
+  "ul",
+];
+
+pub fn outer_html(dom: &Dom, node: NodeId) -> String {
+  let mut out = String::new();
+  write_node(dom, node, &mut out, false, None);
+  out
+}
+
+pub fn inner_html(dom: &Dom, node: NodeId) -> String {
+  let mut out = String::new();
+  write_children(dom, node, &mut out, None);
+  out
+}
+
+// like outer_html, but block elements holding only other block elements put
+// each child on its own indented line. Text and phrasing content are written
+// compactly, new whitespace there would render as a space.
+pub fn pretty_html(dom: &Dom, node: NodeId) -> String {
+  let mut out = String::new();
+  write_node(dom, node, &mut out, false, Some(0));
+  if !out.ends_with('\n') {
+    out.push('\n');
+  }
+  out
+}
+
+fn write_node(dom: &Dom, node: NodeId, out: &mut String, raw: bool, indent: Option<usize>) {
+  match &dom[node] {
+    Node::Document(_) => write_children(dom, node, out, indent),
+    Node::Doctype(doctype) => {
+      out.push_str("<!DOCTYPE ");
+      out.push_str(&doctype.name);
+      if !doctype.public_id.is_empty() {
+        out.push_str(&format!(" PUBLIC {}", quote_id(&doctype.public_id)));
+        if !doctype.system_id.is_empty() {
+          out.push_str(&format!(" {}", quote_id(&doctype.system_id)));
+        }
+      } else if !doctype.system_id.is_empty() {
+        out.push_str(&format!(" SYSTEM {}", quote_id(&doctype.system_id)));
+      }
+      out.push('>');
+    }
+    Node::Text(text) if raw => out.push_str(&text.text),
+    Node::Text(text) => out.push_str(&escape_text(&text.text)),
+    Node::Comment(comment) => {
+      out.push_str("<!--");
+      out.push_str(&comment.text);
+      out.push_str("-->");
+    }
+    Node::ProcessingInstruction(instruction) => {
+      out.push_str("<?");
+      out.push_str(&instruction.target);
+      if !instruction.data.is_empty() {
+        out.push(' ');
+        out.push_str(&instruction.data);
+      }
+      out.push_str("?>");
+    }
+    Node::Element(element) => {
+      out.push('<');
+      out.push_str(&element.tag);
+
+      for (name, value) in &element.attributes {
+        out.push_str(&format!(" {}=\"{}\"", name, escape_attribute(value)));
+      }
+      out.push('>');
+
+      // svg and mathml elements never use the html tag rules, a <title>
+      // in <svg> is written like any other element
+      let html = element.namespace == HTML_NAMESPACE;
+      if html && VOID_TAGS.contains(&element.tag.as_str()) {
+        return;
+      }
+
+      if html
+        && LEADING_NEWLINE_TAGS.contains(&element.tag.as_str())
+        && let Some(first) = dom.first_child(node)
+        && let Node::Text(text) = &dom[first]
+        && text.text.starts_with('\n')
+      {
+        out.push('\n');
+      }
+
+      let indent = indent.filter(|_| {
+        !html
+          || !RAW_TEXT_TAGS.contains(&element.tag.as_str())
+            && !LEADING_NEWLINE_TAGS.contains(&element.tag.as_str())
+      });
+      write_children(dom, node, out, indent);
+
+      if let Some(indent) = indent
+        && indents_children(dom, node)
+      {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent));
+      }
+      out.push_str("</");
+      out.push_str(&element.tag);
+      out.push('>');
+    }
+  }
+}
+
+fn write_children(dom: &Dom, node: NodeId, out: &mut String, indent: Option<usize>) {
+  let raw = match &dom[node] {
+    Node::Element(element) => {
+      element.namespace == HTML_NAMESPACE && RAW_TEXT_TAGS.contains(&element.tag.as_str())
+    }
+    _ => false,
+  };
+  let is_document = matches!(&dom[node], Node::Document(_));
+
+  let indent = indent.filter(|_| indents_children(dom, node));
+
+  for (i, child) in dom.children(node).enumerate() {
+    if let Some(indent) = indent {
+      if is_document {
+        if i > 0 {
+          out.push('\n');
+        }
+        write_node(dom, child, out, raw, Some(indent));
+      } else {
+        out.push('\n');
+        out.push_str(&" ".repeat(indent + 2));
+        write_node(dom, child, out, raw, Some(indent + 2));
+      }
+    } else {
+      write_node(dom, child, out, raw, None);
+    }
+  }
+}
+
+fn indents_children(dom: &Dom, node: NodeId) -> bool {
+  let is_block = |node: NodeId| match &dom[node] {
+    Node::Element(element) => {
+      element.namespace == HTML_NAMESPACE && BLOCK_TAGS.contains(&element.tag.as_str())
+    }
+    Node::Text(_) => false,
+    _ => true,
+  };
+
+  match &dom[node] {
+    Node::Document(_) => dom.has_children(node),
+    // nothing in the head is rendered
+    Node::Element(element) if element.tag == "head" && element.namespace == HTML_NAMESPACE => {
+      dom.has_children(node)
+        && dom
+          .children(node)
+          .all(|child| !matches!(&dom[child], Node::Text(_)))
+    }
+    _ => is_block(node) && dom.has_children(node) && dom.children(node).all(is_block),
+  }
+}
+
+fn escape_text(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('\u{a0}', "&nbsp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+fn escape_attribute(value: &str) -> String {
+  value
+    .replace('&', "&amp;")
+    .replace('\u{a0}', "&nbsp;")
+    .replace('"', "&quot;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+}
+
+// identifiers can't contain the quote they're written in, one holding a "
+// was written with ' in the source
+fn quote_id(id: &str) -> String {
+  if id.contains('"') {
+    format!("'{id}'")
+  } else {
+    format!("\"{id}\"")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rendering::HTMLParser;
+
+  fn serialize(input: &str) -> String {
+    let dom = HTMLParser::new(input.to_string()).parse();
+    outer_html(&dom, dom.document())
+  }
+
+  #[test]
+  fn canonical_markup_is_written_back_byte_for_byte() {
+    let inputs = [
+      "<!DOCTYPE html><html><head><title>a &amp; b</title></head><body><p class=\"x\" id=\"y\">1<br>2</p></body></html>",
+      "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" \"http://www.w3.org/TR/html4/strict.dtd\"><html><body></body></html>",
+      "<html><body><p id=\"y\" class=\"x\" data-b=\"1\" data-a=\"2\"></p></body></html>",
+      "<!--a--><html><body><script>if (a < b && c) {}</script><!--b--></body></html>",
+      "<html><body><pre>\n\nx</pre><textarea>&lt;/textarea&gt;</textarea></body></html>",
+      "<html><body><p title=\"&quot;&amp;&nbsp;\">&lt;&gt;&nbsp;</p></body></html>",
+    ];
+    for input in inputs {
+      assert_eq!(serialize(input), input);
+    }
+  }
+
+  #[test]
+  fn serializing_a_reparse_changes_nothing() {
+    let inputs = [
+      "<p>a<p>b<table>x<tr><td>1</table>",
+      "<b>1<i>2</b>3</i><a href=?a=1&b=2>q</a>",
+      "<ul><li>a<li><!-- c -->b</ul><select><option>1<option>2</select>",
+      "<!DOCTYPE html SYSTEM 'a\"b'><title><x></title><xmp>&lt;</xmp>",
+    ];
+    for input in inputs {
+      let once = serialize(input);
+      assert_eq!(serialize(&once), once);
+    }
+  }
+
+  #[test]
+  fn inner_html_leaves_out_the_element() {
+    let dom = HTMLParser::new("<div id=a><p>x</p>y</div>".to_string()).parse();
+    let html = dom.first_child(dom.document()).unwrap();
+    let body = dom.last_child(html).unwrap();
+    let div = dom.first_child(body).unwrap();
+    assert_eq!(inner_html(&dom, div), "<p>x</p>y");
+    assert_eq!(outer_html(&dom, div), "<div id=\"a\"><p>x</p>y</div>");
+  }
+
+  #[test]
+  fn pretty_html_only_indents_between_elements() {
+    let dom = HTMLParser::new("<!DOCTYPE html><ul><li>a <b>b</b></li></ul>".to_string()).parse();
+    assert_eq!(
+      pretty_html(&dom, dom.document()),
+      "<!DOCTYPE html>\n<html>\n  <body>\n    <ul>\n      <li>a <b>b</b></li>\n    </ul>\n  </body>\n</html>\n"
+    );
+  }
+
+  #[test]
+  fn pretty_html_keeps_phrasing_content_together() {
+    let dom = HTMLParser::new(
+      "<html><head><meta charset=utf-8><title>t</title></head><body><p><b>a</b><i>b</i></p><div><span>c</span><div>d</div></div></body></html>".to_string(),
+    )
+    .parse();
+    assert_eq!(
+      pretty_html(&dom, dom.document()),
+      "<html>\n  <head>\n    <meta charset=\"utf-8\">\n    <title>t</title>\n  </head>\n  <body>\n    <p><b>a</b><i>b</i></p>\n    <div><span>c</span><div>d</div></div>\n  </body>\n</html>\n"
+    );
+  }
+
+  #[test]
+  fn foreign_elements_keep_their_case() {
+    assert_eq!(
+      serialize(
+        "<html><body><svg viewBox=\"0 0 1 1\"><circle r=\"1\"></circle><clipPath></clipPath></svg></body></html>"
+      ),
+      "<html><body><svg viewBox=\"0 0 1 1\"><circle r=\"1\"></circle><clipPath></clipPath></svg></body></html>"
+    );
+  }
+}
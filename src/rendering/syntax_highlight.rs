@@ -1,58 +1,78 @@
-use crate::utils::Node;
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::utils::{Dom, Edge, Node, NodeId};
 
-pub fn syntax_highlight(node: &Rc<RefCell<Node>>) -> String {
+pub fn syntax_highlight(dom: &Dom, node: NodeId) -> String {
   let mut result = String::from("<pre>");
-  walk(node, &mut result, 0);
+  let mut depth = 0;
+
+  for edge in dom.traverse(node) {
+    match edge {
+      Edge::Open(node) => match &dom[node] {
+        Node::Document(_) => (),
+        Node::Doctype(d) => line(&mut result, depth, &format_doctype(d)),
+        Node::Text(t) => {
+          let trimmed = t.text.trim();
+          if !trimmed.is_empty() {
+            result.push_str(&indent(depth));
+            result.push_str("<b>");
+            result.push_str(&escape_html(trimmed));
+            result.push_str("</b>");
+            result.push('\n');
+          }
+        }
+        Node::Comment(c) => line(&mut result, depth, &format!("<!--{}-->", c.text)),
+        Node::ProcessingInstruction(p) => {
+          line(&mut result, depth, &format!("<?{} {}?>", p.target, p.data))
+        }
+        Node::Element(e) => {
+          line(&mut result, depth, &format_open_tag(e));
+          depth += 4;
+        }
+      },
+      Edge::Close(node) => {
+        if let Node::Element(e) = &dom[node] {
+          depth -= 4;
+          if dom.has_children(node) {
+            line(&mut result, depth, &format!("</{}>", e.tag));
+          }
+        }
+      }
+    }
+  }
+
   result.push_str("</pre>");
   result
 }
 
-fn walk(node: &Rc<RefCell<Node>>, out: &mut String, depth: usize) {
-  let borrowed = node.borrow();
+fn line(out: &mut String, depth: usize, markup: &str) {
+  out.push_str(&indent(depth));
+  out.push_str(&escape_html(markup));
+  out.push('\n');
+}
 
-  match &*borrowed {
-    crate::utils::Node::Text(t) => {
-      let trimmed = t.text.trim();
-      if trimmed.is_empty() {
-        return;
-      }
-      out.push_str(&indent(depth));
-      out.push_str("<b>");
-      out.push_str(&escape_html(trimmed));
-      out.push_str("</b>");
-      out.push('\n');
-    }
-    crate::utils::Node::Element(e) => {
-      out.push_str(&indent(depth));
-      out.push_str(&escape_html(&format_open_tag(e)));
-      out.push('\n');
+fn indent(depth: usize) -> String {
+  " ".repeat(depth)
+}
 
-      for child in &e.children {
-        walk(child, out, depth + 4);
-      }
+fn format_doctype(d: &crate::utils::Doctype) -> String {
+  let mut s = format!("<!DOCTYPE {}", d.name);
 
-      if !e.children.is_empty() {
-        out.push_str(&indent(depth));
-        out.push_str(&escape_html(&format!("</{}>", e.tag)));
-        out.push('\n');
-      }
+  if !d.public_id.is_empty() {
+    s.push_str(&format!(" PUBLIC \"{}\"", d.public_id));
+    if !d.system_id.is_empty() {
+      s.push_str(&format!(" \"{}\"", d.system_id));
     }
+  } else if !d.system_id.is_empty() {
+    s.push_str(&format!(" SYSTEM \"{}\"", d.system_id));
   }
-}
 
-fn indent(depth: usize) -> String {
-  " ".repeat(depth)
+  s.push('>');
+  s
 }
 
 fn format_open_tag(e: &crate::utils::Element) -> String {
   let mut s = format!("<{}", e.tag);
 
-  let mut attrs: Vec<(&String, &String)> = e.attributes.iter().collect();
-  attrs.sort_by_key(|(k, _)| k.as_str());
-
-  for (key, value) in attrs {
+  for (key, value) in &e.attributes {
     if value.is_empty() {
       s.push_str(&format!(" {}", key));
     } else {
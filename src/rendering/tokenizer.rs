@@ -0,0 +1,1512 @@
+use std::collections::VecDeque;
+
+use crate::rendering::entities::{self, Reference};
+use crate::utils::{Position, Span};
+
+// a subset of the WHATWG tokenizer states, see
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+  Data,
+  RcData,
+  RawText,
+  ScriptData,
+  PlainText,
+  TagOpen,
+  EndTagOpen,
+  TagName,
+  RcDataLessThanSign,
+  RcDataEndTagOpen,
+  RcDataEndTagName,
+  RawTextLessThanSign,
+  RawTextEndTagOpen,
+  RawTextEndTagName,
+  ScriptDataLessThanSign,
+  ScriptDataEndTagOpen,
+  ScriptDataEndTagName,
+  ScriptDataEscapeStart,
+  ScriptDataEscapeStartDash,
+  ScriptDataEscaped,
+  ScriptDataEscapedDash,
+  ScriptDataEscapedDashDash,
+  ScriptDataEscapedLessThanSign,
+  ScriptDataEscapedEndTagOpen,
+  ScriptDataEscapedEndTagName,
+  ScriptDataDoubleEscapeStart,
+  ScriptDataDoubleEscaped,
+  ScriptDataDoubleEscapedDash,
+  ScriptDataDoubleEscapedDashDash,
+  ScriptDataDoubleEscapedLessThanSign,
+  ScriptDataDoubleEscapeEnd,
+  BeforeAttributeName,
+  AttributeName,
+  AfterAttributeName,
+  BeforeAttributeValue,
+  AttributeValueDoubleQuoted,
+  AttributeValueSingleQuoted,
+  AttributeValueUnquoted,
+  AfterAttributeValueQuoted,
+  SelfClosingStartTag,
+  BogusComment,
+  MarkupDeclarationOpen,
+  CommentStart,
+  CommentStartDash,
+  Comment,
+  CommentEndDash,
+  CommentEnd,
+  CommentEndBang,
+  Doctype,
+  BeforeDoctypeName,
+  DoctypeName,
+  AfterDoctypeName,
+  AfterDoctypePublicKeyword,
+  BeforeDoctypePublicIdentifier,
+  DoctypePublicIdentifierDoubleQuoted,
+  DoctypePublicIdentifierSingleQuoted,
+  AfterDoctypePublicIdentifier,
+  BetweenDoctypePublicAndSystemIdentifiers,
+  AfterDoctypeSystemKeyword,
+  BeforeDoctypeSystemIdentifier,
+  DoctypeSystemIdentifierDoubleQuoted,
+  DoctypeSystemIdentifierSingleQuoted,
+  AfterDoctypeSystemIdentifier,
+  BogusDoctype,
+  CdataSection,
+  CdataSectionBracket,
+  CdataSectionEnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+  StartTag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    self_closing: bool,
+  },
+  EndTag {
+    name: String,
+  },
+  Doctype(Doctype),
+  Text(String),
+  Comment(String),
+  Eof,
+}
+
+// a recoverable mistake in the markup, named after the WHATWG error codes
+// where the spec has one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+  pub code: &'static str,
+  pub position: Position,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Doctype {
+  pub name: Option<String>,
+  pub public_id: Option<String>,
+  pub system_id: Option<String>,
+  pub force_quirks: bool,
+}
+
+#[derive(Default)]
+struct TagBuilder {
+  name: String,
+  end: bool,
+  self_closing: bool,
+  attributes: Vec<(String, String)>,
+  attribute: Option<(String, String)>,
+}
+
+// the tokenizer is resumable: `feed` hands it more input, `next_token`
+// returns None when it needs more, and `end` marks the end of the input
+pub struct Tokenizer {
+  input: VecDeque<char>,
+  ended: bool,
+  done: bool,
+  after_cr: bool,
+  state: State,
+  tokens: VecDeque<(Token, Span)>,
+  text: String,
+  tag: TagBuilder,
+  comment: String,
+  doctype: Doctype,
+  buffer: String,
+  last_start_tag: String,
+  // <![CDATA[ only opens a section in svg and mathml content
+  cdata_allowed: bool,
+  // offsets count characters after newline normalisation
+  consumed: usize,
+  fed: usize,
+  line_starts: Vec<usize>,
+  text_start: usize,
+  markup_start: usize,
+  errors: Vec<ParseError>,
+}
+
+impl Default for Tokenizer {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Tokenizer {
+  pub fn new() -> Self {
+    Tokenizer {
+      input: VecDeque::new(),
+      ended: false,
+      done: false,
+      after_cr: false,
+      state: State::Data,
+      tokens: VecDeque::new(),
+      text: String::new(),
+      tag: TagBuilder::default(),
+      comment: String::new(),
+      doctype: Doctype::default(),
+      buffer: String::new(),
+      last_start_tag: String::new(),
+      cdata_allowed: false,
+      consumed: 0,
+      fed: 0,
+      line_starts: vec![0],
+      text_start: 0,
+      markup_start: 0,
+      errors: vec![],
+    }
+  }
+
+  pub fn feed(&mut self, text: &str) {
+    // newlines are normalised before tokenizing: CRLF and CR become LF
+    for c in text.chars() {
+      match c {
+        '\r' => {
+          self.push_input('\n');
+          self.after_cr = true;
+        }
+        '\n' if self.after_cr => self.after_cr = false,
+        c => {
+          self.push_input(c);
+          self.after_cr = false;
+        }
+      }
+    }
+  }
+
+  fn push_input(&mut self, c: char) {
+    self.input.push_back(c);
+    self.fed += 1;
+    if c == '\n' {
+      self.line_starts.push(self.fed);
+    }
+  }
+
+  pub fn end(&mut self) {
+    self.ended = true;
+  }
+
+  // the tree builder switches states, e.g. to script data after <script>
+  pub fn set_state(&mut self, state: State) {
+    self.state = state;
+  }
+
+  // the tree builder allows CDATA sections while the current node is foreign
+  pub fn set_cdata_allowed(&mut self, allowed: bool) {
+    self.cdata_allowed = allowed;
+  }
+
+  // errors found so far, handed over once
+  pub fn take_errors(&mut self) -> Vec<ParseError> {
+    std::mem::take(&mut self.errors)
+  }
+
+  pub fn next_token(&mut self) -> Option<(Token, Span)> {
+    loop {
+      if let Some(token) = self.tokens.pop_front() {
+        return Some(token);
+      }
+
+      if self.done {
+        return None;
+      }
+
+      if !self.step() {
+        // out of input for now; hand over the text we have so far, unless
+        // it's only whitespace. The tree builder drops whitespace that
+        // stands alone, so it has to wait for whatever text follows it.
+        // A CDATA section is handed over whole, so its span takes in the
+        // closing "]]>".
+        let in_cdata = matches!(
+          self.state,
+          State::CdataSection | State::CdataSectionBracket | State::CdataSectionEnd
+        );
+        if self.text.trim().is_empty() || in_cdata {
+          return None;
+        }
+        let end = if self.in_text() {
+          self.consumed
+        } else {
+          self.markup_start
+        };
+        self.flush_text(end);
+        return self.tokens.pop_front();
+      }
+    }
+  }
+
+  fn flush_text(&mut self, end: usize) {
+    if !self.text.is_empty() {
+      let span = self.span(self.text_start, end);
+      self
+        .tokens
+        .push_back((Token::Text(std::mem::take(&mut self.text)), span));
+      self.text_start = end;
+    }
+  }
+
+  // markup tokens run from the '<' that started them to the current character
+  fn emit(&mut self, token: Token) {
+    self.flush_text(self.markup_start);
+    let span = self.span(self.markup_start, self.consumed);
+    self.tokens.push_back((token, span));
+    self.text_start = self.consumed;
+  }
+
+  // whether the tokenizer is between tokens, so everything consumed so far
+  // belongs to the pending text
+  fn in_text(&self) -> bool {
+    matches!(
+      self.state,
+      State::Data
+        | State::RcData
+        | State::RawText
+        | State::ScriptData
+        | State::PlainText
+        | State::ScriptDataEscapeStart
+        | State::ScriptDataEscapeStartDash
+        | State::ScriptDataEscaped
+        | State::ScriptDataEscapedDash
+        | State::ScriptDataEscapedDashDash
+        | State::ScriptDataDoubleEscapeStart
+        | State::ScriptDataDoubleEscaped
+        | State::ScriptDataDoubleEscapedDash
+        | State::ScriptDataDoubleEscapedDashDash
+        | State::ScriptDataDoubleEscapedLessThanSign
+        | State::ScriptDataDoubleEscapeEnd
+        | State::CdataSection
+        | State::CdataSectionBracket
+        | State::CdataSectionEnd
+    )
+  }
+
+  fn position(&self, offset: usize) -> Position {
+    let line = self.line_starts.partition_point(|&start| start <= offset);
+    Position {
+      line,
+      column: offset - self.line_starts[line - 1] + 1,
+    }
+  }
+
+  fn span(&self, start: usize, end: usize) -> Span {
+    Span {
+      start: self.position(start),
+      end: self.position(end),
+    }
+  }
+
+  // reported at the character just consumed
+  fn error(&mut self, code: &'static str) {
+    self.error_at(self.consumed.saturating_sub(1), code);
+  }
+
+  fn error_at(&mut self, offset: usize, code: &'static str) {
+    let error = ParseError {
+      code,
+      position: self.position(offset),
+    };
+
+    // a reconsumed character must not report the same error twice
+    if self.errors.last() != Some(&error) {
+      self.errors.push(error);
+    }
+  }
+
+  fn unread(&mut self, c: char) {
+    self.input.push_front(c);
+    self.consumed -= 1;
+  }
+
+  fn skip(&mut self, count: usize) {
+    self.input.drain(..count);
+    self.consumed += count;
+  }
+
+  fn emit_tag(&mut self) {
+    self.finish_attribute();
+    let tag = std::mem::take(&mut self.tag);
+
+    if tag.end {
+      if !tag.attributes.is_empty() {
+        self.error("end-tag-with-attributes");
+      }
+      if tag.self_closing {
+        self.error("end-tag-with-trailing-solidus");
+      }
+      self.emit(Token::EndTag { name: tag.name });
+    } else {
+      self.last_start_tag = tag.name.clone();
+      self.emit(Token::StartTag {
+        name: tag.name,
+        attributes: tag.attributes,
+        self_closing: tag.self_closing,
+      });
+    }
+  }
+
+  fn emit_doctype(&mut self) {
+    let doctype = std::mem::take(&mut self.doctype);
+    self.emit(Token::Doctype(doctype));
+  }
+
+  fn doctype_name(&mut self) -> &mut String {
+    self.doctype.name.get_or_insert_default()
+  }
+
+  fn emit_comment(&mut self) {
+    let comment = std::mem::take(&mut self.comment);
+    self.emit(Token::Comment(comment));
+  }
+
+  fn start_tag(&mut self, end: bool) {
+    self.tag = TagBuilder {
+      end,
+      ..TagBuilder::default()
+    };
+  }
+
+  fn start_attribute(&mut self) {
+    self.finish_attribute();
+    self.tag.attribute = Some((String::new(), String::new()));
+  }
+
+  // a repeated attribute is dropped, the first occurrence wins
+  fn finish_attribute(&mut self) {
+    let Some((name, value)) = self.tag.attribute.take() else {
+      return;
+    };
+
+    if self
+      .tag
+      .attributes
+      .iter()
+      .any(|(existing, _)| *existing == name)
+    {
+      self.error("duplicate-attribute");
+    } else {
+      self.tag.attributes.push((name, value));
+    }
+  }
+
+  fn attribute_name(&mut self) -> &mut String {
+    &mut self.tag.attribute.get_or_insert_default().0
+  }
+
+  fn attribute_value(&mut self) -> &mut String {
+    &mut self.tag.attribute.get_or_insert_default().1
+  }
+
+  fn is_appropriate_end_tag(&self) -> bool {
+    self.tag.end && self.tag.name == self.last_start_tag
+  }
+
+  // whether the upcoming input starts with `expected`; None means there is
+  // not enough input yet to tell
+  fn lookahead(&mut self, expected: &str, ignore_case: bool) -> Option<bool> {
+    let length = expected.chars().count();
+    let available: String = self.input.iter().take(length).collect();
+
+    let matches = if ignore_case {
+      expected
+        .to_ascii_lowercase()
+        .starts_with(&available.to_ascii_lowercase())
+    } else {
+      expected.starts_with(&available)
+    };
+
+    if !matches {
+      Some(false)
+    } else if available.chars().count() == length {
+      Some(true)
+    } else if self.ended {
+      Some(false)
+    } else {
+      None
+    }
+  }
+
+  // runs after the '&' has been consumed; false means more input is needed
+  fn character_reference(&mut self, in_attribute: bool) -> bool {
+    let reference =
+      entities::match_reference(self.input.make_contiguous(), self.ended, in_attribute);
+
+    let decoded = match reference {
+      Reference::Incomplete => {
+        self.unread('&');
+        return false;
+      }
+      Reference::Literal => String::from("&"),
+      Reference::Decoded(value, consumed) => {
+        self.skip(consumed);
+        value
+      }
+    };
+
+    if in_attribute {
+      self.attribute_value().push_str(&decoded);
+    } else {
+      self.text.push_str(&decoded);
+    }
+    true
+  }
+
+  // processes one input character; false when it has to wait for input
+  fn step(&mut self) -> bool {
+    let Some(c) = self.input.pop_front() else {
+      if !self.ended {
+        return false;
+      }
+      self.end_of_file();
+      return true;
+    };
+    self.consumed += 1;
+
+    if c == '<' && self.in_text() {
+      self.markup_start = self.consumed - 1;
+    }
+    if c == '\0' && self.state != State::CdataSection {
+      self.error("unexpected-null-character");
+    }
+
+    match self.state {
+      State::Data => match c {
+        '&' => return self.character_reference(false),
+        '<' => self.state = State::TagOpen,
+        '\0' => (),
+        c => self.text.push(c),
+      },
+      State::RcData => match c {
+        '&' => return self.character_reference(false),
+        '<' => self.state = State::RcDataLessThanSign,
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::RawText => match c {
+        '<' => self.state = State::RawTextLessThanSign,
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::ScriptData => match c {
+        '<' => self.state = State::ScriptDataLessThanSign,
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::PlainText => match c {
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::TagOpen => match c {
+        '!' => self.state = State::MarkupDeclarationOpen,
+        '/' => self.state = State::EndTagOpen,
+        c if c.is_ascii_alphabetic() => {
+          self.start_tag(false);
+          self.reconsume(c, State::TagName);
+        }
+        '?' => {
+          self.error("unexpected-question-mark-instead-of-tag-name");
+          self.comment.clear();
+          self.reconsume(c, State::BogusComment);
+        }
+        c => {
+          self.error("invalid-first-character-of-tag-name");
+          self.text.push('<');
+          self.reconsume(c, State::Data);
+        }
+      },
+      State::EndTagOpen => match c {
+        c if c.is_ascii_alphabetic() => {
+          self.start_tag(true);
+          self.reconsume(c, State::TagName);
+        }
+        '>' => {
+          self.error("missing-end-tag-name");
+          self.state = State::Data;
+          self.text_start = self.consumed;
+        }
+        c => {
+          self.error("invalid-first-character-of-tag-name");
+          self.comment.clear();
+          self.reconsume(c, State::BogusComment);
+        }
+      },
+      State::TagName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeAttributeName,
+        '/' => self.state = State::SelfClosingStartTag,
+        '>' => {
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        '\0' => self.tag.name.push('\u{fffd}'),
+        c => self.tag.name.push(c.to_ascii_lowercase()),
+      },
+      State::RcDataLessThanSign => {
+        self.raw_less_than_sign(c, State::RcData, State::RcDataEndTagOpen)
+      }
+      State::RcDataEndTagOpen => self.raw_end_tag_open(c, State::RcData, State::RcDataEndTagName),
+      State::RcDataEndTagName => self.raw_end_tag_name(c, State::RcData),
+      State::RawTextLessThanSign => {
+        self.raw_less_than_sign(c, State::RawText, State::RawTextEndTagOpen)
+      }
+      State::RawTextEndTagOpen => {
+        self.raw_end_tag_open(c, State::RawText, State::RawTextEndTagName)
+      }
+      State::RawTextEndTagName => self.raw_end_tag_name(c, State::RawText),
+      State::ScriptDataLessThanSign => match c {
+        // "<!--" inside a script starts an escaped section
+        '!' => {
+          self.text.push_str("<!");
+          self.state = State::ScriptDataEscapeStart;
+        }
+        c => self.raw_less_than_sign(c, State::ScriptData, State::ScriptDataEndTagOpen),
+      },
+      State::ScriptDataEndTagOpen => {
+        self.raw_end_tag_open(c, State::ScriptData, State::ScriptDataEndTagName)
+      }
+      State::ScriptDataEndTagName => self.raw_end_tag_name(c, State::ScriptData),
+      State::ScriptDataEscapeStart => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataEscapeStartDash;
+        }
+        c => self.reconsume(c, State::ScriptData),
+      },
+      State::ScriptDataEscapeStartDash => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataEscapedDashDash;
+        }
+        c => self.reconsume(c, State::ScriptData),
+      },
+      State::ScriptDataEscaped => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataEscapedDash;
+        }
+        '<' => self.state = State::ScriptDataEscapedLessThanSign,
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::ScriptDataEscapedDash => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataEscapedDashDash;
+        }
+        '<' => self.state = State::ScriptDataEscapedLessThanSign,
+        c => {
+          self.text.push(if c == '\0' { '\u{fffd}' } else { c });
+          self.state = State::ScriptDataEscaped;
+        }
+      },
+      State::ScriptDataEscapedDashDash => match c {
+        '-' => self.text.push('-'),
+        '<' => self.state = State::ScriptDataEscapedLessThanSign,
+        '>' => {
+          self.text.push('>');
+          self.state = State::ScriptData;
+        }
+        c => {
+          self.text.push(if c == '\0' { '\u{fffd}' } else { c });
+          self.state = State::ScriptDataEscaped;
+        }
+      },
+      State::ScriptDataEscapedLessThanSign => match c {
+        '/' => {
+          self.buffer.clear();
+          self.state = State::ScriptDataEscapedEndTagOpen;
+        }
+        c if c.is_ascii_alphabetic() => {
+          self.buffer.clear();
+          self.text.push('<');
+          self.reconsume(c, State::ScriptDataDoubleEscapeStart);
+        }
+        c => {
+          self.text.push('<');
+          self.reconsume(c, State::ScriptDataEscaped);
+        }
+      },
+      State::ScriptDataEscapedEndTagOpen => self.raw_end_tag_open(
+        c,
+        State::ScriptDataEscaped,
+        State::ScriptDataEscapedEndTagName,
+      ),
+      State::ScriptDataEscapedEndTagName => self.raw_end_tag_name(c, State::ScriptDataEscaped),
+      State::ScriptDataDoubleEscapeStart => match c {
+        '\t' | '\n' | '\x0c' | ' ' | '/' | '>' => {
+          self.state = if self.buffer == "script" {
+            State::ScriptDataDoubleEscaped
+          } else {
+            State::ScriptDataEscaped
+          };
+          self.text.push(c);
+        }
+        c if c.is_ascii_alphabetic() => {
+          self.buffer.push(c.to_ascii_lowercase());
+          self.text.push(c);
+        }
+        c => self.reconsume(c, State::ScriptDataEscaped),
+      },
+      State::ScriptDataDoubleEscaped => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataDoubleEscapedDash;
+        }
+        '<' => {
+          self.text.push('<');
+          self.state = State::ScriptDataDoubleEscapedLessThanSign;
+        }
+        '\0' => self.text.push('\u{fffd}'),
+        c => self.text.push(c),
+      },
+      State::ScriptDataDoubleEscapedDash => match c {
+        '-' => {
+          self.text.push('-');
+          self.state = State::ScriptDataDoubleEscapedDashDash;
+        }
+        '<' => {
+          self.text.push('<');
+          self.state = State::ScriptDataDoubleEscapedLessThanSign;
+        }
+        c => {
+          self.text.push(if c == '\0' { '\u{fffd}' } else { c });
+          self.state = State::ScriptDataDoubleEscaped;
+        }
+      },
+      State::ScriptDataDoubleEscapedDashDash => match c {
+        '-' => self.text.push('-'),
+        '<' => {
+          self.text.push('<');
+          self.state = State::ScriptDataDoubleEscapedLessThanSign;
+        }
+        '>' => {
+          self.text.push('>');
+          self.state = State::ScriptData;
+        }
+        c => {
+          self.text.push(if c == '\0' { '\u{fffd}' } else { c });
+          self.state = State::ScriptDataDoubleEscaped;
+        }
+      },
+      State::ScriptDataDoubleEscapedLessThanSign => match c {
+        '/' => {
+          self.buffer.clear();
+          self.text.push('/');
+          self.state = State::ScriptDataDoubleEscapeEnd;
+        }
+        c => self.reconsume(c, State::ScriptDataDoubleEscaped),
+      },
+      State::ScriptDataDoubleEscapeEnd => match c {
+        '\t' | '\n' | '\x0c' | ' ' | '/' | '>' => {
+          self.state = if self.buffer == "script" {
+            State::ScriptDataEscaped
+          } else {
+            State::ScriptDataDoubleEscaped
+          };
+          self.text.push(c);
+        }
+        c if c.is_ascii_alphabetic() => {
+          self.buffer.push(c.to_ascii_lowercase());
+          self.text.push(c);
+        }
+        c => self.reconsume(c, State::ScriptDataDoubleEscaped),
+      },
+      State::BeforeAttributeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '/' | '>' => self.reconsume(c, State::AfterAttributeName),
+        '=' => {
+          self.error("unexpected-equals-sign-before-attribute-name");
+          self.start_attribute();
+          self.attribute_name().push(c);
+          self.state = State::AttributeName;
+        }
+        c => {
+          self.start_attribute();
+          self.reconsume(c, State::AttributeName);
+        }
+      },
+      State::AttributeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' | '/' | '>' => self.reconsume(c, State::AfterAttributeName),
+        '=' => self.state = State::BeforeAttributeValue,
+        '\0' => self.attribute_name().push('\u{fffd}'),
+        c => {
+          if matches!(c, '"' | '\'' | '<') {
+            self.error("unexpected-character-in-attribute-name");
+          }
+          self.attribute_name().push(c.to_ascii_lowercase());
+        }
+      },
+      State::AfterAttributeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '/' => self.state = State::SelfClosingStartTag,
+        '=' => self.state = State::BeforeAttributeValue,
+        '>' => {
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        c => {
+          self.start_attribute();
+          self.reconsume(c, State::AttributeName);
+        }
+      },
+      State::BeforeAttributeValue => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '"' => self.state = State::AttributeValueDoubleQuoted,
+        '\'' => self.state = State::AttributeValueSingleQuoted,
+        '>' => {
+          self.error("missing-attribute-value");
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        c => self.reconsume(c, State::AttributeValueUnquoted),
+      },
+      State::AttributeValueDoubleQuoted => match c {
+        '"' => self.state = State::AfterAttributeValueQuoted,
+        '&' => return self.character_reference(true),
+        '\0' => self.attribute_value().push('\u{fffd}'),
+        c => self.attribute_value().push(c),
+      },
+      State::AttributeValueSingleQuoted => match c {
+        '\'' => self.state = State::AfterAttributeValueQuoted,
+        '&' => return self.character_reference(true),
+        '\0' => self.attribute_value().push('\u{fffd}'),
+        c => self.attribute_value().push(c),
+      },
+      State::AttributeValueUnquoted => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeAttributeName,
+        '&' => return self.character_reference(true),
+        '>' => {
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        '\0' => self.attribute_value().push('\u{fffd}'),
+        c => {
+          if matches!(c, '"' | '\'' | '<' | '=' | '`') {
+            self.error("unexpected-character-in-unquoted-attribute-value");
+          }
+          self.attribute_value().push(c);
+        }
+      },
+      State::AfterAttributeValueQuoted => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeAttributeName,
+        '/' => self.state = State::SelfClosingStartTag,
+        '>' => {
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        c => {
+          self.error("missing-whitespace-between-attributes");
+          self.reconsume(c, State::BeforeAttributeName);
+        }
+      },
+      State::SelfClosingStartTag => match c {
+        '>' => {
+          self.tag.self_closing = true;
+          self.state = State::Data;
+          self.emit_tag();
+        }
+        c => {
+          self.error("unexpected-solidus-in-tag");
+          self.reconsume(c, State::BeforeAttributeName);
+        }
+      },
+      State::BogusComment => match c {
+        '>' => {
+          self.state = State::Data;
+          self.emit_comment();
+        }
+        '\0' => self.comment.push('\u{fffd}'),
+        c => self.comment.push(c),
+      },
+      State::MarkupDeclarationOpen => {
+        self.unread(c);
+
+        match (
+          self.lookahead("--", false),
+          self.lookahead("DOCTYPE", true),
+          self.lookahead("[CDATA[", false),
+        ) {
+          (Some(true), _, _) => {
+            self.skip(2);
+            self.comment.clear();
+            self.state = State::CommentStart;
+          }
+          (_, Some(true), _) => {
+            self.skip(7);
+            self.state = State::Doctype;
+          }
+          (_, _, Some(true)) if self.cdata_allowed => {
+            self.skip(7);
+            self.state = State::CdataSection;
+          }
+          // in html content it becomes a bogus comment
+          (_, _, Some(true)) => {
+            self.skip(7);
+            self.error("cdata-in-html-content");
+            self.comment = String::from("[CDATA[");
+            self.state = State::BogusComment;
+          }
+          (None, _, _) | (_, None, _) | (_, _, None) => return false,
+          (Some(false), Some(false), Some(false)) => {
+            self.error_at(self.consumed, "incorrectly-opened-comment");
+            self.comment.clear();
+            self.state = State::BogusComment;
+          }
+        }
+      }
+      State::Doctype => {
+        self.doctype = Doctype::default();
+        match c {
+          '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeDoctypeName,
+          '>' => self.reconsume(c, State::BeforeDoctypeName),
+          c => {
+            self.error("missing-whitespace-before-doctype-name");
+            self.reconsume(c, State::BeforeDoctypeName);
+          }
+        }
+      }
+      State::BeforeDoctypeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '>' => {
+          self.error("missing-doctype-name");
+          self.doctype.force_quirks = true;
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        '\0' => {
+          self.doctype_name().push('\u{fffd}');
+          self.state = State::DoctypeName;
+        }
+        c => {
+          self.doctype_name().push(c.to_ascii_lowercase());
+          self.state = State::DoctypeName;
+        }
+      },
+      State::DoctypeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::AfterDoctypeName,
+        '>' => {
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        '\0' => self.doctype_name().push('\u{fffd}'),
+        c => self.doctype_name().push(c.to_ascii_lowercase()),
+      },
+      State::AfterDoctypeName => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '>' => {
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        c => {
+          self.unread(c);
+          match (
+            self.lookahead("PUBLIC", true),
+            self.lookahead("SYSTEM", true),
+          ) {
+            (Some(true), _) => {
+              self.skip(6);
+              self.state = State::AfterDoctypePublicKeyword;
+            }
+            (_, Some(true)) => {
+              self.skip(6);
+              self.state = State::AfterDoctypeSystemKeyword;
+            }
+            (None, _) | (_, None) => return false,
+            (Some(false), Some(false)) => {
+              self.error_at(
+                self.consumed,
+                "invalid-character-sequence-after-doctype-name",
+              );
+              self.doctype.force_quirks = true;
+              self.state = State::BogusDoctype;
+            }
+          }
+        }
+      },
+      State::AfterDoctypePublicKeyword | State::BeforeDoctypePublicIdentifier => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeDoctypePublicIdentifier,
+        '"' | '\'' => {
+          if self.state == State::AfterDoctypePublicKeyword {
+            self.error("missing-whitespace-after-doctype-public-keyword");
+          }
+          self.doctype.public_id = Some(String::new());
+          self.state = if c == '"' {
+            State::DoctypePublicIdentifierDoubleQuoted
+          } else {
+            State::DoctypePublicIdentifierSingleQuoted
+          };
+        }
+        '>' => {
+          self.error("missing-doctype-public-identifier");
+          self.doctype.force_quirks = true;
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        c => {
+          self.error("missing-quote-before-doctype-public-identifier");
+          self.doctype.force_quirks = true;
+          self.reconsume(c, State::BogusDoctype);
+        }
+      },
+      State::DoctypePublicIdentifierDoubleQuoted | State::DoctypePublicIdentifierSingleQuoted => {
+        let quote = if self.state == State::DoctypePublicIdentifierDoubleQuoted {
+          '"'
+        } else {
+          '\''
+        };
+        match c {
+          c if c == quote => self.state = State::AfterDoctypePublicIdentifier,
+          '>' => {
+            self.error("abrupt-doctype-public-identifier");
+            self.doctype.force_quirks = true;
+            self.state = State::Data;
+            self.emit_doctype();
+          }
+          '\0' => self
+            .doctype
+            .public_id
+            .get_or_insert_default()
+            .push('\u{fffd}'),
+          c => self.doctype.public_id.get_or_insert_default().push(c),
+        }
+      }
+      State::AfterDoctypePublicIdentifier | State::BetweenDoctypePublicAndSystemIdentifiers => {
+        match c {
+          '\t' | '\n' | '\x0c' | ' ' => {
+            self.state = State::BetweenDoctypePublicAndSystemIdentifiers
+          }
+          '>' => {
+            self.state = State::Data;
+            self.emit_doctype();
+          }
+          '"' | '\'' => {
+            if self.state == State::AfterDoctypePublicIdentifier {
+              self.error("missing-whitespace-between-doctype-public-and-system-identifiers");
+            }
+            self.doctype.system_id = Some(String::new());
+            self.state = if c == '"' {
+              State::DoctypeSystemIdentifierDoubleQuoted
+            } else {
+              State::DoctypeSystemIdentifierSingleQuoted
+            };
+          }
+          c => {
+            self.error("missing-quote-before-doctype-system-identifier");
+            self.doctype.force_quirks = true;
+            self.reconsume(c, State::BogusDoctype);
+          }
+        }
+      }
+      State::AfterDoctypeSystemKeyword | State::BeforeDoctypeSystemIdentifier => match c {
+        '\t' | '\n' | '\x0c' | ' ' => self.state = State::BeforeDoctypeSystemIdentifier,
+        '"' | '\'' => {
+          if self.state == State::AfterDoctypeSystemKeyword {
+            self.error("missing-whitespace-after-doctype-system-keyword");
+          }
+          self.doctype.system_id = Some(String::new());
+          self.state = if c == '"' {
+            State::DoctypeSystemIdentifierDoubleQuoted
+          } else {
+            State::DoctypeSystemIdentifierSingleQuoted
+          };
+        }
+        '>' => {
+          self.error("missing-doctype-system-identifier");
+          self.doctype.force_quirks = true;
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        c => {
+          self.error("missing-quote-before-doctype-system-identifier");
+          self.doctype.force_quirks = true;
+          self.reconsume(c, State::BogusDoctype);
+        }
+      },
+      State::DoctypeSystemIdentifierDoubleQuoted | State::DoctypeSystemIdentifierSingleQuoted => {
+        let quote = if self.state == State::DoctypeSystemIdentifierDoubleQuoted {
+          '"'
+        } else {
+          '\''
+        };
+        match c {
+          c if c == quote => self.state = State::AfterDoctypeSystemIdentifier,
+          '>' => {
+            self.error("abrupt-doctype-system-identifier");
+            self.doctype.force_quirks = true;
+            self.state = State::Data;
+            self.emit_doctype();
+          }
+          '\0' => self
+            .doctype
+            .system_id
+            .get_or_insert_default()
+            .push('\u{fffd}'),
+          c => self.doctype.system_id.get_or_insert_default().push(c),
+        }
+      }
+      State::AfterDoctypeSystemIdentifier => match c {
+        '\t' | '\n' | '\x0c' | ' ' => (),
+        '>' => {
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+        // trailing junk is ignored but doesn't force quirks mode
+        c => {
+          self.error("unexpected-character-after-doctype-system-identifier");
+          self.reconsume(c, State::BogusDoctype);
+        }
+      },
+      State::BogusDoctype => {
+        if c == '>' {
+          self.state = State::Data;
+          self.emit_doctype();
+        }
+      }
+      State::CdataSection => match c {
+        ']' => self.state = State::CdataSectionBracket,
+        c => self.text.push(c),
+      },
+      State::CdataSectionBracket => match c {
+        ']' => self.state = State::CdataSectionEnd,
+        c => {
+          self.text.push(']');
+          self.reconsume(c, State::CdataSection);
+        }
+      },
+      State::CdataSectionEnd => match c {
+        ']' => self.text.push(']'),
+        '>' => self.state = State::Data,
+        c => {
+          self.text.push_str("]]");
+          self.reconsume(c, State::CdataSection);
+        }
+      },
+      State::CommentStart => match c {
+        '-' => self.state = State::CommentStartDash,
+        '>' => {
+          self.error("abrupt-closing-of-empty-comment");
+          self.state = State::Data;
+          self.emit_comment();
+        }
+        c => self.reconsume(c, State::Comment),
+      },
+      State::CommentStartDash => match c {
+        '-' => self.state = State::CommentEnd,
+        '>' => {
+          self.error("abrupt-closing-of-empty-comment");
+          self.state = State::Data;
+          self.emit_comment();
+        }
+        c => {
+          self.comment.push('-');
+          self.reconsume(c, State::Comment);
+        }
+      },
+      State::Comment => match c {
+        '-' => self.state = State::CommentEndDash,
+        '\0' => self.comment.push('\u{fffd}'),
+        c => self.comment.push(c),
+      },
+      State::CommentEndDash => match c {
+        '-' => self.state = State::CommentEnd,
+        c => {
+          self.comment.push('-');
+          self.reconsume(c, State::Comment);
+        }
+      },
+      State::CommentEnd => match c {
+        '>' => {
+          self.state = State::Data;
+          self.emit_comment();
+        }
+        '!' => self.state = State::CommentEndBang,
+        '-' => self.comment.push('-'),
+        c => {
+          self.comment.push_str("--");
+          self.reconsume(c, State::Comment);
+        }
+      },
+      State::CommentEndBang => match c {
+        '-' => {
+          self.comment.push_str("--!");
+          self.state = State::CommentEndDash;
+        }
+        '>' => {
+          self.error("incorrectly-closed-comment");
+          self.state = State::Data;
+          self.emit_comment();
+        }
+        c => {
+          self.comment.push_str("--!");
+          self.reconsume(c, State::Comment);
+        }
+      },
+    }
+
+    true
+  }
+
+  // the "less-than sign", "end tag open" and "end tag name" states are the
+  // same for RCDATA, RAWTEXT and script data apart from where they return to
+  fn raw_less_than_sign(&mut self, c: char, text_state: State, end_tag_open: State) {
+    if c == '/' {
+      self.buffer.clear();
+      self.state = end_tag_open;
+    } else {
+      self.text.push('<');
+      self.reconsume(c, text_state);
+    }
+  }
+
+  fn raw_end_tag_open(&mut self, c: char, text_state: State, end_tag_name: State) {
+    if c.is_ascii_alphabetic() {
+      self.start_tag(true);
+      self.reconsume(c, end_tag_name);
+    } else {
+      self.text.push_str("</");
+      self.reconsume(c, text_state);
+    }
+  }
+
+  // only the end tag matching the element that switched states counts,
+  // anything else is text: </b> inside <title> stays as written
+  fn raw_end_tag_name(&mut self, c: char, text_state: State) {
+    match c {
+      '\t' | '\n' | '\x0c' | ' ' if self.is_appropriate_end_tag() => {
+        self.state = State::BeforeAttributeName
+      }
+      '/' if self.is_appropriate_end_tag() => self.state = State::SelfClosingStartTag,
+      '>' if self.is_appropriate_end_tag() => {
+        self.state = State::Data;
+        self.emit_tag();
+      }
+      c if c.is_ascii_alphabetic() => {
+        self.tag.name.push(c.to_ascii_lowercase());
+        self.buffer.push(c);
+      }
+      c => {
+        self.text.push_str("</");
+        let buffer = std::mem::take(&mut self.buffer);
+        self.text.push_str(&buffer);
+        self.reconsume(c, text_state);
+      }
+    }
+  }
+
+  fn reconsume(&mut self, c: char, state: State) {
+    self.unread(c);
+    self.state = state;
+  }
+
+  fn end_of_file(&mut self) {
+    let mut text_end = self.consumed;
+
+    match self.state {
+      State::TagOpen => {
+        self.error_at(self.consumed, "eof-before-tag-name");
+        self.text.push('<');
+      }
+      State::EndTagOpen => {
+        self.error_at(self.consumed, "eof-before-tag-name");
+        self.text.push_str("</");
+      }
+      State::RcDataLessThanSign | State::RawTextLessThanSign | State::ScriptDataLessThanSign => {
+        self.text.push('<')
+      }
+      State::ScriptDataEscapedLessThanSign => self.text.push('<'),
+      State::RcDataEndTagOpen
+      | State::RawTextEndTagOpen
+      | State::ScriptDataEndTagOpen
+      | State::ScriptDataEscapedEndTagOpen => self.text.push_str("</"),
+      State::RcDataEndTagName
+      | State::RawTextEndTagName
+      | State::ScriptDataEndTagName
+      | State::ScriptDataEscapedEndTagName => {
+        self.text.push_str("</");
+        let buffer = std::mem::take(&mut self.buffer);
+        self.text.push_str(&buffer);
+      }
+      State::BogusComment
+      | State::CommentStart
+      | State::CommentStartDash
+      | State::Comment
+      | State::CommentEndDash
+      | State::CommentEnd
+      | State::CommentEndBang => {
+        if self.state != State::BogusComment {
+          self.error_at(self.consumed, "eof-in-comment");
+        }
+        self.emit_comment();
+      }
+      State::Doctype
+      | State::BeforeDoctypeName
+      | State::DoctypeName
+      | State::AfterDoctypeName
+      | State::AfterDoctypePublicKeyword
+      | State::BeforeDoctypePublicIdentifier
+      | State::DoctypePublicIdentifierDoubleQuoted
+      | State::DoctypePublicIdentifierSingleQuoted
+      | State::AfterDoctypePublicIdentifier
+      | State::BetweenDoctypePublicAndSystemIdentifiers
+      | State::AfterDoctypeSystemKeyword
+      | State::BeforeDoctypeSystemIdentifier
+      | State::DoctypeSystemIdentifierDoubleQuoted
+      | State::DoctypeSystemIdentifierSingleQuoted
+      | State::AfterDoctypeSystemIdentifier => {
+        self.error_at(self.consumed, "eof-in-doctype");
+        self.doctype.force_quirks = true;
+        self.emit_doctype();
+      }
+      State::BogusDoctype => self.emit_doctype(),
+      State::CdataSection | State::CdataSectionBracket | State::CdataSectionEnd => {
+        match self.state {
+          State::CdataSectionBracket => self.text.push(']'),
+          State::CdataSectionEnd => self.text.push_str("]]"),
+          _ => (),
+        }
+        self.error_at(self.consumed, "eof-in-cdata");
+      }
+      State::ScriptDataEscaped
+      | State::ScriptDataEscapedDash
+      | State::ScriptDataEscapedDashDash
+      | State::ScriptDataDoubleEscaped
+      | State::ScriptDataDoubleEscapedDash
+      | State::ScriptDataDoubleEscapedDashDash => {
+        self.error_at(self.consumed, "eof-in-script-html-comment-like-text")
+      }
+      // an unfinished tag is dropped
+      _ if !self.in_text() => {
+        self.error_at(self.consumed, "eof-in-tag");
+        text_end = self.markup_start;
+      }
+      _ => (),
+    }
+
+    self.state = State::Data;
+    self.flush_text(text_end);
+    let end = self.span(self.consumed, self.consumed);
+    self.tokens.push_back((Token::Eof, end));
+    self.done = true;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tokenize(input: &str) -> (Vec<Token>, Vec<&'static str>) {
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.feed(input);
+    tokenizer.end();
+    let mut tokens = vec![];
+    while let Some((token, _)) = tokenizer.next_token() {
+      tokens.push(token);
+    }
+    let errors = tokenizer
+      .take_errors()
+      .iter()
+      .map(|error| error.code)
+      .collect();
+    (tokens, errors)
+  }
+
+  fn start(name: &str, attributes: &[(&str, &str)], self_closing: bool) -> Token {
+    Token::StartTag {
+      name: name.to_string(),
+      attributes: attributes
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect(),
+      self_closing,
+    }
+  }
+
+  fn end(name: &str) -> Token {
+    Token::EndTag {
+      name: name.to_string(),
+    }
+  }
+
+  fn text(text: &str) -> Token {
+    Token::Text(text.to_string())
+  }
+
+  #[test]
+  fn attributes_in_every_quoting_style() {
+    let (tokens, errors) = tokenize("<A Href=x title='a > b' data-X=\"1\">y</a>");
+    assert_eq!(
+      tokens,
+      vec![
+        start(
+          "a",
+          &[("href", "x"), ("title", "a > b"), ("data-x", "1")],
+          false
+        ),
+        text("y"),
+        end("a"),
+        Token::Eof,
+      ]
+    );
+    assert!(errors.is_empty());
+  }
+
+  #[test]
+  fn duplicate_attribute_keeps_the_first() {
+    let (tokens, errors) = tokenize("<p id=a id=b>");
+    assert_eq!(tokens, vec![start("p", &[("id", "a")], false), Token::Eof]);
+    assert_eq!(errors, vec!["duplicate-attribute"]);
+  }
+
+  #[test]
+  fn self_closing_start_tag() {
+    let (tokens, _) = tokenize("<br/><img src=a />");
+    assert_eq!(
+      tokens,
+      vec![
+        start("br", &[], true),
+        start("img", &[("src", "a")], true),
+        Token::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn comments() {
+    let (tokens, errors) = tokenize("<!-- a -- b --><!---->");
+    assert_eq!(
+      tokens,
+      vec![
+        Token::Comment(" a -- b ".to_string()),
+        Token::Comment(String::new()),
+        Token::Eof,
+      ]
+    );
+    assert!(errors.is_empty());
+  }
+
+  #[test]
+  fn bogus_comment_from_a_question_mark() {
+    let (tokens, errors) = tokenize("<?xml version='1.0'?>");
+    assert_eq!(
+      tokens,
+      vec![
+        Token::Comment("?xml version='1.0'?".to_string()),
+        Token::Eof
+      ]
+    );
+    assert_eq!(errors, vec!["unexpected-question-mark-instead-of-tag-name"]);
+  }
+
+  #[test]
+  fn newlines_are_normalised() {
+    let (tokens, _) = tokenize("a\r\nb\rc");
+    assert_eq!(tokens, vec![text("a\nb\nc"), Token::Eof]);
+  }
+
+  #[test]
+  fn lone_less_than_sign_is_text() {
+    let (tokens, errors) = tokenize("a < b");
+    assert_eq!(tokens, vec![text("a < b"), Token::Eof]);
+    assert_eq!(errors, vec!["invalid-first-character-of-tag-name"]);
+  }
+  #[test]
+  fn chunks_give_the_same_tokens() {
+    let input = "<p class=x>one &amp; two<!--c--></p>";
+    let (expected, _) = tokenize(input);
+
+    let mut tokenizer = Tokenizer::new();
+    let mut tokens = vec![];
+    for c in input.chars() {
+      tokenizer.feed(&c.to_string());
+      while let Some((token, _)) = tokenizer.next_token() {
+        tokens.push(token);
+      }
+    }
+    tokenizer.end();
+    while let Some((token, _)) = tokenizer.next_token() {
+      tokens.push(token);
+    }
+
+    // text may arrive in pieces, the tree builder joins them
+    let mut joined: Vec<Token> = vec![];
+    for token in tokens {
+      match (joined.last_mut(), token) {
+        (Some(Token::Text(previous)), Token::Text(text)) => previous.push_str(&text),
+        (_, token) => joined.push(token),
+      }
+    }
+    assert_eq!(joined, expected);
+  }
+
+  #[test]
+  fn character_references_in_text_and_attributes() {
+    let (tokens, _) = tokenize("<a href=\"?a=1&copy=2\" title=&lt;x>&amp;&notin;&#x41</a>");
+    assert_eq!(
+      tokens,
+      vec![
+        start("a", &[("href", "?a=1&copy=2"), ("title", "<x")], false),
+        text("&\u{2209}A"),
+        end("a"),
+        Token::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn doctype_with_identifiers() {
+    let (tokens, _) = tokenize(
+      "<!DOCTYPE html PUBLIC \"-//W3C//DTD HTML 4.01//EN\" 'http://www.w3.org/TR/html4/strict.dtd'>",
+    );
+    assert_eq!(
+      tokens,
+      vec![
+        Token::Doctype(Doctype {
+          name: Some("html".to_string()),
+          public_id: Some("-//W3C//DTD HTML 4.01//EN".to_string()),
+          system_id: Some("http://www.w3.org/TR/html4/strict.dtd".to_string()),
+          force_quirks: false,
+        }),
+        Token::Eof,
+      ]
+    );
+  }
+
+  #[test]
+  fn broken_doctype_forces_quirks() {
+    let (tokens, _) = tokenize("<!DOCTYPE html PUBLIC");
+    assert!(matches!(&tokens[0], Token::Doctype(d) if d.force_quirks));
+  }
+
+  #[test]
+  fn tokens_carry_their_spans() {
+    let mut tokenizer = Tokenizer::new();
+    tokenizer.feed("<p>\nab</p>");
+    tokenizer.end();
+    let mut spans = vec![];
+    while let Some((_, span)) = tokenizer.next_token() {
+      spans.push((
+        (span.start.line, span.start.column),
+        (span.end.line, span.end.column),
+      ));
+    }
+    assert_eq!(
+      spans[..3],
+      [((1, 1), (1, 4)), ((1, 4), (2, 3)), ((2, 3), (2, 7))]
+    );
+  }
+}
@@ -0,0 +1,964 @@
+use crate::rendering::entities;
+use crate::utils::{
+  Comment, Doctype, Dom, Element, Node, NodeId, Position, ProcessingInstruction, Span, Text,
+  XML_NAMESPACE, XMLNS_NAMESPACE,
+};
+
+use std::collections::HashMap;
+use std::fmt;
+
+// content types that get XML rules instead of the HTML parser
+const XML_CONTENT_TYPES: [&str; 4] = [
+  "application/xhtml+xml",
+  "application/xml",
+  "image/svg+xml",
+  "text/xml",
+];
+
+// how many characters entity references may add to a document in total, so
+// a few nested declarations can't expand into gigabytes
+const MAX_EXPANSION: usize = 1 << 20;
+
+pub fn is_xml(content_type: &str) -> bool {
+  XML_CONTENT_TYPES.contains(&content_type)
+}
+
+// a well-formedness error; XML has no error recovery, the first one ends
+// parsing
+#[derive(Debug, Clone)]
+pub struct XMLError {
+  pub message: String,
+  pub position: Position,
+}
+
+impl fmt::Display for XMLError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} at line {}, column {}",
+      self.message, self.position.line, self.position.column
+    )
+  }
+}
+
+impl std::error::Error for XMLError {}
+
+// what an `&...;` in the document stands for
+enum Reference {
+  Text(String),
+  // an entity declared in the internal subset, its replacement text is read
+  // where the reference is
+  Entity(String),
+}
+
+pub struct XMLParser {
+  input: Vec<char>,
+  index: usize,
+  line_starts: Vec<usize>,
+  dom: Dom,
+  unfinished: Vec<NodeId>,
+  // prefix -> namespace for each open element, "" is the default namespace
+  namespaces: Vec<HashMap<String, String>>,
+  // a DTD we don't read may declare entities, the HTML ones are assumed
+  external_dtd: bool,
+  // general entities declared in the internal subset, name -> replacement
+  entities: HashMap<String, String>,
+  // the entities being read, innermost last, with where the outermost
+  // reference is in the document
+  expanding: Vec<(String, usize)>,
+  expanded: usize,
+}
+
+impl XMLParser {
+  pub fn new(body: String) -> Self {
+    let body = body.replace("\r\n", "\n").replace('\r', "\n");
+    let input: Vec<char> = body
+      .strip_prefix('\u{feff}')
+      .unwrap_or(&body)
+      .chars()
+      .collect();
+
+    let mut line_starts = vec![0];
+    for (index, c) in input.iter().enumerate() {
+      if *c == '\n' {
+        line_starts.push(index + 1);
+      }
+    }
+
+    let mut predefined = HashMap::new();
+    predefined.insert(String::from("xml"), XML_NAMESPACE.to_string());
+    predefined.insert(String::from("xmlns"), XMLNS_NAMESPACE.to_string());
+
+    XMLParser {
+      input,
+      index: 0,
+      line_starts,
+      dom: Dom::new(),
+      unfinished: vec![],
+      namespaces: vec![predefined],
+      external_dtd: false,
+      entities: HashMap::new(),
+      expanding: vec![],
+      expanded: 0,
+    }
+  }
+
+  pub fn parse(&mut self) -> Result<Dom, XMLError> {
+    // the declaration isn't a processing instruction, it isn't kept
+    if self.starts_with("<?xml") && self.peek_at(5).is_some_and(is_whitespace) {
+      self.processing_instruction(true)?;
+    }
+
+    self.misc()?;
+    if self.starts_with("<!DOCTYPE") {
+      self.doctype()?;
+      self.misc()?;
+    }
+
+    if self.peek() != Some('<') {
+      return Err(self.error("no root element found"));
+    }
+    self.content()?;
+
+    self.misc()?;
+    if self.index < self.input.len() {
+      return Err(self.error("junk after document element"));
+    }
+
+    Ok(std::mem::take(&mut self.dom))
+  }
+
+  // comments, processing instructions and whitespace around the root
+  fn misc(&mut self) -> Result<(), XMLError> {
+    loop {
+      self.skip_whitespace();
+
+      if self.starts_with("<!--") {
+        let comment = self.comment()?;
+        self.append_node(Node::Comment(comment));
+      } else if self.starts_with("<?") {
+        let instruction = self.processing_instruction(false)?;
+        self.append_node(Node::ProcessingInstruction(instruction));
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  // the root element and everything in it; a loop rather than recursion so
+  // deeply nested documents can't overflow the stack
+  fn content(&mut self) -> Result<(), XMLError> {
+    loop {
+      if self.index >= self.input.len() {
+        return Err(self.error("no element found"));
+      }
+
+      if self.unfinished.is_empty() {
+        self.start_tag()?;
+      } else if self.starts_with("</") {
+        self.end_tag()?;
+      } else {
+        self.content_item()?;
+      }
+
+      if self.unfinished.is_empty() {
+        return Ok(());
+      }
+    }
+  }
+
+  // the replacement text of an entity referenced in content; it has to be
+  // balanced, every element started in it ends in it too
+  fn entity_content(&mut self) -> Result<(), XMLError> {
+    let depth = self.unfinished.len();
+
+    while self.index < self.input.len() {
+      if self.starts_with("</") {
+        if self.unfinished.len() == depth {
+          return Err(self.error("asynchronous entity"));
+        }
+        self.end_tag()?;
+      } else {
+        self.content_item()?;
+      }
+    }
+
+    if self.unfinished.len() != depth {
+      return Err(self.error("asynchronous entity"));
+    }
+    Ok(())
+  }
+
+  // anything inside an element but its end tag
+  fn content_item(&mut self) -> Result<(), XMLError> {
+    if self.starts_with("<!--") {
+      let comment = self.comment()?;
+      self.append_node(Node::Comment(comment));
+    } else if self.starts_with("<![CDATA[") {
+      self.cdata()?;
+    } else if self.starts_with("<?") {
+      let instruction = self.processing_instruction(false)?;
+      self.append_node(Node::ProcessingInstruction(instruction));
+    } else if self.starts_with("<!") {
+      return Err(self.error("not well-formed"));
+    } else if self.starts_with("<") {
+      self.start_tag()?;
+    } else {
+      self.char_data()?;
+    }
+    Ok(())
+  }
+
+  fn start_tag(&mut self) -> Result<(), XMLError> {
+    let start = self.index;
+    self.index += 1;
+    let tag = self.name()?;
+
+    let mut attributes: Vec<(String, String)> = vec![];
+    let self_closing = loop {
+      let spaced = self.skip_whitespace();
+
+      if self.starts_with("/>") {
+        self.index += 2;
+        break true;
+      }
+      if self.peek() == Some('>') {
+        self.index += 1;
+        break false;
+      }
+      if !spaced {
+        return Err(self.error("not well-formed"));
+      }
+
+      let name_start = self.index;
+      let name = self.name()?;
+      self.skip_whitespace();
+      if self.peek() != Some('=') {
+        return Err(self.error("not well-formed"));
+      }
+      self.index += 1;
+      self.skip_whitespace();
+      let value = self.attribute_value()?;
+
+      if attributes.iter().any(|(existing, _)| *existing == name) {
+        return Err(self.error_at(name_start, "duplicate attribute"));
+      }
+      attributes.push((name, value));
+    };
+
+    let mut scope = HashMap::new();
+    for (name, value) in &attributes {
+      if name == "xmlns" {
+        scope.insert(String::new(), value.clone());
+      } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+        if value.is_empty() {
+          return Err(self.error_at(start, "must not undeclare prefix"));
+        }
+        scope.insert(prefix.to_string(), value.clone());
+      }
+    }
+    self.namespaces.push(scope);
+
+    let namespace = match tag.split_once(':') {
+      Some((prefix, _)) => self
+        .namespace(prefix)
+        .ok_or_else(|| self.error_at(start, "unbound prefix"))?,
+      None => self.namespace("").unwrap_or_default(),
+    };
+    for (name, _) in &attributes {
+      if let Some((prefix, _)) = name.split_once(':')
+        && prefix != "xmlns"
+        && self.namespace(prefix).is_none()
+      {
+        return Err(self.error_at(start, "unbound prefix"));
+      }
+    }
+
+    let span = Span {
+      start: self.position(start),
+      end: self.position(self.index),
+    };
+    let node = self.dom.create(Node::Element(Element {
+      tag,
+      namespace,
+      attributes,
+      span,
+    }));
+    self.append(node);
+
+    if self_closing {
+      self.namespaces.pop();
+    } else {
+      self.unfinished.push(node);
+    }
+    Ok(())
+  }
+
+  fn end_tag(&mut self) -> Result<(), XMLError> {
+    let start = self.index;
+    self.index += 2;
+    let name = self.name()?;
+    self.skip_whitespace();
+    if self.peek() != Some('>') {
+      return Err(self.error("not well-formed"));
+    }
+    self.index += 1;
+
+    let current = *self.unfinished.last().unwrap();
+    let expected = self.dom[current].tag().unwrap_or("").to_string();
+    if name != expected {
+      return Err(self.error_at(start, &format!("mismatched tag. Expected: </{expected}>.")));
+    }
+
+    let end = self.position(self.index);
+    if let Node::Element(element) = &mut self.dom[current] {
+      element.span.end = end;
+    }
+    self.unfinished.pop();
+    self.namespaces.pop();
+    Ok(())
+  }
+
+  fn char_data(&mut self) -> Result<(), XMLError> {
+    let mut start = self.index;
+    let mut text = String::new();
+
+    while let Some(c) = self.peek() {
+      match c {
+        '<' => break,
+        '&' => {
+          let reference_start = self.index;
+          match self.reference()? {
+            Reference::Text(value) => text.push_str(&value),
+            Reference::Entity(name) => {
+              self.add_text(std::mem::take(&mut text), start, reference_start);
+              self.in_entity(name, reference_start, Self::entity_content)?;
+              start = self.index;
+            }
+          }
+        }
+        ']' if self.starts_with("]]>") => {
+          return Err(self.error("not well-formed"));
+        }
+        c => {
+          text.push(c);
+          self.index += 1;
+        }
+      }
+    }
+
+    self.add_text(text, start, self.index);
+    Ok(())
+  }
+
+  fn cdata(&mut self) -> Result<(), XMLError> {
+    let start = self.index;
+    self.index += "<![CDATA[".len();
+    let text = self.until("]]>")?;
+    self.add_text(text, start, self.index);
+    Ok(())
+  }
+
+  fn comment(&mut self) -> Result<Comment, XMLError> {
+    let start = self.index;
+    self.index += "<!--".len();
+    let text = self.until("-->")?;
+    if text.contains("--") || text.ends_with('-') {
+      return Err(self.error_at(start, "not well-formed"));
+    }
+
+    Ok(Comment {
+      text,
+      span: self.span(start),
+    })
+  }
+
+  fn processing_instruction(
+    &mut self,
+    declaration: bool,
+  ) -> Result<ProcessingInstruction, XMLError> {
+    let start = self.index;
+    self.index += 2;
+    let target = self.name()?;
+
+    if target.eq_ignore_ascii_case("xml") && !declaration {
+      return Err(self.error_at(start, "XML or text declaration not at start of entity"));
+    }
+    if !self.skip_whitespace() && !self.starts_with("?>") {
+      return Err(self.error("not well-formed"));
+    }
+
+    let data = self.until("?>")?;
+    Ok(ProcessingInstruction {
+      target,
+      data,
+      span: self.span(start),
+    })
+  }
+
+  fn doctype(&mut self) -> Result<(), XMLError> {
+    let start = self.index;
+    self.index += "<!DOCTYPE".len();
+    if !self.skip_whitespace() {
+      return Err(self.error("not well-formed"));
+    }
+
+    let name = self.name()?;
+    self.skip_whitespace();
+
+    let mut public_id = String::new();
+    let mut system_id = String::new();
+    if self.starts_with("PUBLIC") {
+      self.index += "PUBLIC".len();
+      self.skip_whitespace();
+      public_id = self.quoted()?;
+      self.skip_whitespace();
+      system_id = self.quoted()?;
+      self.external_dtd = true;
+    } else if self.starts_with("SYSTEM") {
+      self.index += "SYSTEM".len();
+      self.skip_whitespace();
+      system_id = self.quoted()?;
+      self.external_dtd = true;
+    }
+    self.skip_whitespace();
+
+    if self.peek() == Some('[') {
+      self.index += 1;
+      self.internal_subset()?;
+      self.skip_whitespace();
+    }
+
+    if self.peek() != Some('>') {
+      return Err(self.error("not well-formed"));
+    }
+    self.index += 1;
+
+    let span = self.span(start);
+    self.append_node(Node::Doctype(Doctype {
+      name,
+      public_id,
+      system_id,
+      span,
+    }));
+    Ok(())
+  }
+
+  // only general entity declarations are read, the other declarations,
+  // comments and processing instructions are checked and skipped
+  fn internal_subset(&mut self) -> Result<(), XMLError> {
+    loop {
+      self.skip_whitespace();
+
+      if self.peek() == Some(']') {
+        self.index += 1;
+        return Ok(());
+      } else if self.starts_with("<!ENTITY") {
+        self.entity_declaration()?;
+      } else if self.starts_with("<!--") {
+        self.comment()?;
+      } else if self.starts_with("<?") {
+        self.processing_instruction(false)?;
+      } else if self.starts_with("<!") {
+        self.markup_declaration()?;
+      } else if self.peek() == Some('%') {
+        // a parameter entity we can't read, what it declares is unknown
+        self.index += 1;
+        self.name()?;
+        if self.peek() != Some(';') {
+          return Err(self.error("not well-formed"));
+        }
+        self.index += 1;
+      } else if self.peek().is_none() {
+        return Err(self.error("unclosed token"));
+      } else {
+        return Err(self.error("not well-formed"));
+      }
+    }
+  }
+
+  // <!ENTITY name "value"> or <!ENTITY % name ...>, only the first
+  // declaration of a name counts
+  fn entity_declaration(&mut self) -> Result<(), XMLError> {
+    self.index += "<!ENTITY".len();
+    if !self.skip_whitespace() {
+      return Err(self.error("not well-formed"));
+    }
+
+    let parameter = self.peek() == Some('%');
+    if parameter {
+      self.index += 1;
+      if !self.skip_whitespace() {
+        return Err(self.error("not well-formed"));
+      }
+    }
+    let name = self.name()?;
+    if !self.skip_whitespace() {
+      return Err(self.error("not well-formed"));
+    }
+
+    let value = match self.peek() {
+      Some('"' | '\'') => Some(self.entity_value()?),
+      // an external entity, its replacement text isn't loaded
+      _ => {
+        self.markup_declaration()?;
+        return Ok(());
+      }
+    };
+    self.skip_whitespace();
+    if self.peek() != Some('>') {
+      return Err(self.error("not well-formed"));
+    }
+    self.index += 1;
+
+    if let Some(value) = value
+      && !parameter
+      && !["amp", "lt", "gt", "apos", "quot"].contains(&name.as_str())
+    {
+      self.entities.entry(name).or_insert(value);
+    }
+    Ok(())
+  }
+
+  // character references are replaced when the entity is declared, entity
+  // references only when it is used
+  fn entity_value(&mut self) -> Result<String, XMLError> {
+    let Some(quote) = self.peek() else {
+      return Err(self.error("unclosed token"));
+    };
+    self.index += 1;
+
+    let mut value = String::new();
+    loop {
+      match self.peek() {
+        None => return Err(self.error("unclosed token")),
+        Some(c) if c == quote => break,
+        Some('%') => return Err(self.error("illegal parameter entity reference")),
+        Some('&') if self.peek_at(1) == Some('#') => match self.reference()? {
+          Reference::Text(text) => value.push_str(&text),
+          Reference::Entity(name) => value.push_str(&format!("&{name};")),
+        },
+        Some(c) => {
+          value.push(c);
+          self.index += 1;
+        }
+      }
+    }
+    self.index += 1;
+
+    Ok(value)
+  }
+
+  // skips a declaration up to its closing '>', quoted '>'s don't count
+  fn markup_declaration(&mut self) -> Result<(), XMLError> {
+    let mut quote = None;
+    loop {
+      match (self.peek(), quote) {
+        (None, _) => return Err(self.error("unclosed token")),
+        (Some(c), Some(q)) if c == q => quote = None,
+        (Some(_), Some(_)) => (),
+        (Some(c @ ('"' | '\'')), None) => quote = Some(c),
+        (Some('>'), None) => break,
+        _ => (),
+      }
+      self.index += 1;
+    }
+    self.index += 1;
+    Ok(())
+  }
+
+  // reads the replacement text of an internal entity with `read`, as if it
+  // were in the document where the reference is
+  fn in_entity<T>(
+    &mut self,
+    name: String,
+    start: usize,
+    read: impl FnOnce(&mut Self) -> Result<T, XMLError>,
+  ) -> Result<T, XMLError> {
+    if self.expanding.iter().any(|(open, _)| *open == name) {
+      return Err(self.error_at(start, "recursive entity reference"));
+    }
+
+    let replacement: Vec<char> = self.entities[&name].chars().collect();
+    self.expanded += replacement.len();
+    if self.expanded > MAX_EXPANSION {
+      return Err(self.error_at(start, "excessive entity expansion"));
+    }
+
+    let reference = self.expanding.first().map_or(start, |&(_, at)| at);
+    let input = std::mem::replace(&mut self.input, replacement);
+    let index = std::mem::replace(&mut self.index, 0);
+    self.expanding.push((name, reference));
+
+    let result = read(self);
+
+    self.expanding.pop();
+    self.input = input;
+    self.index = index;
+    result
+  }
+
+  fn attribute_value(&mut self) -> Result<String, XMLError> {
+    let Some(quote @ ('"' | '\'')) = self.peek() else {
+      return Err(self.error("not well-formed"));
+    };
+    self.index += 1;
+    let value = self.attribute_text(Some(quote))?;
+    self.index += 1;
+
+    Ok(value)
+  }
+
+  // an attribute value up to its closing quote, or the whole replacement
+  // text of an entity referenced in one
+  fn attribute_text(&mut self, quote: Option<char>) -> Result<String, XMLError> {
+    let mut value = String::new();
+    loop {
+      match self.peek() {
+        None if quote.is_none() => break,
+        None => return Err(self.error("unclosed token")),
+        Some(c) if Some(c) == quote => break,
+        Some('<') => return Err(self.error("not well-formed")),
+        Some('&') => {
+          let start = self.index;
+          match self.reference()? {
+            Reference::Text(text) => value.push_str(&text),
+            Reference::Entity(name) => {
+              value.push_str(&self.in_entity(name, start, |parser| parser.attribute_text(None))?)
+            }
+          }
+        }
+        // attribute value normalisation turns whitespace into spaces
+        Some(c) => {
+          value.push(if is_whitespace(c) { ' ' } else { c });
+          self.index += 1;
+        }
+      }
+    }
+
+    Ok(value)
+  }
+
+  fn quoted(&mut self) -> Result<String, XMLError> {
+    let Some(quote @ ('"' | '\'')) = self.peek() else {
+      return Err(self.error("not well-formed"));
+    };
+    self.index += 1;
+    self.until(&quote.to_string())
+  }
+
+  // a character or entity reference starting at the '&'
+  fn reference(&mut self) -> Result<Reference, XMLError> {
+    let start = self.index;
+    self.index += 1;
+
+    let Some(length) = self.input[self.index..].iter().position(|&c| c == ';') else {
+      return Err(self.error_at(start, "not well-formed"));
+    };
+    let name: String = self.input[self.index..self.index + length].iter().collect();
+    self.index += length + 1;
+
+    if let Some(number) = name.strip_prefix('#') {
+      let code = match number.strip_prefix('x') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => number.parse(),
+      };
+      return code
+        .ok()
+        .filter(|&code| code != 0)
+        .and_then(char::from_u32)
+        .map(|c| Reference::Text(String::from(c)))
+        .ok_or_else(|| self.error_at(start, "reference to invalid character number"));
+    }
+
+    if self.entities.contains_key(&name) {
+      return Ok(Reference::Entity(name));
+    }
+
+    let value = match name.as_str() {
+      "amp" => Some("&"),
+      "lt" => Some("<"),
+      "gt" => Some(">"),
+      "apos" => Some("'"),
+      "quot" => Some("\""),
+      _ if self.external_dtd => entities::lookup(&format!("{name};")),
+      _ => None,
+    };
+
+    match value {
+      Some(value) => Ok(Reference::Text(value.to_string())),
+      None if is_name(&name) => Err(self.error_at(start, "undefined entity")),
+      None => Err(self.error_at(start, "not well-formed")),
+    }
+  }
+
+  fn name(&mut self) -> Result<String, XMLError> {
+    let start = self.index;
+    while let Some(c) = self.peek() {
+      if !is_name_char(c) || (self.index == start && !is_name_start_char(c)) {
+        break;
+      }
+      self.index += 1;
+    }
+
+    if self.index == start {
+      return Err(self.error("not well-formed"));
+    }
+    Ok(self.input[start..self.index].iter().collect())
+  }
+
+  // consumes everything up to and including `end`, returning what came before
+  fn until(&mut self, end: &str) -> Result<String, XMLError> {
+    let start = self.index;
+    while !self.starts_with(end) {
+      if self.index >= self.input.len() {
+        return Err(self.error_at(start, "unclosed token"));
+      }
+      self.index += 1;
+    }
+
+    let text = self.input[start..self.index].iter().collect();
+    self.index += end.chars().count();
+    Ok(text)
+  }
+
+  fn add_text(&mut self, text: String, start: usize, end: usize) {
+    let span = Span {
+      start: self.position(start),
+      end: self.position(end),
+    };
+    let parent = *self.unfinished.last().unwrap();
+
+    // CDATA next to ordinary text is one text node
+    if let Some(last) = self.dom.last_child(parent)
+      && let Node::Text(previous) = &mut self.dom[last]
+    {
+      previous.text.push_str(&text);
+      previous.span.end = span.end;
+      return;
+    }
+
+    if text.is_empty() {
+      return;
+    }
+
+    let node = self.dom.create(Node::Text(Text { text, span }));
+    self.dom.append(parent, node);
+  }
+
+  fn append_node(&mut self, node: Node) {
+    let node = self.dom.create(node);
+    self.append(node);
+  }
+
+  fn append(&mut self, node: NodeId) {
+    let parent = self
+      .unfinished
+      .last()
+      .copied()
+      .unwrap_or(self.dom.document());
+    self.dom.append(parent, node);
+  }
+
+  fn namespace(&self, prefix: &str) -> Option<String> {
+    self
+      .namespaces
+      .iter()
+      .rev()
+      .find_map(|scope| scope.get(prefix))
+      .filter(|namespace| !namespace.is_empty())
+      .cloned()
+  }
+
+  fn peek(&self) -> Option<char> {
+    self.input.get(self.index).copied()
+  }
+
+  fn peek_at(&self, offset: usize) -> Option<char> {
+    self.input.get(self.index + offset).copied()
+  }
+
+  fn starts_with(&self, expected: &str) -> bool {
+    (self.index..)
+      .zip(expected.chars())
+      .all(|(index, c)| self.input.get(index) == Some(&c))
+  }
+
+  fn skip_whitespace(&mut self) -> bool {
+    let start = self.index;
+    while self.peek().is_some_and(is_whitespace) {
+      self.index += 1;
+    }
+    self.index > start
+  }
+
+  // from `start` to where the parser is now
+  fn span(&self, start: usize) -> Span {
+    Span {
+      start: self.position(start),
+      end: self.position(self.index),
+    }
+  }
+
+  // inside an entity everything is where the reference to it is
+  fn position(&self, index: usize) -> Position {
+    let index = self.expanding.first().map_or(index, |&(_, at)| at);
+    let line = self.line_starts.partition_point(|&start| start <= index);
+    Position {
+      line,
+      column: index - self.line_starts[line - 1] + 1,
+    }
+  }
+
+  fn error(&self, message: &str) -> XMLError {
+    self.error_at(self.index, message)
+  }
+
+  fn error_at(&self, index: usize, message: &str) -> XMLError {
+    XMLError {
+      message: message.to_string(),
+      position: self.position(index),
+    }
+  }
+}
+
+fn is_whitespace(c: char) -> bool {
+  matches!(c, ' ' | '\t' | '\n')
+}
+
+fn is_name_start_char(c: char) -> bool {
+  c.is_ascii_alphabetic() || c == '_' || c == ':' || (!c.is_ascii() && c != '\u{b7}')
+}
+
+fn is_name_char(c: char) -> bool {
+  is_name_start_char(c) || c.is_ascii_digit() || matches!(c, '-' | '.' | '\u{b7}')
+}
+
+fn is_name(name: &str) -> bool {
+  name.starts_with(is_name_start_char) && name.chars().all(is_name_char)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rendering::outer_html;
+
+  fn parse(input: &str) -> Result<String, (String, usize, usize)> {
+    match XMLParser::new(input.to_string()).parse() {
+      Ok(dom) => Ok(outer_html(&dom, dom.document())),
+      Err(error) => Err((error.message, error.position.line, error.position.column)),
+    }
+  }
+
+  fn error(message: &str, line: usize, column: usize) -> Result<String, (String, usize, usize)> {
+    Err((message.to_string(), line, column))
+  }
+
+  #[test]
+  fn references() {
+    assert_eq!(
+      parse("<r a='&lt;&#65;'>&amp;&#x42;&quot;</r>"),
+      Ok("<r a=\"&lt;A\">&amp;B\"</r>".to_string())
+    );
+    assert_eq!(parse("<r>&nbsp;</r>"), error("undefined entity", 1, 4));
+    assert_eq!(
+      parse("<r>&#0;</r>"),
+      error("reference to invalid character number", 1, 4)
+    );
+
+    // an external DTD may define the HTML entities, so they're accepted
+    assert_eq!(
+      parse("<!DOCTYPE r SYSTEM 'r.dtd'><r>&copy;</r>"),
+      Ok("<!DOCTYPE r SYSTEM \"r.dtd\"><r>\u{a9}</r>".to_string())
+    );
+  }
+
+  #[test]
+  fn internal_entities_expand() {
+    assert_eq!(
+      parse(
+        "<!DOCTYPE r [<!ENTITY e 'a<b>x</b>'><!ENTITY amp2 '&#38;#38;'><!ENTITY t 'v'>]>\
+         <r a='&t;&amp2;'>&e;&amp2;&lt;</r>"
+      ),
+      Ok("<!DOCTYPE r><r a=\"v&amp;\">a<b>x</b>&amp;&lt;</r>".to_string())
+    );
+  }
+
+  #[test]
+  fn entity_errors() {
+    assert_eq!(
+      parse("<!DOCTYPE r [<!ENTITY a '&b;'><!ENTITY b '&a;'>]><r>&a;</r>"),
+      error("recursive entity reference", 1, 53)
+    );
+    assert_eq!(
+      parse("<!DOCTYPE r [<!ENTITY e '<b>'>]><r>&e;</b></r>"),
+      error("asynchronous entity", 1, 36)
+    );
+  }
+
+  #[test]
+  fn comments_and_processing_instructions_are_kept() {
+    assert_eq!(
+      parse("<?xml version='1.0'?><!--a--><r><?pi d?><!--b--></r><?end?>"),
+      Ok("<!--a--><r><?pi d?><!--b--></r><?end?>".to_string())
+    );
+    assert_eq!(parse("<r><!--a--b--></r>"), error("not well-formed", 1, 4));
+  }
+
+  #[test]
+  fn cdata_is_text() {
+    assert_eq!(
+      parse("<r><![CDATA[<&>]]></r>"),
+      Ok("<r>&lt;&amp;&gt;</r>".to_string())
+    );
+  }
+
+  fn namespaces(dom: &Dom, node: NodeId, out: &mut Vec<(String, String)>) {
+    if let Node::Element(element) = &dom[node] {
+      out.push((element.tag.clone(), element.namespace.clone()));
+    }
+    for child in dom.children(node) {
+      namespaces(dom, child, out);
+    }
+  }
+
+  #[test]
+  fn namespaces_are_resolved() {
+    let dom = XMLParser::new("<r xmlns='urn:a' xmlns:s='urn:s'><s:x/><y/></r>".to_string())
+      .parse()
+      .unwrap();
+    let mut found = vec![];
+    namespaces(&dom, dom.document(), &mut found);
+    let found: Vec<_> = found
+      .iter()
+      .map(|(tag, namespace)| (tag.as_str(), namespace.as_str()))
+      .collect();
+    assert_eq!(
+      found,
+      vec![("r", "urn:a"), ("s:x", "urn:s"), ("y", "urn:a")]
+    );
+    assert_eq!(parse("<p:r/>"), error("unbound prefix", 1, 1));
+  }
+
+  #[test]
+  fn well_formedness_errors() {
+    assert_eq!(
+      parse("<r><a></r>"),
+      error("mismatched tag. Expected: </a>.", 1, 7)
+    );
+    assert_eq!(
+      parse("<r/><r/>"),
+      error("junk after document element", 1, 5)
+    );
+    assert_eq!(parse("<r a='<'/>"), error("not well-formed", 1, 7));
+    assert_eq!(parse(""), error("no root element found", 1, 1));
+  }
+
+  #[test]
+  fn xml_content_types() {
+    assert!(is_xml("application/xhtml+xml"));
+    assert!(is_xml("image/svg+xml"));
+    assert!(is_xml("text/xml"));
+    assert!(!is_xml("text/html"));
+  }
+}
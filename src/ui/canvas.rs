@@ -19,9 +19,24 @@ impl<'a> canvas::Program<Message> for BrowserCanvas<'a> {
     _state: &mut Self::State,
     event: canvas::Event,
     bounds: iced::Rectangle,
-    _cursor: iced::mouse::Cursor,
+    cursor: iced::mouse::Cursor,
   ) -> (canvas::event::Status, Option<Message>) {
     match event {
+      canvas::Event::Mouse(iced::mouse::Event::ButtonPressed(iced::mouse::Button::Left)) => {
+        let link = cursor.position_in(bounds).and_then(|position| {
+          self
+            .display_list
+            .link_at(position.x, position.y + self.scroll_offset)
+        });
+
+        match link {
+          Some(href) => (
+            canvas::event::Status::Captured,
+            Some(Message::FollowLink(href.to_string())),
+          ),
+          None => (canvas::event::Status::Ignored, None),
+        }
+      }
       canvas::Event::Mouse(iced::mouse::Event::WheelScrolled { delta }) => match delta {
         iced::mouse::ScrollDelta::Lines { y, .. } | iced::mouse::ScrollDelta::Pixels { y, .. } => {
           let total_content_height = self.max_y + 40.0;
@@ -36,7 +51,7 @@ impl<'a> canvas::Program<Message> for BrowserCanvas<'a> {
           )
         }
       },
-      canvas::Event::Keyboard(iced::keyboard::Event::KeyPressed { key, .. }) => {
+      canvas::Event::Keyboard(iced::keyboard::Event::KeyPressed { key, modifiers, .. }) => {
         let mut new_offset = 0.0;
         match key {
           iced::keyboard::Key::Named(iced::keyboard::key::Named::ArrowUp) => {
@@ -48,6 +63,15 @@ impl<'a> canvas::Program<Message> for BrowserCanvas<'a> {
             let target_offset = self.scroll_offset + 20.0;
             new_offset = target_offset.min(scrollable_limit);
           }
+          iced::keyboard::Key::Character(c) if modifiers.alt() && c.as_str() == "z" => {
+            return (canvas::event::Status::Captured, Some(Message::ToggleWrap));
+          }
+          iced::keyboard::Key::Character(c) if modifiers.alt() && c.as_str() == "l" => {
+            return (
+              canvas::event::Status::Captured,
+              Some(Message::ToggleLineNumbers),
+            );
+          }
           _ => (),
         }
         (
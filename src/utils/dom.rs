@@ -0,0 +1,631 @@
+use crate::utils::{
+  Comment, Doctype, Document, DocumentMode, Edge, Element, Node, NodeId, ProcessingInstruction,
+  Text,
+};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+// the document tree: every node lives in one arena and refers to the others
+// by NodeId, so walking it needs no reference counting or dynamic borrows
+// and the whole tree can be sent to another thread. Removed nodes stay in
+// the arena, detached, until the Dom is dropped.
+pub struct Dom {
+  nodes: Vec<Slot>,
+  generation: u64,
+}
+
+struct Slot {
+  node: Node,
+  parent: Option<NodeId>,
+  first_child: Option<NodeId>,
+  last_child: Option<NodeId>,
+  previous_sibling: Option<NodeId>,
+  next_sibling: Option<NodeId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DOMError {
+  // the node can't go there: a text node as a parent, a node inside itself
+  HierarchyRequest,
+  // the reference node isn't a child of the parent
+  NotFound,
+}
+
+impl fmt::Display for DOMError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      DOMError::HierarchyRequest => write!(f, "the node can't be inserted there"),
+      DOMError::NotFound => write!(f, "the node is not a child of this parent"),
+    }
+  }
+}
+
+impl Error for DOMError {}
+
+impl Default for Dom {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Index<NodeId> for Dom {
+  type Output = Node;
+
+  fn index(&self, id: NodeId) -> &Node {
+    &self.nodes[id.0].node
+  }
+}
+
+impl IndexMut<NodeId> for Dom {
+  fn index_mut(&mut self, id: NodeId) -> &mut Node {
+    &mut self.nodes[id.0].node
+  }
+}
+
+impl Dom {
+  // an empty document
+  pub fn new() -> Self {
+    let mut dom = Dom {
+      nodes: vec![],
+      generation: 0,
+    };
+    dom.create(Node::Document(Document {
+      mode: DocumentMode::default(),
+    }));
+    dom
+  }
+
+  pub fn document(&self) -> NodeId {
+    NodeId(0)
+  }
+
+  pub fn mode(&self) -> DocumentMode {
+    match &self[self.document()] {
+      Node::Document(document) => document.mode,
+      _ => DocumentMode::default(),
+    }
+  }
+
+  // bumped by every change made through the methods below, a layout of an
+  // older generation is out of date
+  pub fn generation(&self) -> u64 {
+    self.generation
+  }
+
+  // a new node that isn't in the tree yet
+  pub fn create(&mut self, node: Node) -> NodeId {
+    self.nodes.push(Slot {
+      node,
+      parent: None,
+      first_child: None,
+      last_child: None,
+      previous_sibling: None,
+      next_sibling: None,
+    });
+    NodeId(self.nodes.len() - 1)
+  }
+
+  pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id.0].parent
+  }
+
+  pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id.0].first_child
+  }
+
+  pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id.0].last_child
+  }
+
+  pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id.0].previous_sibling
+  }
+
+  pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+    self.nodes[id.0].next_sibling
+  }
+
+  pub fn children(&self, id: NodeId) -> Children<'_> {
+    Children {
+      dom: self,
+      next: self.first_child(id),
+    }
+  }
+
+  pub fn has_children(&self, id: NodeId) -> bool {
+    self.first_child(id).is_some()
+  }
+
+  // moves `child` to the end of `parent`, taking it out of wherever it was
+  pub fn append_child(&mut self, parent: NodeId, child: NodeId) -> Result<(), DOMError> {
+    self.insert_before(parent, child, None)
+  }
+
+  // inserts `child` before `reference`, or at the end when there is none
+  pub fn insert_before(
+    &mut self,
+    parent: NodeId,
+    child: NodeId,
+    reference: Option<NodeId>,
+  ) -> Result<(), DOMError> {
+    self.check_insert(parent, child)?;
+    if let Some(reference) = reference
+      && self.parent(reference) != Some(parent)
+    {
+      return Err(DOMError::NotFound);
+    }
+    if reference == Some(child) {
+      return Ok(());
+    }
+
+    self.detach(child);
+    self.link(parent, child, reference);
+    self.generation += 1;
+    Ok(())
+  }
+
+  // takes the node out of its parent; a node without one is left alone
+  pub fn remove_node(&mut self, id: NodeId) {
+    if self.parent(id).is_some() {
+      self.detach(id);
+      self.generation += 1;
+    }
+  }
+
+  // puts `new` where `old` is and takes `old` out
+  pub fn replace_node(&mut self, old: NodeId, new: NodeId) -> Result<(), DOMError> {
+    let Some(parent) = self.parent(old) else {
+      return Err(DOMError::NotFound);
+    };
+    if old == new {
+      return Ok(());
+    }
+
+    self.insert_before(parent, new, Some(old))?;
+    self.remove_node(old);
+    Ok(())
+  }
+
+  // a copy without a parent; a deep copy includes the whole subtree
+  pub fn clone_node(&mut self, id: NodeId, deep: bool) -> NodeId {
+    let node = match &self[id] {
+      Node::Document(document) => Node::Document(Document {
+        mode: document.mode,
+      }),
+      Node::Doctype(doctype) => Node::Doctype(Doctype {
+        name: doctype.name.clone(),
+        public_id: doctype.public_id.clone(),
+        system_id: doctype.system_id.clone(),
+        span: doctype.span,
+      }),
+      Node::Text(text) => Node::Text(Text {
+        text: text.text.clone(),
+        span: text.span,
+      }),
+      Node::Comment(comment) => Node::Comment(Comment {
+        text: comment.text.clone(),
+        span: comment.span,
+      }),
+      Node::ProcessingInstruction(instruction) => {
+        Node::ProcessingInstruction(ProcessingInstruction {
+          target: instruction.target.clone(),
+          data: instruction.data.clone(),
+          span: instruction.span,
+        })
+      }
+      Node::Element(element) => Node::Element(Element {
+        tag: element.tag.clone(),
+        namespace: element.namespace.clone(),
+        attributes: element.attributes.clone(),
+        span: element.span,
+      }),
+    };
+    let copy = self.create(node);
+
+    // walked without recursion, a deep document would overflow the stack
+    if deep {
+      let edges: Vec<Edge> = self.traverse(id).skip(1).collect();
+      let mut parents = vec![copy];
+      for edge in edges {
+        match edge {
+          Edge::Open(node) => {
+            let child = self.clone_node(node, false);
+            if let Some(&parent) = parents.last() {
+              self.append(parent, child);
+            }
+            parents.push(child);
+          }
+          Edge::Close(_) => {
+            parents.pop();
+          }
+        }
+      }
+    }
+    copy
+  }
+
+  // merges adjacent text nodes and drops empty ones, in the whole subtree
+  pub fn normalize(&mut self, id: NodeId) {
+    // every node that can hold text, found up front as merging changes the
+    // children being walked
+    let parents: Vec<NodeId> = self
+      .traverse(id)
+      .filter_map(|edge| match edge {
+        Edge::Open(node) if self.has_children(node) => Some(node),
+        _ => None,
+      })
+      .collect();
+
+    let mut changed = false;
+    for parent in parents {
+      let mut child = self.first_child(parent);
+
+      while let Some(current) = child {
+        child = self.next_sibling(current);
+
+        let Node::Text(text) = &self[current] else {
+          continue;
+        };
+
+        if text.text.is_empty() {
+          self.detach(current);
+          changed = true;
+        } else if let Some(previous) = self.previous_sibling(current)
+          && matches!(&self[previous], Node::Text(_))
+        {
+          let (text, end) = (text.text.clone(), text.span.end);
+          if let Node::Text(previous) = &mut self[previous] {
+            previous.text.push_str(&text);
+            previous.span.end = end;
+          }
+          self.detach(current);
+          changed = true;
+        }
+      }
+    }
+
+    if changed {
+      self.generation += 1;
+    }
+  }
+
+  // appends without the checks or the generation bump, for the parsers
+  // building a fresh tree
+  pub(crate) fn append(&mut self, parent: NodeId, child: NodeId) {
+    self.insert(parent, child, None);
+  }
+
+  // like insert_before, unchecked like append
+  pub(crate) fn insert(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+    self.detach(child);
+    self.link(parent, child, reference);
+  }
+
+  pub(crate) fn detach(&mut self, id: NodeId) {
+    let Some(parent) = self.nodes[id.0].parent.take() else {
+      return;
+    };
+    let previous = self.nodes[id.0].previous_sibling.take();
+    let next = self.nodes[id.0].next_sibling.take();
+
+    match previous {
+      Some(previous) => self.nodes[previous.0].next_sibling = next,
+      None => self.nodes[parent.0].first_child = next,
+    }
+    match next {
+      Some(next) => self.nodes[next.0].previous_sibling = previous,
+      None => self.nodes[parent.0].last_child = previous,
+    }
+  }
+
+  // `child` must be detached; `reference` must be a child of `parent`
+  fn link(&mut self, parent: NodeId, child: NodeId, reference: Option<NodeId>) {
+    let previous = match reference {
+      Some(reference) => self.nodes[reference.0].previous_sibling,
+      None => self.nodes[parent.0].last_child,
+    };
+
+    let slot = &mut self.nodes[child.0];
+    slot.parent = Some(parent);
+    slot.previous_sibling = previous;
+    slot.next_sibling = reference;
+
+    match previous {
+      Some(previous) => self.nodes[previous.0].next_sibling = Some(child),
+      None => self.nodes[parent.0].first_child = Some(child),
+    }
+    match reference {
+      Some(reference) => self.nodes[reference.0].previous_sibling = Some(child),
+      None => self.nodes[parent.0].last_child = Some(child),
+    }
+  }
+
+  fn check_insert(&self, parent: NodeId, child: NodeId) -> Result<(), DOMError> {
+    let parent_ok = matches!(&self[parent], Node::Document(_) | Node::Element(_));
+    let child_ok = !matches!(&self[child], Node::Document(_));
+    if !parent_ok || !child_ok {
+      return Err(DOMError::HierarchyRequest);
+    }
+
+    // the parent can't be the child itself or one of its descendants
+    let mut ancestor = Some(parent);
+    while let Some(node) = ancestor {
+      if node == child {
+        return Err(DOMError::HierarchyRequest);
+      }
+      ancestor = self.parent(node);
+    }
+    Ok(())
+  }
+}
+
+pub struct Children<'a> {
+  dom: &'a Dom,
+  next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_> {
+  type Item = NodeId;
+
+  fn next(&mut self) -> Option<NodeId> {
+    let current = self.next?;
+    self.next = self.dom.next_sibling(current);
+    Some(current)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::utils::{HTML_NAMESPACE, Span};
+
+  fn element(dom: &mut Dom, tag: &str) -> NodeId {
+    dom.create(Node::Element(Element {
+      tag: tag.to_string(),
+      namespace: HTML_NAMESPACE.to_string(),
+      attributes: vec![],
+      span: Span::default(),
+    }))
+  }
+
+  fn text(dom: &mut Dom, text: &str) -> NodeId {
+    dom.create(Node::Text(Text {
+      text: text.to_string(),
+      span: Span::default(),
+    }))
+  }
+
+  // the children of `parent` read forwards, after checking that every link
+  // between them agrees with the others
+  fn children(dom: &Dom, parent: NodeId) -> Vec<NodeId> {
+    let forward: Vec<NodeId> = dom.children(parent).collect();
+    let mut backward = vec![];
+    let mut child = dom.last_child(parent);
+    while let Some(current) = child {
+      backward.push(current);
+      child = dom.previous_sibling(current);
+    }
+    backward.reverse();
+    assert_eq!(forward, backward);
+
+    for (i, &child) in forward.iter().enumerate() {
+      assert_eq!(dom.parent(child), Some(parent));
+      assert_eq!(
+        dom.previous_sibling(child),
+        i.checked_sub(1).map(|i| forward[i])
+      );
+      assert_eq!(dom.next_sibling(child), forward.get(i + 1).copied());
+    }
+    forward
+  }
+
+  fn detached(dom: &Dom, node: NodeId) -> bool {
+    dom.parent(node).is_none()
+      && dom.previous_sibling(node).is_none()
+      && dom.next_sibling(node).is_none()
+  }
+
+  fn texts(dom: &Dom, parent: NodeId) -> Vec<String> {
+    children(dom, parent)
+      .into_iter()
+      .map(|child| match &dom[child] {
+        Node::Text(text) => text.text.clone(),
+        Node::Element(element) => format!("<{}>", element.tag),
+        _ => String::new(),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn insertions_keep_the_links_consistent() {
+    let mut dom = Dom::new();
+    let body = element(&mut dom, "body");
+    let [a, b, c] = ["a", "b", "c"].map(|tag| element(&mut dom, tag));
+    dom.append_child(dom.document(), body).unwrap();
+
+    let generation = dom.generation();
+    dom.append_child(body, b).unwrap();
+    assert_eq!(dom.generation(), generation + 1);
+    dom.insert_before(body, a, Some(b)).unwrap();
+    dom.insert_before(body, c, None).unwrap();
+    assert_eq!(children(&dom, body), [a, b, c]);
+    assert_eq!(dom.generation(), generation + 3);
+
+    // moving a node takes it out of where it was
+    dom.append_child(body, a).unwrap();
+    assert_eq!(children(&dom, body), [b, c, a]);
+    dom.insert_before(body, a, Some(b)).unwrap();
+    assert_eq!(children(&dom, body), [a, b, c]);
+    dom.append_child(c, b).unwrap();
+    assert_eq!(children(&dom, body), [a, c]);
+    assert_eq!(children(&dom, c), [b]);
+
+    // inserting a node before itself changes nothing
+    let generation = dom.generation();
+    dom.insert_before(body, a, Some(a)).unwrap();
+    assert_eq!(children(&dom, body), [a, c]);
+    assert_eq!(dom.generation(), generation);
+  }
+
+  #[test]
+  fn bad_insertions_are_refused() {
+    let mut dom = Dom::new();
+    let [outer, inner, other] = ["div", "p", "span"].map(|tag| element(&mut dom, tag));
+    let leaf = text(&mut dom, "x");
+    dom.append_child(dom.document(), outer).unwrap();
+    dom.append_child(outer, inner).unwrap();
+    let generation = dom.generation();
+
+    let document = dom.document();
+    let cases = [
+      // into itself or one of its descendants
+      (dom.append_child(outer, outer), DOMError::HierarchyRequest),
+      (dom.append_child(inner, outer), DOMError::HierarchyRequest),
+      // text has no children and the document has no parent
+      (dom.append_child(leaf, other), DOMError::HierarchyRequest),
+      (
+        dom.append_child(outer, document),
+        DOMError::HierarchyRequest,
+      ),
+      // the reference node isn't a child of the parent
+      (
+        dom.insert_before(outer, other, Some(outer)),
+        DOMError::NotFound,
+      ),
+      (
+        dom.insert_before(inner, other, Some(inner)),
+        DOMError::NotFound,
+      ),
+      (dom.replace_node(other, leaf), DOMError::NotFound),
+    ];
+    for (i, (result, error)) in cases.into_iter().enumerate() {
+      assert_eq!(result, Err(error), "case {i}");
+    }
+
+    assert_eq!(children(&dom, outer), [inner]);
+    assert!(detached(&dom, other));
+    assert_eq!(dom.generation(), generation);
+  }
+
+  #[test]
+  fn remove_and_replace() {
+    let mut dom = Dom::new();
+    let body = element(&mut dom, "body");
+    let [a, b, c, d] = ["a", "b", "c", "d"].map(|tag| element(&mut dom, tag));
+    for child in [a, b, c] {
+      dom.append_child(body, child).unwrap();
+    }
+
+    let generation = dom.generation();
+    dom.remove_node(b);
+    assert_eq!(children(&dom, body), [a, c]);
+    assert!(detached(&dom, b));
+    assert_eq!(dom.generation(), generation + 1);
+
+    // a node without a parent is left alone
+    dom.remove_node(b);
+    assert_eq!(dom.generation(), generation + 1);
+
+    dom.replace_node(a, d).unwrap();
+    assert_eq!(children(&dom, body), [d, c]);
+    assert!(detached(&dom, a));
+    dom.replace_node(c, a).unwrap();
+    assert_eq!(children(&dom, body), [d, a]);
+    assert!(dom.generation() > generation + 1);
+
+    // the last child of a parent and the first of another
+    dom.append_child(d, c).unwrap();
+    dom.remove_node(a);
+    dom.remove_node(c);
+    assert_eq!(children(&dom, body), [d]);
+    assert_eq!(children(&dom, d), []);
+    assert_eq!(dom.first_child(d), None);
+    assert_eq!(dom.last_child(d), None);
+  }
+
+  #[test]
+  fn clone_node_copies_the_subtree_when_deep() {
+    let mut dom = Dom::new();
+    let div = element(&mut dom, "div");
+    let p = element(&mut dom, "p");
+    let words = text(&mut dom, "words");
+    dom.append_child(dom.document(), div).unwrap();
+    dom.append_child(div, p).unwrap();
+    dom.append_child(p, words).unwrap();
+
+    let generation = dom.generation();
+    let shallow = dom.clone_node(div, false);
+    assert!(detached(&dom, shallow));
+    assert!(!dom.has_children(shallow));
+
+    let deep = dom.clone_node(div, true);
+    assert!(detached(&dom, deep));
+    let [copy] = children(&dom, deep)[..] else {
+      panic!("expected one child");
+    };
+    assert_ne!(copy, p);
+    assert_eq!(texts(&dom, copy), ["words"]);
+    assert_eq!(dom.text_content(deep), "words");
+
+    // the original is untouched, and nothing in the tree changed
+    assert_eq!(children(&dom, p), [words]);
+    assert_eq!(dom.generation(), generation);
+
+    // deeper than the stack would allow with recursion
+    let mut node = div;
+    for _ in 0..100_000 {
+      let child = element(&mut dom, "div");
+      dom.append(node, child);
+      node = child;
+    }
+    let copy = dom.clone_node(div, true);
+    assert_eq!(dom.descendants(copy).count(), 100_000 + 2);
+  }
+
+  #[test]
+  fn normalize_merges_and_drops_text() {
+    let mut dom = Dom::new();
+    let body = element(&mut dom, "body");
+    let p = element(&mut dom, "p");
+    dom.append_child(dom.document(), body).unwrap();
+    for part in ["a", "", "b"] {
+      let node = text(&mut dom, part);
+      dom.append_child(body, node).unwrap();
+    }
+    dom.append_child(body, p).unwrap();
+    for part in ["", "c", "d", ""] {
+      let node = text(&mut dom, part);
+      dom.append_child(p, node).unwrap();
+    }
+    let empty = element(&mut dom, "i");
+    let nothing = text(&mut dom, "");
+    dom.append_child(p, empty).unwrap();
+    dom.append_child(empty, nothing).unwrap();
+
+    let generation = dom.generation();
+    dom.normalize(dom.document());
+    assert_eq!(texts(&dom, body), ["ab", "<p>"]);
+    assert_eq!(texts(&dom, p), ["cd", "<i>"]);
+    assert_eq!(children(&dom, empty), []);
+    assert_eq!(dom.generation(), generation + 1);
+
+    // a normalized tree is left as it is
+    dom.normalize(dom.document());
+    assert_eq!(dom.generation(), generation + 1);
+
+    // deeper than the stack would allow with recursion
+    let mut node = body;
+    for _ in 0..100_000 {
+      let child = element(&mut dom, "div");
+      dom.append(node, child);
+      node = child;
+    }
+    for part in ["x", "y"] {
+      let part = text(&mut dom, part);
+      dom.append(node, part);
+    }
+    dom.normalize(dom.document());
+    assert_eq!(texts(&dom, node), ["xy"]);
+  }
+}
@@ -0,0 +1,203 @@
+use std::env;
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::kv::{Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+
+const FILTER_VAR: &str = "AGR_LOG";
+
+// where formatted lines go, stderr until something else is plugged in
+pub enum Sink {
+  Stderr,
+  Writer(Box<dyn Write + Send>),
+  // kept in memory, for tests to look at
+  Memory(Vec<String>),
+}
+
+lazy_static! {
+  static ref SINK: Mutex<Sink> = Mutex::new(Sink::Stderr);
+}
+
+// filters use the same syntax as env_logger: a default level followed by
+// per-module overrides, e.g. `AGR_LOG=warn,project_agr::net=debug`
+struct Logger {
+  default: LevelFilter,
+  modules: Vec<(String, LevelFilter)>,
+}
+
+impl Logger {
+  fn parse(spec: &str) -> Self {
+    let mut logger = Logger {
+      default: LevelFilter::Warn,
+      modules: Vec::new(),
+    };
+
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+      match directive.split_once('=') {
+        Some((module, level)) => {
+          if let Ok(level) = level.trim().parse() {
+            logger.modules.push((module.trim().to_string(), level));
+          }
+        }
+        None => match directive.parse() {
+          Ok(level) => logger.default = level,
+          // a bare module name turns on everything for that module
+          Err(_) => logger
+            .modules
+            .push((directive.to_string(), LevelFilter::Trace)),
+        },
+      }
+    }
+
+    // the most specific module wins, so check longer prefixes first
+    logger
+      .modules
+      .sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+    logger
+  }
+
+  fn level_for(&self, target: &str) -> LevelFilter {
+    self
+      .modules
+      .iter()
+      .find(|(module, _)| target == module || target.starts_with(&format!("{module}::")))
+      .map(|(_, level)| *level)
+      .unwrap_or(self.default)
+  }
+
+  fn max_level(&self) -> LevelFilter {
+    self
+      .modules
+      .iter()
+      .map(|(_, level)| *level)
+      .fold(self.default, Ord::max)
+  }
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level_for(metadata.target())
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    let mut line = format!(
+      "[{:<5} {}] {}",
+      record.level(),
+      record.target(),
+      record.args()
+    );
+
+    let mut fields = Fields(&mut line);
+    let _ = record.key_values().visit(&mut fields);
+
+    match &mut *SINK.lock().unwrap() {
+      Sink::Stderr => {
+        let _ = writeln!(io::stderr(), "{line}");
+      }
+      Sink::Writer(writer) => {
+        let _ = writeln!(writer, "{line}");
+      }
+      Sink::Memory(lines) => lines.push(line),
+    }
+  }
+
+  fn flush(&self) {
+    match &mut *SINK.lock().unwrap() {
+      Sink::Stderr => {
+        let _ = io::stderr().flush();
+      }
+      Sink::Writer(writer) => {
+        let _ = writer.flush();
+      }
+      Sink::Memory(_) => (),
+    }
+  }
+}
+
+struct Fields<'a>(&'a mut String);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+  fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+    self.0.push_str(&format!(" {key}={value}"));
+    Ok(())
+  }
+}
+
+pub fn init() {
+  install(&env::var(FILTER_VAR).unwrap_or_default());
+}
+
+// only the first logger installed in a process takes effect
+fn install(spec: &str) {
+  let logger = Logger::parse(spec);
+  let max_level = logger.max_level();
+
+  if log::set_boxed_logger(Box::new(logger)).is_ok() {
+    log::set_max_level(max_level);
+  }
+}
+
+// sends log lines somewhere else from now on, returning the old sink
+pub fn set_sink(sink: Sink) -> Sink {
+  std::mem::replace(&mut *SINK.lock().unwrap(), sink)
+}
+
+// starts keeping log lines in memory instead of writing them out; the logger
+// is installed with `spec` as its filter if nothing was installed yet
+pub fn capture(spec: &str) {
+  install(spec);
+
+  if !matches!(*SINK.lock().unwrap(), Sink::Memory(_)) {
+    set_sink(Sink::Memory(Vec::new()));
+  }
+}
+
+// the lines captured since the last call
+pub fn captured() -> Vec<String> {
+  match &mut *SINK.lock().unwrap() {
+    Sink::Memory(lines) => std::mem::take(lines),
+    _ => Vec::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn most_specific_module_wins() {
+    let logger = Logger::parse("warn,project_agr::net=debug,project_agr::net::auth=error");
+
+    assert_eq!(logger.level_for("project_agr::app"), LevelFilter::Warn);
+    assert_eq!(
+      logger.level_for("project_agr::net::hsts"),
+      LevelFilter::Debug
+    );
+    assert_eq!(
+      logger.level_for("project_agr::net::auth"),
+      LevelFilter::Error
+    );
+    assert_eq!(logger.level_for("project_agr::network"), LevelFilter::Warn);
+    assert_eq!(logger.max_level(), LevelFilter::Debug);
+  }
+
+  #[test]
+  fn captures_lines_with_their_fields() {
+    capture("project_agr::utils::logging=info");
+
+    log::info!(url = "http://example.com/", status = 200; "loaded");
+    log::debug!("not enabled");
+
+    let lines = captured();
+    assert!(lines.contains(&String::from(
+      "[INFO  project_agr::utils::logging::tests] loaded url=http://example.com/ status=200"
+    )));
+    assert!(!lines.iter().any(|line| line.contains("not enabled")));
+  }
+}
@@ -1,3 +1,12 @@
+mod dom;
+pub mod logging;
 mod structs;
+mod traverse;
 
-pub use structs::{Element, Node, Text};
+pub use dom::{Children, DOMError, Dom};
+pub use structs::{
+  Comment, Doctype, Document, DocumentMode, Element, HTML_NAMESPACE, MATHML_NAMESPACE, Node,
+  NodeId, Position, ProcessingInstruction, SVG_NAMESPACE, Span, Text, XML_NAMESPACE,
+  XMLNS_NAMESPACE,
+};
+pub use traverse::{Ancestors, Descendants, Edge, PostOrder, Siblings, Traverse};
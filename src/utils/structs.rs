@@ -1,44 +1,111 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::{Rc, Weak};
+pub const HTML_NAMESPACE: &str = "http://www.w3.org/1999/xhtml";
+pub const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+pub const MATHML_NAMESPACE: &str = "http://www.w3.org/1998/Math/MathML";
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+// a node in a Dom, the index of its slot in the arena
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub(crate) usize);
 
 pub enum Node {
+  Document(Document),
+  Doctype(Doctype),
   Text(Text),
+  Comment(Comment),
+  ProcessingInstruction(ProcessingInstruction),
   Element(Element),
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentMode {
+  #[default]
+  NoQuirks,
+  LimitedQuirks,
+  Quirks,
+}
+
+// 1-based, columns count characters
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+  pub line: usize,
+  pub column: usize,
+}
+
+// where a node came from in the source, end is exclusive
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+  pub start: Position,
+  pub end: Position,
+}
+
+pub struct Document {
+  pub mode: DocumentMode,
+}
+
+pub struct Doctype {
+  pub name: String,
+  pub public_id: String,
+  pub system_id: String,
+  pub span: Span,
+}
+
 pub struct Text {
   pub text: String,
-  pub children: Vec<Rc<RefCell<Node>>>,
-  pub parent: Option<Weak<RefCell<Node>>>,
+  pub span: Span,
+}
+
+pub struct Comment {
+  pub text: String,
+  pub span: Span,
+}
+
+// only XML documents have them, in HTML `<?x?>` is a bogus comment
+pub struct ProcessingInstruction {
+  pub target: String,
+  pub data: String,
+  pub span: Span,
 }
 
 pub struct Element {
   pub tag: String,
-  pub children: Vec<Rc<RefCell<Node>>>,
-  pub attributes: HashMap<String, String>,
-  pub parent: Option<Weak<RefCell<Node>>>,
+  // empty for elements in no namespace
+  pub namespace: String,
+  // in the order they appear in the source
+  pub attributes: Vec<(String, String)>,
+  pub span: Span,
 }
 
-impl Node {
-  pub fn tag(&self) -> Option<&str> {
-    match self {
-      Node::Element(e) => Some(&e.tag),
-      Node::Text(_) => None,
-    }
+impl Element {
+  pub fn attribute(&self, name: &str) -> Option<&str> {
+    self
+      .attributes
+      .iter()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value.as_str())
   }
+}
 
-  pub fn children(&self) -> &Vec<Rc<RefCell<Node>>> {
+impl Node {
+  pub fn span(&self) -> Option<Span> {
     match self {
-      Node::Element(e) => &e.children,
-      Node::Text(t) => &t.children,
+      Node::Document(_) => None,
+      Node::Doctype(d) => Some(d.span),
+      Node::Element(e) => Some(e.span),
+      Node::Text(t) => Some(t.span),
+      Node::Comment(c) => Some(c.span),
+      Node::ProcessingInstruction(p) => Some(p.span),
     }
   }
 
-  pub fn children_mut(&mut self) -> &mut Vec<Rc<RefCell<Node>>> {
+  pub fn tag(&self) -> Option<&str> {
     match self {
-      Node::Element(e) => &mut e.children,
-      Node::Text(t) => &mut t.children,
+      Node::Element(e) => Some(&e.tag),
+      Node::Document(_)
+      | Node::Doctype(_)
+      | Node::Text(_)
+      | Node::Comment(_)
+      | Node::ProcessingInstruction(_) => None,
     }
   }
 }
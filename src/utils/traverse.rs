@@ -0,0 +1,341 @@
+use crate::utils::{Dom, Node, NodeId};
+
+// iterators over a Dom. They follow the sibling links, so none of them needs
+// a stack or recursion, and a subtree is always walked in document order
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+  // entering a node, before its children
+  Open(NodeId),
+  // leaving a node, after its children
+  Close(NodeId),
+}
+
+impl Dom {
+  // an Open and a Close for every node of the subtree, the root included
+  pub fn traverse(&self, root: NodeId) -> Traverse<'_> {
+    Traverse {
+      dom: self,
+      root,
+      current: None,
+      next: Some(Edge::Open(root)),
+    }
+  }
+
+  // the nodes below `root` in pre-order, not including it
+  pub fn descendants(&self, root: NodeId) -> Descendants<'_> {
+    Descendants(self.traverse(root))
+  }
+
+  // the nodes below `root` in post-order, children before their parent, not
+  // including it
+  pub fn post_order(&self, root: NodeId) -> PostOrder<'_> {
+    PostOrder(self.traverse(root))
+  }
+
+  // the parent, its parent and so on up to the document
+  pub fn ancestors(&self, id: NodeId) -> Ancestors<'_> {
+    Ancestors {
+      dom: self,
+      next: self.parent(id),
+    }
+  }
+
+  // the siblings before the node, nearest first
+  pub fn previous_siblings(&self, id: NodeId) -> Siblings<'_> {
+    Siblings {
+      dom: self,
+      next: self.previous_sibling(id),
+      forward: false,
+    }
+  }
+
+  // the siblings after the node, nearest first
+  pub fn next_siblings(&self, id: NodeId) -> Siblings<'_> {
+    Siblings {
+      dom: self,
+      next: self.next_sibling(id),
+      forward: true,
+    }
+  }
+
+  // the element descendants of `root` in pre-order
+  pub fn elements(&self, root: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    self
+      .descendants(root)
+      .filter(|&node| matches!(&self[node], Node::Element(_)))
+  }
+
+  // the text node descendants of `root` in pre-order
+  pub fn text_nodes(&self, root: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+    self
+      .descendants(root)
+      .filter(|&node| matches!(&self[node], Node::Text(_)))
+  }
+
+  // all the text of the subtree joined together, like textContent
+  pub fn text_content(&self, id: NodeId) -> String {
+    if let Node::Text(text) = &self[id] {
+      return text.text.clone();
+    }
+
+    self
+      .text_nodes(id)
+      .filter_map(|node| match &self[node] {
+        Node::Text(text) => Some(text.text.as_str()),
+        _ => None,
+      })
+      .collect()
+  }
+}
+
+pub struct Traverse<'a> {
+  dom: &'a Dom,
+  root: NodeId,
+  current: Option<Edge>,
+  next: Option<Edge>,
+}
+
+impl Traverse<'_> {
+  // called right after an Open, goes straight to its Close without visiting
+  // the children
+  pub fn skip_children(&mut self) {
+    if let Some(Edge::Open(node)) = self.current {
+      self.next = Some(Edge::Close(node));
+    }
+  }
+}
+
+impl Iterator for Traverse<'_> {
+  type Item = Edge;
+
+  fn next(&mut self) -> Option<Edge> {
+    let edge = self.next?;
+    self.next = match edge {
+      Edge::Open(node) => Some(match self.dom.first_child(node) {
+        Some(child) => Edge::Open(child),
+        None => Edge::Close(node),
+      }),
+      Edge::Close(node) if node == self.root => None,
+      Edge::Close(node) => match self.dom.next_sibling(node) {
+        Some(sibling) => Some(Edge::Open(sibling)),
+        None => self.dom.parent(node).map(Edge::Close),
+      },
+    };
+    self.current = Some(edge);
+    Some(edge)
+  }
+}
+
+pub struct Descendants<'a>(Traverse<'a>);
+
+impl Iterator for Descendants<'_> {
+  type Item = NodeId;
+
+  fn next(&mut self) -> Option<NodeId> {
+    loop {
+      match self.0.next()? {
+        Edge::Open(node) if node != self.0.root => return Some(node),
+        _ => (),
+      }
+    }
+  }
+}
+
+pub struct PostOrder<'a>(Traverse<'a>);
+
+impl Iterator for PostOrder<'_> {
+  type Item = NodeId;
+
+  fn next(&mut self) -> Option<NodeId> {
+    loop {
+      match self.0.next()? {
+        Edge::Close(node) if node != self.0.root => return Some(node),
+        _ => (),
+      }
+    }
+  }
+}
+
+pub struct Ancestors<'a> {
+  dom: &'a Dom,
+  next: Option<NodeId>,
+}
+
+impl Iterator for Ancestors<'_> {
+  type Item = NodeId;
+
+  fn next(&mut self) -> Option<NodeId> {
+    let current = self.next?;
+    self.next = self.dom.parent(current);
+    Some(current)
+  }
+}
+
+pub struct Siblings<'a> {
+  dom: &'a Dom,
+  next: Option<NodeId>,
+  forward: bool,
+}
+
+impl Iterator for Siblings<'_> {
+  type Item = NodeId;
+
+  fn next(&mut self) -> Option<NodeId> {
+    let current = self.next?;
+    self.next = if self.forward {
+      self.dom.next_sibling(current)
+    } else {
+      self.dom.previous_sibling(current)
+    };
+    Some(current)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::rendering::{HTMLParser, get_element_by_id};
+
+  // <div id=r><p>a<b>b</b></p><ul><li>1</li><li>2</li></ul>c</div>
+  fn page() -> (Dom, NodeId) {
+    let dom =
+      HTMLParser::new("<div id=r><p>a<b>b</b></p><ul><li>1</li><li>2</li></ul>c</div>".to_string())
+        .parse();
+    let root = get_element_by_id(&dom, dom.document(), "r").unwrap();
+    (dom, root)
+  }
+
+  // a tag name, or the text of a text node
+  fn name(dom: &Dom, node: NodeId) -> String {
+    match &dom[node] {
+      Node::Element(element) => element.tag.clone(),
+      Node::Text(text) => format!("'{}'", text.text),
+      _ => String::from("?"),
+    }
+  }
+
+  fn names(dom: &Dom, nodes: impl Iterator<Item = NodeId>) -> Vec<String> {
+    nodes.map(|node| name(dom, node)).collect()
+  }
+
+  fn edges(dom: &Dom, traverse: impl Iterator<Item = Edge>) -> Vec<String> {
+    traverse
+      .map(|edge| match edge {
+        Edge::Open(node) => name(dom, node),
+        Edge::Close(node) => format!("/{}", name(dom, node)),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn traverse_opens_and_closes_every_node() {
+    let (dom, root) = page();
+    assert_eq!(
+      edges(&dom, dom.traverse(root)),
+      [
+        "div", "p", "'a'", "/'a'", "b", "'b'", "/'b'", "/b", "/p", "ul", "li", "'1'", "/'1'",
+        "/li", "li", "'2'", "/'2'", "/li", "/ul", "'c'", "/'c'", "/div"
+      ]
+    );
+
+    // a subtree ends at its root even when the root has siblings
+    let p = dom.first_child(root).unwrap();
+    assert_eq!(
+      edges(&dom, dom.traverse(p)),
+      ["p", "'a'", "/'a'", "b", "'b'", "/'b'", "/b", "/p"]
+    );
+
+    let leaf = dom.last_child(root).unwrap();
+    assert_eq!(edges(&dom, dom.traverse(leaf)), ["'c'", "/'c'"]);
+  }
+
+  #[test]
+  fn skip_children_goes_to_the_close() {
+    let (dom, root) = page();
+
+    // skipping the list partway through the walk resumes after it
+    let mut traverse = dom.traverse(root);
+    let mut seen = vec![];
+    while let Some(edge) = traverse.next() {
+      if let Edge::Open(node) = edge
+        && name(&dom, node) == "ul"
+      {
+        traverse.skip_children();
+      }
+      seen.push(edge);
+    }
+    assert_eq!(
+      edges(&dom, seen.into_iter()),
+      [
+        "div", "p", "'a'", "/'a'", "b", "'b'", "/'b'", "/b", "/p", "ul", "/ul", "'c'", "/'c'",
+        "/div"
+      ]
+    );
+
+    // skipping at the root ends the walk
+    let mut traverse = dom.traverse(root);
+    assert_eq!(traverse.next(), Some(Edge::Open(root)));
+    traverse.skip_children();
+    assert_eq!(traverse.next(), Some(Edge::Close(root)));
+    assert_eq!(traverse.next(), None);
+
+    // after a Close there is nothing to skip
+    let mut traverse = dom.traverse(root);
+    let mut count = 0;
+    while let Some(edge) = traverse.next() {
+      if matches!(edge, Edge::Close(node) if name(&dom, node) == "p") {
+        traverse.skip_children();
+      }
+      count += 1;
+    }
+    assert_eq!(count, 22);
+  }
+
+  #[test]
+  fn orders() {
+    let (dom, root) = page();
+    assert_eq!(
+      names(&dom, dom.descendants(root)),
+      [
+        "p", "'a'", "b", "'b'", "ul", "li", "'1'", "li", "'2'", "'c'"
+      ]
+    );
+    assert_eq!(
+      names(&dom, dom.post_order(root)),
+      [
+        "'a'", "'b'", "b", "p", "'1'", "li", "'2'", "li", "ul", "'c'"
+      ]
+    );
+    assert_eq!(
+      names(&dom, dom.elements(root)),
+      ["p", "b", "ul", "li", "li"]
+    );
+    assert_eq!(
+      names(&dom, dom.text_nodes(root)),
+      ["'a'", "'b'", "'1'", "'2'", "'c'"]
+    );
+    assert_eq!(dom.text_content(root), "ab12c");
+    assert_eq!(dom.text_content(dom.first_child(root).unwrap()), "ab");
+    assert_eq!(dom.text_content(dom.last_child(root).unwrap()), "c");
+  }
+
+  #[test]
+  fn ancestors_and_siblings() {
+    let (dom, root) = page();
+    let b = dom.elements(root).nth(1).unwrap();
+    assert_eq!(
+      names(&dom, dom.ancestors(b)),
+      ["p", "div", "body", "html", "?"]
+    );
+    assert_eq!(dom.ancestors(b).last(), Some(dom.document()));
+    assert_eq!(dom.ancestors(dom.document()).count(), 0);
+
+    let ul = dom.elements(root).nth(2).unwrap();
+    assert_eq!(names(&dom, dom.previous_siblings(ul)), ["p"]);
+    assert_eq!(names(&dom, dom.next_siblings(ul)), ["'c'"]);
+    let last = dom.last_child(root).unwrap();
+    assert_eq!(names(&dom, dom.previous_siblings(last)), ["ul", "p"]);
+    assert_eq!(dom.next_siblings(last).count(), 0);
+  }
+}